@@ -0,0 +1,30 @@
+//! Transaction status check example
+//! 
+//! Run with: cargo run --example check_status -- <transaction_uuid> <total_amount>
+
+use rustpayment::{check_transaction_status, EsewaEnvironment, Npr};
+
+#[tokio::main]
+async fn main() {
+    let mut args = std::env::args().skip(1);
+    let transaction_uuid = args.next().unwrap_or_else(|| "id-123-abc".to_string());
+    let total_amount: Npr = args
+        .next()
+        .unwrap_or_else(|| "110".to_string())
+        .parse()
+        .expect("total amount must be a valid NPR amount");
+
+    println!("Checking status of {} ({})", transaction_uuid, total_amount);
+
+    match check_transaction_status("EPAYTEST", total_amount, &transaction_uuid, EsewaEnvironment::Sandbox).await {
+        Ok(result) => {
+            println!("Status: {:?}", result.status);
+            if result.status.is_success() {
+                println!("Reference ID: {}", result.ref_id.unwrap_or_default());
+            }
+        }
+        Err(e) => {
+            eprintln!("\n Status check error: {}", e);
+        }
+    }
+}
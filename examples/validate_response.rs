@@ -3,7 +3,7 @@
 //! Run with: cargo run --example validate_response
 
 use base64::{engine::general_purpose, Engine};
-use rustpayment::{generate_signature, validate_esewa_response, EsewaPaymentResponse};
+use rustpayment::{validate_esewa_response, EsewaPaymentResponse, EsewaStatus, Npr};
 
 fn main() {
     let secret_key = "8gBm/:&EnhH.1/q";
@@ -12,22 +12,21 @@ fn main() {
     println!("================================\n");
 
     // Simulate a valid response from eSewa
-    let response = EsewaPaymentResponse {
+    let mut response = EsewaPaymentResponse {
         transaction_code: "000D13A".to_string(),
-        status: "COMPLETE".to_string(),
-        total_amount: "110.0".to_string(),
+        status: EsewaStatus::Complete,
+        total_amount: Npr::from_rupees(110),
         transaction_uuid: "id-1234567890-abcdef".to_string(),
         product_code: "EPAYTEST".to_string(),
         signed_field_names:
             "transaction_code,status,total_amount,transaction_uuid,product_code,signed_field_names"
                 .to_string(),
-        signature: generate_signature(
-            "110.0",
-            "id-1234567890-abcdef",
-            "EPAYTEST",
-            secret_key,
-        ),
+        signature: String::new(),
     };
+    // eSewa signs every field listed in `signed_field_names`
+    response.signature = response
+        .compute_signature(secret_key)
+        .expect("all signed fields are present");
 
     // Encode to base64 (this is what eSewa sends)
     let json = serde_json::to_string(&response).unwrap();
@@ -61,7 +60,7 @@ fn main() {
 
     // Test with invalid signature
     let mut invalid_response = response.clone();
-    invalid_response.signature = "INVALID_SIGNATURE".to_string();
+    invalid_response.signature = "c2lnbmVkIHdpdGggYW5vdGhlciBrZXk=".to_string();
 
     let json = serde_json::to_string(&invalid_response).unwrap();
     let encoded = general_purpose::STANDARD.encode(json.as_bytes());
@@ -0,0 +1,261 @@
+//! eSewa callback extractor and checkout routes for actix-web.
+//!
+//! Enabled with the `actix-web` feature.
+//!
+//! - [`EsewaCallback`] extracts the `data` query parameter of a success
+//!   redirect as an [`EsewaPaymentResponse`] whose signature was checked with
+//!   the [`EsewaClient`] registered as `web::Data<EsewaClient>`. Requests
+//!   with a missing, malformed or wrongly signed payload get a 400.
+//! - [`EsewaCheckout::scope`] serves the [`checkout`](crate::checkout)
+//!   service as `POST /initiate`, `GET /success` and `GET /failure` under a
+//!   path of your choice.
+//!
+//! ```no_run
+//! use actix_web::{get, web, App, HttpResponse, HttpServer};
+//! use rustpayment::actix_web::EsewaCallback;
+//! use rustpayment::EsewaClient;
+//!
+//! #[get("/success")]
+//! async fn success(callback: EsewaCallback) -> HttpResponse {
+//!     // Signature verified; still check the amount against your order
+//!     HttpResponse::Ok().body(format!("paid {}", callback.total_amount))
+//! }
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let client = web::Data::new(EsewaClient::builder("EPAYTEST", "8gBm/:&EnhH.1/q").build()?);
+//! HttpServer::new(move || App::new().app_data(client.clone()).service(success))
+//!     .bind(("127.0.0.1", 8080))?
+//!     .run()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::checkout::{error_status, CheckoutPage};
+use crate::error::PaymentError;
+use crate::esewa::{EsewaClient, EsewaPaymentResponse};
+use ::actix_web::dev::Payload;
+use ::actix_web::http::{header, StatusCode};
+use ::actix_web::{web, FromRequest, HttpRequest, HttpResponse, ResponseError, Scope};
+use serde::Deserialize;
+use std::future::{ready, Ready};
+use std::ops::Deref;
+
+pub use crate::checkout::{CheckoutHooks, EsewaCheckout, EsewaCheckoutBuilder};
+
+/// Signature-checked eSewa callback taken from the `data` query parameter
+///
+/// Only the signature is checked. Compare the amount and transaction UUID
+/// with your order, e.g. with
+/// [`EsewaClient::verify_callback`](crate::EsewaClient::verify_callback), or
+/// use [`EsewaCheckout::scope`], which does so against its transaction store.
+#[derive(Debug, Clone)]
+pub struct EsewaCallback(pub EsewaPaymentResponse);
+
+impl EsewaCallback {
+    /// Unwraps the callback
+    pub fn into_inner(self) -> EsewaPaymentResponse {
+        self.0
+    }
+}
+
+impl Deref for EsewaCallback {
+    type Target = EsewaPaymentResponse;
+
+    fn deref(&self) -> &EsewaPaymentResponse {
+        &self.0
+    }
+}
+
+impl FromRequest for EsewaCallback {
+    type Error = ::actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(extract_callback(req).map_err(Into::into))
+    }
+}
+
+fn extract_callback(req: &HttpRequest) -> Result<EsewaCallback, PaymentError> {
+    let client = req.app_data::<web::Data<EsewaClient>>().ok_or_else(|| {
+        PaymentError::Config(
+            "EsewaCallback needs an EsewaClient registered as web::Data<EsewaClient>".to_string(),
+        )
+    })?;
+    let query = web::Query::<SuccessQuery>::from_query(req.query_string())
+        .map_err(|e| PaymentError::decode("data parameter", e))?;
+
+    let result = client.validate_response(&query.data)?;
+    if !result.signature_valid {
+        return Err(PaymentError::SignatureMismatch);
+    }
+    Ok(EsewaCallback(result.response))
+}
+
+/// Answers with the status and its reason phrase only
+impl ResponseError for PaymentError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::from_u16(error_status(self)).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        HttpResponse::build(status).body(status.canonical_reason().unwrap_or("Error"))
+    }
+}
+
+impl EsewaCheckout {
+    /// Scope serving `POST /initiate`, `GET /success` and `GET /failure` under `path`
+    ///
+    /// `path` must match the path of the base URL the checkout was built
+    /// with, e.g. `"/pay"` for `https://shop.example.com/pay`.
+    pub fn scope(self, path: &str) -> Scope {
+        web::scope(path)
+            .app_data(web::Data::new(self))
+            .route("/initiate", web::post().to(initiate))
+            .route("/success", web::get().to(success))
+            .route("/failure", web::get().to(failure))
+    }
+}
+
+#[derive(Deserialize)]
+struct InitiateForm {
+    order_id: String,
+}
+
+#[derive(Deserialize)]
+struct SuccessQuery {
+    data: String,
+}
+
+#[derive(Deserialize)]
+struct FailureQuery {
+    transaction_uuid: String,
+}
+
+async fn initiate(
+    checkout: web::Data<EsewaCheckout>,
+    form: web::Form<InitiateForm>,
+) -> Result<HttpResponse, PaymentError> {
+    let form = checkout.initiate(&form.order_id).await?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(form.to_html()))
+}
+
+async fn success(
+    checkout: web::Data<EsewaCheckout>,
+    query: web::Query<SuccessQuery>,
+) -> Result<HttpResponse, PaymentError> {
+    let record = checkout.handle_success(&query.data).await?;
+    Ok(page_response(checkout.render(&record)))
+}
+
+async fn failure(
+    checkout: web::Data<EsewaCheckout>,
+    query: web::Query<FailureQuery>,
+) -> Result<HttpResponse, PaymentError> {
+    let record = checkout.handle_failure(&query.transaction_uuid).await?;
+    Ok(page_response(checkout.render(&record)))
+}
+
+fn page_response(page: CheckoutPage) -> HttpResponse {
+    match page {
+        CheckoutPage::Html(html) => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(html),
+        CheckoutPage::Redirect(url) => HttpResponse::SeeOther()
+            .insert_header((header::LOCATION, url))
+            .finish(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::esewa::test_callbacks::{encode, signed_response, SECRET};
+    use crate::esewa::EsewaStatus;
+    use crate::money::Npr;
+    use crate::store::TransactionRecord;
+    use ::actix_web::{test, App};
+    use async_trait::async_trait;
+
+    fn client() -> EsewaClient {
+        EsewaClient::builder("EPAYTEST", SECRET).build().unwrap()
+    }
+
+    fn callback(status: EsewaStatus, signed_status: EsewaStatus) -> String {
+        let mut response = signed_response(|response| response.status = signed_status);
+        response.status = status;
+        encode(&response)
+    }
+
+    fn success_uri(data: &str) -> String {
+        let mut url = reqwest::Url::parse("http://shop.test/success").unwrap();
+        url.query_pairs_mut().append_pair("data", data);
+        format!("{}?{}", url.path(), url.query().unwrap())
+    }
+
+    async fn handler(callback: EsewaCallback) -> HttpResponse {
+        HttpResponse::Ok().body(callback.transaction_code.clone())
+    }
+
+    #[::actix_web::test]
+    async fn test_extractor_accepts_signed_callback_and_rejects_others() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(client()))
+                .route("/success", web::get().to(handler)),
+        )
+        .await;
+
+        let valid = callback(EsewaStatus::Complete, EsewaStatus::Complete);
+        let tampered = callback(EsewaStatus::Complete, EsewaStatus::Pending);
+        for (uri, expected) in [
+            (success_uri(&valid), StatusCode::OK),
+            (success_uri(&tampered), StatusCode::BAD_REQUEST),
+            (success_uri("not-base64"), StatusCode::BAD_REQUEST),
+            ("/success".to_string(), StatusCode::BAD_REQUEST),
+        ] {
+            let response =
+                test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+            assert_eq!(response.status(), expected, "{}", uri);
+        }
+    }
+
+    struct Shop;
+
+    #[async_trait]
+    impl CheckoutHooks for Shop {
+        async fn order_amount(&self, _order_id: &str) -> Result<Npr, PaymentError> {
+            Ok(Npr::from_rupees(250))
+        }
+
+        async fn on_paid(&self, _record: &TransactionRecord) -> Result<(), PaymentError> {
+            Ok(())
+        }
+    }
+
+    #[::actix_web::test]
+    async fn test_scope_serves_checkout_routes() {
+        let checkout = EsewaCheckout::builder(client(), "http://shop.test/pay", Shop)
+            .build()
+            .unwrap();
+        let app = test::init_service(App::new().service(checkout.scope("/pay"))).await;
+
+        let request = test::TestRequest::post()
+            .uri("/pay/initiate")
+            .set_form([("order_id", "order-1")])
+            .to_request();
+        let html = test::call_and_read_body(&app, request).await;
+        let html = String::from_utf8(html.to_vec()).unwrap();
+        assert!(html.contains("rc-epay.esewa.com.np"));
+        assert!(html.contains("value=\"http://shop.test/pay/success\""));
+
+        let request = test::TestRequest::get()
+            .uri("/pay/failure?transaction_uuid=unknown")
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
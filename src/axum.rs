@@ -0,0 +1,124 @@
+//! Ready-made eSewa checkout routes for axum.
+//!
+//! Enabled with the `axum` feature. [`EsewaCheckout::router`] serves the
+//! [`checkout`](crate::checkout) service as three routes, to be nested under
+//! the path given as the checkout's base URL:
+//!
+//! - `POST /initiate` takes a form-encoded `order_id`, asks
+//!   [`CheckoutHooks::order_amount`] what to charge, records the payment in
+//!   the [`TransactionStore`](crate::store::TransactionStore) and returns the
+//!   self-submitting eSewa form.
+//! - `GET /success?data=…` verifies the callback against the stored payment
+//!   with a [`CallbackProcessor`](crate::replay::CallbackProcessor), updates
+//!   its state and calls [`CheckoutHooks::on_paid`] or
+//!   [`CheckoutHooks::on_failed`] once.
+//! - `GET /failure?transaction_uuid=…` asks eSewa for the payment's status,
+//!   since the failure redirect itself carries nothing signed, and records it.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! use async_trait::async_trait;
+//! use rustpayment::axum::{CheckoutHooks, EsewaCheckout};
+//! use rustpayment::{EsewaClient, Npr, PaymentError, TransactionRecord};
+//!
+//! struct Shop;
+//!
+//! #[async_trait]
+//! impl CheckoutHooks for Shop {
+//!     async fn order_amount(&self, order_id: &str) -> Result<Npr, PaymentError> {
+//!         Ok(Npr::from_rupees(100))
+//!     }
+//!
+//!     async fn on_paid(&self, record: &TransactionRecord) -> Result<(), PaymentError> {
+//!         println!("order {} paid", record.order_id);
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let client = EsewaClient::builder("EPAYTEST", "8gBm/:&EnhH.1/q").build()?;
+//! let checkout = EsewaCheckout::builder(client, "https://shop.example.com/pay", Shop).build()?;
+//! let app = axum::Router::new().nest("/pay", checkout.router());
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::checkout::{error_status, CheckoutPage};
+use crate::error::PaymentError;
+use ::axum::extract::{Form, Query, State};
+use ::axum::http::StatusCode;
+use ::axum::response::{Html, IntoResponse, Redirect, Response};
+use ::axum::routing::{get, post};
+use ::axum::Router;
+use serde::Deserialize;
+
+pub use crate::checkout::{CheckoutHooks, EsewaCheckout, EsewaCheckoutBuilder};
+
+impl EsewaCheckout {
+    /// Router serving `POST /initiate`, `GET /success` and `GET /failure`
+    pub fn router(self) -> Router {
+        Router::new()
+            .route("/initiate", post(initiate))
+            .route("/success", get(success))
+            .route("/failure", get(failure))
+            .with_state(self)
+    }
+}
+
+#[derive(Deserialize)]
+struct InitiateForm {
+    order_id: String,
+}
+
+#[derive(Deserialize)]
+struct SuccessQuery {
+    data: String,
+}
+
+#[derive(Deserialize)]
+struct FailureQuery {
+    transaction_uuid: String,
+}
+
+async fn initiate(
+    State(checkout): State<EsewaCheckout>,
+    Form(form): Form<InitiateForm>,
+) -> Response {
+    match checkout.initiate(&form.order_id).await {
+        Ok(form) => Html(form.to_html()).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+async fn success(
+    State(checkout): State<EsewaCheckout>,
+    Query(query): Query<SuccessQuery>,
+) -> Response {
+    match checkout.handle_success(&query.data).await {
+        Ok(record) => page_response(checkout.render(&record)),
+        Err(e) => error_response(e),
+    }
+}
+
+async fn failure(
+    State(checkout): State<EsewaCheckout>,
+    Query(query): Query<FailureQuery>,
+) -> Response {
+    match checkout.handle_failure(&query.transaction_uuid).await {
+        Ok(record) => page_response(checkout.render(&record)),
+        Err(e) => error_response(e),
+    }
+}
+
+fn page_response(page: CheckoutPage) -> Response {
+    match page {
+        CheckoutPage::Html(html) => Html(html).into_response(),
+        CheckoutPage::Redirect(url) => Redirect::to(&url).into_response(),
+    }
+}
+
+fn error_response(error: PaymentError) -> Response {
+    let status =
+        StatusCode::from_u16(error_status(&error)).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let reason = status.canonical_reason().unwrap_or("Error");
+    (status, reason).into_response()
+}
@@ -0,0 +1,231 @@
+//! `rustpayment` command-line tool for debugging eSewa integrations.
+//!
+//! Built with the `cli` feature:
+//!
+//! ```text
+//! cargo install rustpayment --features cli
+//! ESEWA_SECRET_KEY=... rustpayment decode eyJ0cmFuc2FjdGlvbl9jb2RlIjoi...
+//! ```
+//!
+//! The secret key is read from `ESEWA_SECRET_KEY` or `--secret-file`, never
+//! from the command line, so it does not end up in shell history or in the
+//! process list.
+
+use clap::{Args, Parser, Subcommand};
+use rustpayment::esewa::{build_signature_message, ENV_PRODUCT_CODE, ENV_SECRET_KEY};
+use rustpayment::{
+    build_checkout_form, check_transaction_status, generate_signature_for_fields,
+    generate_transaction_uuid, validate_esewa_response, EsewaEnvironment, EsewaPaymentRequest,
+    MerchantSecret, Npr, PaymentError,
+};
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+#[derive(Parser)]
+#[command(
+    name = "rustpayment",
+    version,
+    about = "Sign, decode and verify eSewa payments",
+    after_help = "The secret key is read from ESEWA_SECRET_KEY or --secret-file.\n\
+                  Exits with 1 on errors and on callbacks whose signature does not verify."
+)]
+struct Cli {
+    /// Read the secret key from this file instead of ESEWA_SECRET_KEY
+    #[arg(long, global = true, value_name = "PATH")]
+    secret_file: Option<PathBuf>,
+
+    /// sandbox, production or a base URL [default: from ESEWA_ENVIRONMENT / ESEWA_BASE_URL, else sandbox]
+    #[arg(long, global = true, value_name = "ENV")]
+    environment: Option<EsewaEnvironment>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Sign fields in the given order, e.g. total_amount=110 transaction_uuid=id-1 product_code=EPAYTEST
+    Sign {
+        #[arg(required = true, value_name = "NAME=VALUE")]
+        fields: Vec<String>,
+    },
+    /// Decode a callback's `data` value and check its signature ("-" reads stdin)
+    Decode { data: String },
+    /// Ask eSewa for the status of a transaction
+    Status {
+        transaction_uuid: String,
+        total_amount: Npr,
+        /// Merchant product code [default: ESEWA_PRODUCT_CODE]
+        #[arg(long)]
+        product_code: Option<String>,
+    },
+    /// Print a self-submitting checkout form for a payment
+    Form(FormArgs),
+    /// Generate a transaction UUID
+    Uuid,
+}
+
+#[derive(Args)]
+struct FormArgs {
+    /// Amount before tax and charges
+    amount: Npr,
+    #[arg(long, default_value_t = Npr::ZERO)]
+    tax_amount: Npr,
+    #[arg(long, default_value_t = Npr::ZERO)]
+    service_charge: Npr,
+    #[arg(long, default_value_t = Npr::ZERO)]
+    delivery_charge: Npr,
+    #[arg(long)]
+    success_url: String,
+    #[arg(long)]
+    failure_url: String,
+    /// [default: a new UUID]
+    #[arg(long)]
+    transaction_uuid: Option<String>,
+    /// Merchant product code [default: ESEWA_PRODUCT_CODE]
+    #[arg(long)]
+    product_code: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli).await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<ExitCode, PaymentError> {
+    match &cli.command {
+        Command::Sign { fields } => {
+            let fields = fields
+                .iter()
+                .map(|field| parse_field(field))
+                .collect::<Result<Vec<_>, _>>()?;
+            let names = fields
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>()
+                .join(",");
+            let value_of = |name: &str| {
+                fields
+                    .iter()
+                    .find(|(field, _)| field == name)
+                    .map(|(_, value)| value.clone())
+            };
+
+            let message = build_signature_message(&names, value_of)?;
+            let signature = generate_signature_for_fields(&names, value_of, &secret_key(&cli)?)?;
+            println!("signed_field_names={}", names);
+            println!("message={}", message);
+            println!("signature={}", signature);
+        }
+        Command::Decode { data } => {
+            let data = if data == "-" {
+                let mut input = String::new();
+                std::io::stdin()
+                    .read_to_string(&mut input)
+                    .map_err(|e| PaymentError::decode("stdin", e))?;
+                input.trim().to_string()
+            } else {
+                data.clone()
+            };
+
+            let result = validate_esewa_response(&data, &secret_key(&cli)?)?;
+            let response = &result.response;
+            let json = serde_json::to_string_pretty(response)
+                .map_err(|e| PaymentError::decode("JSON output", e))?;
+            println!("{}", json);
+            println!(
+                "message: {}",
+                build_signature_message(&response.signed_field_names, |name| response.field(name))?
+            );
+            if result.signature_valid {
+                println!("signature: valid");
+            } else {
+                println!("signature: INVALID");
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::Status {
+            transaction_uuid,
+            total_amount,
+            product_code,
+        } => {
+            let product_code = product_code_or_env(product_code)?;
+            let status = check_transaction_status(
+                &product_code,
+                *total_amount,
+                transaction_uuid,
+                environment(&cli)?,
+            )
+            .await?;
+            let json = serde_json::to_string_pretty(&status)
+                .map_err(|e| PaymentError::decode("JSON output", e))?;
+            println!("{}", json);
+        }
+        Command::Form(args) => {
+            let request = EsewaPaymentRequest::builder(args.amount)
+                .tax_amount(args.tax_amount)
+                .product_service_charge(args.service_charge)
+                .product_delivery_charge(args.delivery_charge)
+                .transaction_uuid(
+                    args.transaction_uuid
+                        .clone()
+                        .unwrap_or_else(generate_transaction_uuid),
+                )
+                .product_code(product_code_or_env(&args.product_code)?)
+                .success_url(args.success_url.clone())
+                .failure_url(args.failure_url.clone())
+                .build()?;
+            let form = build_checkout_form(&request, &secret_key(&cli)?, environment(&cli)?)?;
+            print!("{}", form.to_html());
+        }
+        Command::Uuid => println!("{}", generate_transaction_uuid()),
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn parse_field(field: &str) -> Result<(String, String), PaymentError> {
+    field
+        .split_once('=')
+        .filter(|(name, _)| !name.is_empty())
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .ok_or_else(|| PaymentError::Config(format!("expected NAME=VALUE, got {:?}", field)))
+}
+
+fn secret_key(cli: &Cli) -> Result<MerchantSecret, PaymentError> {
+    match &cli.secret_file {
+        Some(path) => MerchantSecret::from_file(path),
+        None => MerchantSecret::from_env(ENV_SECRET_KEY).map_err(|_| {
+            PaymentError::Config(format!(
+                "no secret key; set {} or pass --secret-file",
+                ENV_SECRET_KEY
+            ))
+        }),
+    }
+}
+
+fn product_code_or_env(product_code: &Option<String>) -> Result<String, PaymentError> {
+    match product_code {
+        Some(code) => Ok(code.clone()),
+        None => std::env::var(ENV_PRODUCT_CODE).map_err(|_| {
+            PaymentError::Config(format!(
+                "no product code; pass --product-code or set {}",
+                ENV_PRODUCT_CODE
+            ))
+        }),
+    }
+}
+
+fn environment(cli: &Cli) -> Result<EsewaEnvironment, PaymentError> {
+    match &cli.environment {
+        Some(environment) => Ok(environment.clone()),
+        None => EsewaEnvironment::from_env(),
+    }
+}
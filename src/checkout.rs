@@ -0,0 +1,296 @@
+//! Framework-independent eSewa checkout service.
+//!
+//! [`EsewaCheckout`] holds what every shop writes around
+//! [`validate_esewa_response`](crate::validate_esewa_response): it records a
+//! payment for an order, verifies the success callback against the stored
+//! payment with a [`CallbackProcessor`], confirms failure redirects with a
+//! status check, and calls the application's [`CheckoutHooks`] once per paid
+//! or failed order. The `axum` and `actix-web` features serve it over HTTP.
+
+use crate::error::PaymentError;
+use crate::esewa::{generate_transaction_uuid, EsewaClient, EsewaPaymentRequest};
+use crate::form::{escape_html, CheckoutForm};
+use crate::lifecycle::PaymentState;
+use crate::money::Npr;
+use crate::replay::{
+    CallbackKey, CallbackOutcome, CallbackProcessor, DedupStore, MemoryDedupStore,
+};
+use crate::store::{MemoryTransactionStore, TransactionRecord, TransactionStore};
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// Application callbacks for [`EsewaCheckout`]
+///
+/// `on_paid` and `on_failed` are called at most once per payment. If they
+/// fail, the callback is forgotten so eSewa's or the customer's retry calls
+/// them again.
+#[async_trait]
+pub trait CheckoutHooks: Send + Sync + 'static {
+    /// Amount to charge for `order_id`; fail to refuse the checkout
+    async fn order_amount(&self, order_id: &str) -> Result<Npr, PaymentError>;
+
+    /// Called once when the payment for an order completes
+    async fn on_paid(&self, record: &TransactionRecord) -> Result<(), PaymentError>;
+
+    /// Called once when the payment for an order fails or is canceled
+    async fn on_failed(&self, record: &TransactionRecord) -> Result<(), PaymentError> {
+        let _ = record;
+        Ok(())
+    }
+
+    /// Page shown to the customer after a success or failure redirect
+    ///
+    /// Defaults to [`CheckoutPage::for_record`]; override to redirect to
+    /// your own order page instead.
+    fn render(&self, record: &TransactionRecord) -> CheckoutPage {
+        CheckoutPage::for_record(record)
+    }
+}
+
+/// eSewa checkout service: initiation and callback handling for orders
+///
+/// Cloning is cheap; all clones share the same stores and hooks.
+#[derive(Clone)]
+pub struct EsewaCheckout {
+    client: EsewaClient,
+    base_url: String,
+    store: Arc<dyn TransactionStore>,
+    dedup: Arc<dyn DedupStore>,
+    processor: CallbackProcessor,
+    hooks: Arc<dyn CheckoutHooks>,
+}
+
+impl std::fmt::Debug for EsewaCheckout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EsewaCheckout")
+            .field("client", &self.client)
+            .field("base_url", &self.base_url)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EsewaCheckout {
+    /// Starts building a checkout service
+    ///
+    /// `base_url` is the public URL the routes are served under, e.g.
+    /// `https://shop.example.com/pay`; eSewa redirects the customer to
+    /// `{base_url}/success` and `{base_url}/failure`.
+    pub fn builder(
+        client: EsewaClient,
+        base_url: impl Into<String>,
+        hooks: impl CheckoutHooks,
+    ) -> EsewaCheckoutBuilder {
+        EsewaCheckoutBuilder {
+            client,
+            base_url: base_url.into(),
+            hooks: Arc::new(hooks),
+            store: None,
+            dedup: None,
+        }
+    }
+
+    /// Transaction store the service records payments in
+    pub fn store(&self) -> &Arc<dyn TransactionStore> {
+        &self.store
+    }
+
+    /// Page to show the customer for `record`, as chosen by the hooks
+    pub fn render(&self, record: &TransactionRecord) -> CheckoutPage {
+        self.hooks.render(record)
+    }
+
+    /// Records a payment for `order_id` and returns its checkout form
+    pub async fn initiate(&self, order_id: &str) -> Result<CheckoutForm, PaymentError> {
+        let amount = self.hooks.order_amount(order_id).await?;
+        let transaction_uuid = generate_transaction_uuid();
+        let request = EsewaPaymentRequest::builder(amount)
+            .transaction_uuid(transaction_uuid.clone())
+            .product_code(self.client.product_code())
+            .success_url(format!("{}/success", self.base_url))
+            .failure_url(format!(
+                "{}/failure?transaction_uuid={}",
+                self.base_url, transaction_uuid
+            ))
+            .build()?;
+
+        self.store
+            .insert(&TransactionRecord::for_esewa(order_id, &request))
+            .await?;
+        self.store
+            .transition(&transaction_uuid, PaymentState::Initiated)
+            .await?;
+        self.client.checkout_form(request)
+    }
+
+    /// Handles the `data` parameter of a success redirect
+    ///
+    /// Re-deliveries return the stored record without calling the hooks.
+    pub async fn handle_success(&self, data: &str) -> Result<TransactionRecord, PaymentError> {
+        let (response, record) = match self.processor.process(data).await? {
+            CallbackOutcome::Processed {
+                response,
+                record: Some(record),
+            } => (response, record),
+            outcome => {
+                return self
+                    .store
+                    .require(&outcome.response().transaction_uuid)
+                    .await
+            }
+        };
+
+        if let Err(e) = self.notify(&record).await {
+            self.processor.forget(&response).await?;
+            return Err(e);
+        }
+        Ok(record)
+    }
+
+    /// Handles a failure redirect by asking eSewa for the payment's status
+    pub async fn handle_failure(
+        &self,
+        transaction_uuid: &str,
+    ) -> Result<TransactionRecord, PaymentError> {
+        let record = self.store.require(transaction_uuid).await?;
+        if record.state.is_settled() {
+            return Ok(record);
+        }
+
+        let status = self
+            .client
+            .check_status(record.amount, transaction_uuid)
+            .await?;
+        let next = PaymentState::from(&status.status);
+        if !next.is_settled() {
+            return self.store.transition(transaction_uuid, next).await;
+        }
+
+        let key = CallbackKey::settlement(transaction_uuid, next);
+        if !self.dedup.mark_processed(&key).await? {
+            return self.store.require(transaction_uuid).await;
+        }
+        let result = match self.store.transition(transaction_uuid, next).await {
+            Ok(record) => self.notify(&record).await.map(|()| record),
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            self.dedup.forget(&key).await?;
+        }
+        result
+    }
+
+    async fn notify(&self, record: &TransactionRecord) -> Result<(), PaymentError> {
+        match record.state {
+            PaymentState::Completed => self.hooks.on_paid(record).await,
+            PaymentState::Failed | PaymentState::Canceled => self.hooks.on_failed(record).await,
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Builder for [`EsewaCheckout`]
+pub struct EsewaCheckoutBuilder {
+    client: EsewaClient,
+    base_url: String,
+    hooks: Arc<dyn CheckoutHooks>,
+    store: Option<Arc<dyn TransactionStore>>,
+    dedup: Option<Arc<dyn DedupStore>>,
+}
+
+impl std::fmt::Debug for EsewaCheckoutBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EsewaCheckoutBuilder")
+            .field("client", &self.client)
+            .field("base_url", &self.base_url)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EsewaCheckoutBuilder {
+    /// Sets the transaction store (defaults to a [`MemoryTransactionStore`])
+    pub fn store(mut self, store: Arc<dyn TransactionStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Sets the dedup store (defaults to a [`MemoryDedupStore`])
+    pub fn dedup(mut self, dedup: Arc<dyn DedupStore>) -> Self {
+        self.dedup = Some(dedup);
+        self
+    }
+
+    /// Builds the service; fails if `base_url` is not an http(s) URL
+    pub fn build(self) -> Result<EsewaCheckout, PaymentError> {
+        let base_url = self.base_url.trim_end_matches('/').to_string();
+        if !base_url.starts_with("http://") && !base_url.starts_with("https://") {
+            return Err(PaymentError::Config(format!(
+                "checkout base URL must be an http(s) URL, got {:?}",
+                self.base_url
+            )));
+        }
+
+        let store = self
+            .store
+            .unwrap_or_else(|| Arc::new(MemoryTransactionStore::new()));
+        let dedup = self
+            .dedup
+            .unwrap_or_else(|| Arc::new(MemoryDedupStore::new()));
+        let processor = CallbackProcessor::new(self.client.secret_keys().clone(), dedup.clone())
+            .with_store(store.clone());
+
+        Ok(EsewaCheckout {
+            client: self.client,
+            base_url,
+            store,
+            dedup,
+            processor,
+            hooks: self.hooks,
+        })
+    }
+}
+
+/// What to show the customer after a success or failure redirect
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckoutPage {
+    /// An HTML page
+    Html(String),
+    /// A redirect (303 See Other) to the given URL
+    Redirect(String),
+}
+
+impl CheckoutPage {
+    /// Short HTML page stating the payment's outcome and the order ID
+    pub fn for_record(record: &TransactionRecord) -> Self {
+        let message = match record.state {
+            PaymentState::Completed => "Payment received. Thank you!",
+            PaymentState::Failed | PaymentState::Canceled => "The payment did not go through.",
+            _ => "Your payment is being processed.",
+        };
+        CheckoutPage::Html(format!(
+            "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Payment</title></head>\n<body>\n<p>{}</p>\n<p>Order {}</p>\n</body>\n</html>\n",
+            message,
+            escape_html(&record.order_id)
+        ))
+    }
+}
+
+/// HTTP status for an error from the checkout routes
+///
+/// The routes answer with the status and its reason phrase only, so error
+/// details are not echoed to the customer.
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+pub(crate) fn error_status(error: &PaymentError) -> u16 {
+    match error {
+        error if error.is_verification_failure() => 400,
+        PaymentError::MalformedSignature(_) | PaymentError::Decode { .. } => 400,
+        PaymentError::UnknownTransaction(_) => 404,
+        PaymentError::InvalidTransition { .. } => 409,
+        PaymentError::Timeout(_) => 504,
+        PaymentError::Retried { last, .. } => error_status(last),
+        PaymentError::Network(_)
+        | PaymentError::Http { .. }
+        | PaymentError::Gateway { .. }
+        | PaymentError::InvalidResponse(_) => 502,
+        _ => 500,
+    }
+}
@@ -0,0 +1,758 @@
+//! connectIPS module implementing the bank-transfer checkout and transaction validation.
+//!
+//! connectIPS authenticates merchants with RSA: the checkout form and every
+//! validation request carry a `TOKEN`, an RSA-SHA256 (PKCS#1 v1.5) signature
+//! of the request fields made with the merchant's private key. The key is
+//! issued as a `.pfx` (PKCS#12) file; PEM keys are accepted as well.
+
+use crate::error::PaymentError;
+use crate::esewa::ExpectedPayment;
+use crate::form::CheckoutForm;
+use crate::gateway::{
+    nepal_date, required_param, CallbackParams, CustomerAction, Initiation, PaymentGateway,
+    PaymentIntent, PaymentOutcome, PaymentStatus,
+};
+use crate::http::{build_client, read_env, send_json, DEFAULT_CONNECT_TIMEOUT, DEFAULT_TIMEOUT};
+use crate::money::{self, Npr};
+use crate::retry::{Idempotency, RetryPolicy};
+use crate::secret::MerchantSecret;
+use async_trait::async_trait;
+use base64::{engine::general_purpose, Engine};
+use reqwest::Client;
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs1v15::SigningKey;
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::signature::{SignatureEncoding, Signer};
+use rsa::RsaPrivateKey;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// Environment variable selecting the connectIPS environment
+pub const ENV_ENVIRONMENT: &str = "CONNECTIPS_ENVIRONMENT";
+/// Environment variable overriding the connectIPS base URL
+pub const ENV_BASE_URL: &str = "CONNECTIPS_BASE_URL";
+/// Environment variable holding the numeric merchant ID
+pub const ENV_MERCHANT_ID: &str = "CONNECTIPS_MERCHANT_ID";
+/// Environment variable holding the application ID
+pub const ENV_APP_ID: &str = "CONNECTIPS_APP_ID";
+/// Environment variable holding the application name
+pub const ENV_APP_NAME: &str = "CONNECTIPS_APP_NAME";
+/// Environment variable holding the application password used for basic auth
+pub const ENV_PASSWORD: &str = "CONNECTIPS_PASSWORD";
+/// Environment variable holding the path of the merchant's `.pfx` key file
+pub const ENV_PFX_PATH: &str = "CONNECTIPS_PFX_PATH";
+/// Environment variable holding the password of the `.pfx` key file
+pub const ENV_PFX_PASSWORD: &str = "CONNECTIPS_PFX_PASSWORD";
+
+/// Which connectIPS environment to use for requests
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectIpsEnvironment {
+    /// Use the connectIPS UAT environment (`uat.connectips.com`)
+    Sandbox,
+    /// Use the production connectIPS hosts
+    Production,
+    /// Use one host serving both the gateway and the web service paths
+    Custom { base_url: String },
+}
+
+impl ConnectIpsEnvironment {
+    /// URL the checkout form posts to
+    pub fn gateway_url(&self) -> String {
+        let base = match self {
+            ConnectIpsEnvironment::Sandbox => "https://uat.connectips.com",
+            ConnectIpsEnvironment::Production => "https://login.connectips.com",
+            ConnectIpsEnvironment::Custom { base_url } => base_url.trim_end_matches('/'),
+        };
+        format!("{}/connectipswebgw/loginpage", base)
+    }
+
+    /// URL of the transaction validation endpoint
+    pub fn validate_url(&self) -> String {
+        let base = match self {
+            ConnectIpsEnvironment::Sandbox => "https://uat.connectips.com",
+            ConnectIpsEnvironment::Production => "https://connectips.com",
+            ConnectIpsEnvironment::Custom { base_url } => base_url.trim_end_matches('/'),
+        };
+        format!("{}/connectipswebws/api/creditor/validatetxn", base)
+    }
+
+    /// Reads the environment from `CONNECTIPS_BASE_URL` or `CONNECTIPS_ENVIRONMENT`
+    ///
+    /// Behaves like [`EsewaEnvironment::from_env`](crate::EsewaEnvironment::from_env).
+    pub fn from_env() -> Result<Self, PaymentError> {
+        if let Some(base_url) = read_env(ENV_BASE_URL)? {
+            return Ok(ConnectIpsEnvironment::Custom { base_url });
+        }
+        match read_env(ENV_ENVIRONMENT)? {
+            Some(value) => value.parse(),
+            None => Ok(ConnectIpsEnvironment::Sandbox),
+        }
+    }
+}
+
+impl std::str::FromStr for ConnectIpsEnvironment {
+    type Err = PaymentError;
+
+    /// Parses `sandbox`, `production`, or an `http(s)://` base URL
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let value = s.trim();
+        match value.to_ascii_lowercase().as_str() {
+            "sandbox" | "test" | "uat" => Ok(ConnectIpsEnvironment::Sandbox),
+            "production" | "prod" | "live" => Ok(ConnectIpsEnvironment::Production),
+            lower if lower.starts_with("http://") || lower.starts_with("https://") => {
+                Ok(ConnectIpsEnvironment::Custom {
+                    base_url: value.to_string(),
+                })
+            }
+            _ => Err(PaymentError::Config(format!(
+                "Unknown connectIPS environment {:?}; expected sandbox, production or a base URL",
+                value
+            ))),
+        }
+    }
+}
+
+/// The merchant's RSA private key used to sign tokens
+///
+/// `Debug` never prints key material.
+#[derive(Clone)]
+pub struct ConnectIpsKey {
+    signing_key: SigningKey<Sha256>,
+}
+
+impl std::fmt::Debug for ConnectIpsKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ConnectIpsKey(..)")
+    }
+}
+
+impl ConnectIpsKey {
+    /// Loads the private key from the contents of a `.pfx` (PKCS#12) file
+    pub fn from_pfx(data: &[u8], password: &str) -> Result<Self, PaymentError> {
+        let keystore = p12_keystore::KeyStore::from_pkcs12(data, password)
+            .map_err(|e| PaymentError::Config(format!("Cannot read .pfx file: {}", e)))?;
+        let (_, chain) = keystore
+            .private_key_chain()
+            .ok_or_else(|| PaymentError::Config(".pfx file has no private key".to_string()))?;
+        let key = RsaPrivateKey::from_pkcs8_der(chain.key())
+            .map_err(|e| PaymentError::Config(format!("Invalid RSA key in .pfx file: {}", e)))?;
+        Ok(Self::from_rsa(key))
+    }
+
+    /// Loads the private key from a `.pfx` (PKCS#12) file on disk
+    pub fn from_pfx_file(path: impl AsRef<Path>, password: &str) -> Result<Self, PaymentError> {
+        let path = path.as_ref();
+        let data = std::fs::read(path)
+            .map_err(|e| PaymentError::Config(format!("Cannot read {}: {}", path.display(), e)))?;
+        Self::from_pfx(&data, password)
+    }
+
+    /// Loads the private key from PEM, either PKCS#8 (`PRIVATE KEY`) or PKCS#1 (`RSA PRIVATE KEY`)
+    pub fn from_pem(pem: &str) -> Result<Self, PaymentError> {
+        let key = RsaPrivateKey::from_pkcs8_pem(pem)
+            .or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem))
+            .map_err(|e| PaymentError::Config(format!("Invalid RSA private key: {}", e)))?;
+        Ok(Self::from_rsa(key))
+    }
+
+    /// Wraps an already loaded RSA key
+    pub fn from_rsa(key: RsaPrivateKey) -> Self {
+        ConnectIpsKey {
+            signing_key: SigningKey::<Sha256>::new(key),
+        }
+    }
+
+    /// Signs `message` with RSA-SHA256 and returns the base64 signature
+    pub fn sign(&self, message: &str) -> String {
+        let signature = self.signing_key.sign(message.as_bytes());
+        general_purpose::STANDARD.encode(signature.to_bytes())
+    }
+}
+
+/// A checkout request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectIpsPaymentRequest {
+    /// Merchant's unique transaction ID (`TXNID`)
+    pub txn_id: String,
+    /// Amount to collect; sent to connectIPS in paisa
+    pub amount: Npr,
+    /// Transaction date as `DD-MM-YYYY`
+    pub date: String,
+    /// Reference shown on the bank statement (`REFERENCEID`)
+    pub reference_id: String,
+    /// Remarks (`REMARKS`)
+    pub remarks: String,
+    /// Particulars (`PARTICULARS`)
+    pub particulars: String,
+}
+
+impl ConnectIpsPaymentRequest {
+    /// Creates a request dated today (Nepal time) that uses `txn_id` as
+    /// reference, remarks and particulars
+    pub fn new(txn_id: impl Into<String>, amount: Npr) -> Self {
+        let txn_id = txn_id.into();
+        let (year, month, day) = nepal_date(SystemTime::now());
+        ConnectIpsPaymentRequest {
+            reference_id: txn_id.clone(),
+            remarks: txn_id.clone(),
+            particulars: txn_id.clone(),
+            txn_id,
+            amount,
+            date: format!("{:02}-{:02}-{}", day, month, year),
+        }
+    }
+}
+
+/// Transaction status reported by the validation endpoint
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ConnectIpsStatus {
+    /// The transfer succeeded
+    Success,
+    /// The transfer failed
+    Failed,
+    /// connectIPS could not determine the outcome
+    Error,
+    /// A status not known to this version of the crate
+    Unknown(String),
+}
+
+impl ConnectIpsStatus {
+    /// The status string as connectIPS sends it
+    pub fn as_str(&self) -> &str {
+        match self {
+            ConnectIpsStatus::Success => "SUCCESS",
+            ConnectIpsStatus::Failed => "FAILED",
+            ConnectIpsStatus::Error => "ERROR",
+            ConnectIpsStatus::Unknown(status) => status,
+        }
+    }
+
+    /// Returns `true` if the transfer succeeded
+    pub fn is_success(&self) -> bool {
+        matches!(self, ConnectIpsStatus::Success)
+    }
+}
+
+impl From<&str> for ConnectIpsStatus {
+    fn from(status: &str) -> Self {
+        match status.to_ascii_uppercase().as_str() {
+            "SUCCESS" => ConnectIpsStatus::Success,
+            "FAILED" => ConnectIpsStatus::Failed,
+            "ERROR" => ConnectIpsStatus::Error,
+            _ => ConnectIpsStatus::Unknown(status.to_string()),
+        }
+    }
+}
+
+impl From<&ConnectIpsStatus> for PaymentStatus {
+    fn from(status: &ConnectIpsStatus) -> Self {
+        match status {
+            ConnectIpsStatus::Success => PaymentStatus::Completed,
+            ConnectIpsStatus::Failed => PaymentStatus::Failed,
+            ConnectIpsStatus::Error | ConnectIpsStatus::Unknown(_) => PaymentStatus::Ambiguous,
+        }
+    }
+}
+
+impl std::fmt::Display for ConnectIpsStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for ConnectIpsStatus {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for ConnectIpsStatus {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let status = String::deserialize(deserializer)?;
+        Ok(ConnectIpsStatus::from(status.as_str()))
+    }
+}
+
+/// Response of the transaction validation endpoint
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectIpsValidation {
+    pub merchant_id: u64,
+    pub app_id: String,
+    /// The merchant's transaction ID
+    pub reference_id: String,
+    #[serde(with = "money::paisa")]
+    pub txn_amt: Npr,
+    /// The token sent in the validation request, echoed back
+    pub token: String,
+    pub status: ConnectIpsStatus,
+    #[serde(default)]
+    pub status_desc: String,
+}
+
+/// Result of [`ConnectIpsClient::validate_transaction`]
+///
+/// connectIPS does not sign its answer, so unlike
+/// [`ValidationResult`](crate::ValidationResult) there is no signature to
+/// check. `token_echoed` only ties the answer to the request this client sent.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectIpsValidationResult {
+    /// `true` when the answer carries the token signed for the request
+    pub token_echoed: bool,
+    pub response: ConnectIpsValidation,
+}
+
+/// Reusable connectIPS client holding the merchant configuration, key and a pooled HTTP client
+///
+/// Create one per process with [`ConnectIpsClient::builder`] and share it; cloning is cheap.
+#[derive(Clone)]
+pub struct ConnectIpsClient {
+    merchant_id: u64,
+    app_id: String,
+    app_name: String,
+    password: MerchantSecret,
+    key: ConnectIpsKey,
+    environment: ConnectIpsEnvironment,
+    http: Client,
+    retry: RetryPolicy,
+}
+
+impl std::fmt::Debug for ConnectIpsClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConnectIpsClient")
+            .field("merchant_id", &self.merchant_id)
+            .field("app_id", &self.app_id)
+            .field("app_name", &self.app_name)
+            .field("environment", &self.environment)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ConnectIpsClient {
+    /// Starts building a client
+    ///
+    /// `password` is the application password connectIPS issues for basic
+    /// authentication of the web service calls.
+    pub fn builder(
+        merchant_id: u64,
+        app_id: impl Into<String>,
+        app_name: impl Into<String>,
+        password: impl Into<MerchantSecret>,
+        key: ConnectIpsKey,
+    ) -> ConnectIpsClientBuilder {
+        ConnectIpsClientBuilder {
+            merchant_id,
+            app_id: app_id.into(),
+            app_name: app_name.into(),
+            password: password.into(),
+            key,
+            environment: ConnectIpsEnvironment::Sandbox,
+            timeout: DEFAULT_TIMEOUT,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            user_agent: None,
+            http_client: None,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Environment this client talks to
+    pub fn environment(&self) -> &ConnectIpsEnvironment {
+        &self.environment
+    }
+
+    /// Returns the checkout form fields, including the signed `TOKEN`, in the order connectIPS signs them
+    pub fn form_fields(&self, request: &ConnectIpsPaymentRequest) -> Vec<(String, String)> {
+        let mut fields: Vec<(String, String)> = [
+            ("MERCHANTID", self.merchant_id.to_string()),
+            ("APPID", self.app_id.clone()),
+            ("APPNAME", self.app_name.clone()),
+            ("TXNID", request.txn_id.clone()),
+            ("TXNDATE", request.date.clone()),
+            ("TXNCRNCY", "NPR".to_string()),
+            ("TXNAMT", request.amount.paisa().to_string()),
+            ("REFERENCEID", request.reference_id.clone()),
+            ("REMARKS", request.remarks.clone()),
+            ("PARTICULARS", request.particulars.clone()),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect();
+
+        let token = self.key.sign(&token_message(&fields));
+        fields.push(("TOKEN".to_string(), token));
+        fields
+    }
+
+    /// Builds the self-submitting checkout form
+    pub fn checkout_form(&self, request: &ConnectIpsPaymentRequest) -> CheckoutForm {
+        CheckoutForm {
+            action: self.environment.gateway_url(),
+            fields: self.form_fields(request),
+        }
+    }
+
+    /// Asks connectIPS to validate a transaction
+    ///
+    /// `token_echoed` is `true` when connectIPS echoed back the token this
+    /// client signed for the request. The reference, amount and status must
+    /// still be checked; [`verify_transaction`](Self::verify_transaction)
+    /// does all of it.
+    pub async fn validate_transaction(
+        &self,
+        txn_id: &str,
+        amount: Npr,
+    ) -> Result<ConnectIpsValidationResult, PaymentError> {
+        let txn_amt = amount.paisa().to_string();
+        let token = self.key.sign(&format!(
+            "MERCHANTID={},APPID={},REFERENCEID={},TXNAMT={}",
+            self.merchant_id, self.app_id, txn_id, txn_amt
+        ));
+        let body = serde_json::json!({
+            "merchantId": self.merchant_id,
+            "appId": self.app_id,
+            "referenceId": txn_id,
+            "txnAmt": txn_amt,
+            "token": token,
+        });
+
+        let request = self
+            .http
+            .post(self.environment.validate_url())
+            .basic_auth(&self.app_id, Some(self.password.expose_secret()))
+            .json(&body);
+        let validation: ConnectIpsValidation =
+            send_json(&self.retry, Idempotency::Idempotent, request).await?;
+
+        Ok(ConnectIpsValidationResult {
+            token_echoed: validation.token == token,
+            response: validation,
+        })
+    }
+
+    /// Validates a transaction and checks it against the expected payment
+    ///
+    /// Fails closed: the echoed token, application, reference and amount must
+    /// all match. An answer without the request's token is reported as
+    /// [`PaymentError::InvalidResponse`]. A failed transfer still verifies;
+    /// check the returned status.
+    pub async fn verify_transaction(
+        &self,
+        expected: &ExpectedPayment,
+    ) -> Result<ConnectIpsValidation, PaymentError> {
+        let result = self
+            .validate_transaction(&expected.transaction_uuid, expected.total_amount)
+            .await?;
+        let validation = result.response;
+
+        if !result.token_echoed {
+            return Err(PaymentError::InvalidResponse(
+                "connectIPS did not echo the request token".to_string(),
+            ));
+        }
+        if validation.app_id != self.app_id {
+            return Err(PaymentError::ProductCodeMismatch {
+                expected: self.app_id.clone(),
+                actual: validation.app_id,
+            });
+        }
+        if validation.reference_id != expected.transaction_uuid {
+            return Err(PaymentError::TransactionUuidMismatch {
+                expected: expected.transaction_uuid.clone(),
+                actual: validation.reference_id,
+            });
+        }
+        if validation.txn_amt != expected.total_amount {
+            return Err(PaymentError::AmountMismatch {
+                expected: expected.total_amount,
+                actual: validation.txn_amt,
+            });
+        }
+
+        Ok(validation)
+    }
+}
+
+/// connectIPS as a [`PaymentGateway`]
+///
+/// The success and failure URLs are registered with connectIPS per
+/// application, so the ones on the [`PaymentIntent`] are not sent.
+#[async_trait]
+impl PaymentGateway for ConnectIpsClient {
+    fn name(&self) -> &'static str {
+        "connectips"
+    }
+
+    async fn initiate(&self, intent: &PaymentIntent) -> Result<Initiation, PaymentError> {
+        let mut request =
+            ConnectIpsPaymentRequest::new(intent.transaction_id.clone(), intent.amount);
+        if let Some(description) = &intent.description {
+            request.remarks = description.clone();
+        }
+
+        Ok(Initiation {
+            action: CustomerAction::Form(self.checkout_form(&request)),
+            gateway_reference: None,
+        })
+    }
+
+    async fn verify_callback(
+        &self,
+        params: &CallbackParams,
+        intent: &PaymentIntent,
+    ) -> Result<PaymentOutcome, PaymentError> {
+        let txn_id = required_param(params, "TXNID")?;
+        if txn_id != intent.transaction_id {
+            return Err(PaymentError::TransactionUuidMismatch {
+                expected: intent.transaction_id.clone(),
+                actual: txn_id.to_string(),
+            });
+        }
+
+        self.lookup_status(intent).await
+    }
+
+    async fn lookup_status(&self, intent: &PaymentIntent) -> Result<PaymentOutcome, PaymentError> {
+        let expected = ExpectedPayment::new(intent.transaction_id.clone(), intent.amount);
+        let validation = self.verify_transaction(&expected).await?;
+
+        Ok(PaymentOutcome {
+            gateway: self.name().to_string(),
+            transaction_id: validation.reference_id,
+            gateway_reference: None,
+            amount: Some(validation.txn_amt),
+            status: PaymentStatus::from(&validation.status),
+        })
+    }
+}
+
+/// Builder for [`ConnectIpsClient`]
+#[derive(Debug)]
+pub struct ConnectIpsClientBuilder {
+    merchant_id: u64,
+    app_id: String,
+    app_name: String,
+    password: MerchantSecret,
+    key: ConnectIpsKey,
+    environment: ConnectIpsEnvironment,
+    timeout: Duration,
+    connect_timeout: Duration,
+    user_agent: Option<String>,
+    http_client: Option<Client>,
+    retry: RetryPolicy,
+}
+
+impl ConnectIpsClientBuilder {
+    /// Starts a builder from environment variables
+    ///
+    /// Reads `CONNECTIPS_MERCHANT_ID`, `CONNECTIPS_APP_ID`, `CONNECTIPS_APP_NAME`,
+    /// `CONNECTIPS_PASSWORD`, `CONNECTIPS_PFX_PATH` and `CONNECTIPS_PFX_PASSWORD`
+    /// (all required), and the environment as described in
+    /// [`ConnectIpsEnvironment::from_env`].
+    pub fn from_env() -> Result<Self, PaymentError> {
+        let required = |name: &str| {
+            read_env(name)?.ok_or_else(|| PaymentError::Config(format!("{} is not set", name)))
+        };
+        let merchant_id = required(ENV_MERCHANT_ID)?;
+        let merchant_id = merchant_id.parse().map_err(|_| {
+            PaymentError::Config(format!(
+                "{} must be a number, got {:?}",
+                ENV_MERCHANT_ID, merchant_id
+            ))
+        })?;
+        let pfx_password = MerchantSecret::from_env(ENV_PFX_PASSWORD)?;
+        let key =
+            ConnectIpsKey::from_pfx_file(required(ENV_PFX_PATH)?, pfx_password.expose_secret())?;
+
+        Ok(ConnectIpsClient::builder(
+            merchant_id,
+            required(ENV_APP_ID)?,
+            required(ENV_APP_NAME)?,
+            MerchantSecret::from_env(ENV_PASSWORD)?,
+            key,
+        )
+        .environment(ConnectIpsEnvironment::from_env()?))
+    }
+
+    /// Sets the connectIPS environment (defaults to [`ConnectIpsEnvironment::Sandbox`])
+    pub fn environment(mut self, environment: ConnectIpsEnvironment) -> Self {
+        self.environment = environment;
+        self
+    }
+
+    /// Sets the total timeout for each request (defaults to 30 seconds)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the connection timeout (defaults to 10 seconds)
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Sets the `User-Agent` header sent to connectIPS
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Uses a preconfigured HTTP client; timeouts and user agent are then ignored
+    pub fn http_client(mut self, client: Client) -> Self {
+        self.http_client = Some(client);
+        self
+    }
+
+    /// Sets how failed requests are retried (defaults to [`RetryPolicy::default`])
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Builds the client
+    pub fn build(self) -> Result<ConnectIpsClient, PaymentError> {
+        if self.app_id.is_empty() {
+            return Err(PaymentError::Config("app ID is empty".to_string()));
+        }
+        if self.password.is_empty() {
+            return Err(PaymentError::Config("app password is empty".to_string()));
+        }
+
+        let http = match self.http_client {
+            Some(client) => client,
+            None => build_client(self.timeout, self.connect_timeout, self.user_agent)?,
+        };
+
+        Ok(ConnectIpsClient {
+            merchant_id: self.merchant_id,
+            app_id: self.app_id,
+            app_name: self.app_name,
+            password: self.password,
+            key: self.key,
+            environment: self.environment,
+            http,
+            retry: self.retry,
+        })
+    }
+}
+
+/// Builds the token message `NAME=value,...,TOKEN=TOKEN` from the form fields
+fn token_message(fields: &[(String, String)]) -> String {
+    let mut parts: Vec<String> = fields
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    parts.push("TOKEN=TOKEN".to_string());
+    parts.join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::pkcs1v15::{Signature, VerifyingKey};
+    use rsa::signature::Verifier;
+
+    const TEST_PFX: &[u8] = include_bytes!("../tests/fixtures/connectips_test.pfx");
+    const TEST_PEM: &str = include_str!("../tests/fixtures/connectips_test.pem");
+
+    fn client() -> ConnectIpsClient {
+        let key = ConnectIpsKey::from_pem(TEST_PEM).unwrap();
+        ConnectIpsClient::builder(123, "MER-123-APP-1", "Test shop", "app-password", key)
+            .build()
+            .unwrap()
+    }
+
+    fn verifying_key() -> VerifyingKey<Sha256> {
+        let key = RsaPrivateKey::from_pkcs1_pem(TEST_PEM).unwrap();
+        VerifyingKey::new(key.to_public_key())
+    }
+
+    #[test]
+    fn test_pfx_and_pem_load_the_same_key() {
+        let from_pfx = ConnectIpsKey::from_pfx(TEST_PFX, "test").unwrap();
+        let from_pem = ConnectIpsKey::from_pem(TEST_PEM).unwrap();
+
+        // PKCS#1 v1.5 signatures are deterministic
+        assert_eq!(from_pfx.sign("message"), from_pem.sign("message"));
+        assert_eq!(format!("{:?}", from_pfx), "ConnectIpsKey(..)");
+        assert!(matches!(
+            ConnectIpsKey::from_pfx(TEST_PFX, "wrong"),
+            Err(PaymentError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_form_fields_are_signed() {
+        let mut request = ConnectIpsPaymentRequest::new("TXN-1", Npr::from_rupees(10));
+        request.date = "16-10-2026".to_string();
+
+        let fields = client().form_fields(&request);
+        let value = |name: &str| {
+            fields
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.as_str())
+        };
+
+        assert_eq!(value("TXNAMT"), Some("1000"));
+        assert_eq!(value("TXNCRNCY"), Some("NPR"));
+
+        let message = "MERCHANTID=123,APPID=MER-123-APP-1,APPNAME=Test shop,TXNID=TXN-1,TXNDATE=16-10-2026,\
+                       TXNCRNCY=NPR,TXNAMT=1000,REFERENCEID=TXN-1,REMARKS=TXN-1,PARTICULARS=TXN-1,TOKEN=TOKEN";
+        let signature = general_purpose::STANDARD
+            .decode(value("TOKEN").unwrap())
+            .unwrap();
+        let signature = Signature::try_from(signature.as_slice()).unwrap();
+        assert!(verifying_key()
+            .verify(message.as_bytes(), &signature)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_checkout_form_posts_to_gateway() {
+        let form = client().checkout_form(&ConnectIpsPaymentRequest::new(
+            "TXN-1",
+            Npr::from_rupees(10),
+        ));
+
+        assert_eq!(
+            form.action,
+            "https://uat.connectips.com/connectipswebgw/loginpage"
+        );
+        assert_eq!(form.field("TXNID"), Some("TXN-1"));
+    }
+
+    #[test]
+    fn test_validation_deserialization() {
+        let body = r#"{"merchantId":123,"appId":"MER-123-APP-1","referenceId":"TXN-1","txnAmt":"1000","token":"abc","status":"SUCCESS","statusDesc":"TRANSACTION SUCCESSFUL"}"#;
+        let validation: ConnectIpsValidation = serde_json::from_str(body).unwrap();
+
+        assert_eq!(validation.status, ConnectIpsStatus::Success);
+        assert_eq!(validation.txn_amt, Npr::from_rupees(10));
+        assert_eq!(
+            PaymentStatus::from(&validation.status),
+            PaymentStatus::Completed
+        );
+        assert_eq!(
+            PaymentStatus::from(&ConnectIpsStatus::from("ERROR")),
+            PaymentStatus::Ambiguous
+        );
+    }
+
+    #[tokio::test]
+    async fn test_gateway_rejects_callback_for_other_transaction() {
+        let intent = PaymentIntent::new("TXN-1", Npr::from_rupees(10), "http://a", "http://b");
+        let params: CallbackParams = [("TXNID".to_string(), "TXN-2".to_string())]
+            .into_iter()
+            .collect();
+
+        let result = PaymentGateway::verify_callback(&client(), &params, &intent).await;
+
+        assert!(matches!(
+            result,
+            Err(PaymentError::TransactionUuidMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,244 @@
+//! The error type shared by every gateway.
+//!
+//! [`PaymentError`] keeps the underlying error where there is one, so
+//! [`std::error::Error::source`] tells a base64 failure from a JSON failure,
+//! or a DNS failure from a refused connection. [`PaymentError::is_retryable`]
+//! classifies failures that may succeed when the request is repeated.
+
+use crate::lifecycle::PaymentState;
+use crate::money::Npr;
+use std::fmt;
+
+/// Boxed source error kept inside a [`PaymentError`]
+pub type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// Error types for the payment library
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum PaymentError {
+    /// The gateway did not answer within the configured timeout
+    Timeout(BoxError),
+    /// The gateway could not be reached, e.g. DNS, connection or TLS failure
+    Network(BoxError),
+    /// The gateway answered with a non-success HTTP status
+    Http { status: u16, body: String },
+    /// The gateway answered, but refused the request with its own error code
+    Gateway { code: String, message: String },
+    /// The request failed after `attempts` attempts; `last` is the final failure
+    ///
+    /// Only returned when at least one retry was made, see
+    /// [`RetryPolicy`](crate::retry::RetryPolicy).
+    Retried {
+        attempts: u32,
+        last: Box<PaymentError>,
+    },
+    /// The gateway's answer does not match its documented format
+    InvalidResponse(String),
+    /// Input could not be decoded; `source` holds the base64, UTF-8 or JSON error
+    Decode {
+        context: String,
+        source: Option<BoxError>,
+    },
+    /// A signature could not be computed, e.g. a signed field is missing
+    Signature(String),
+    /// A received signature is not well-formed, e.g. not valid base64
+    MalformedSignature(BoxError),
+    /// The signature does not match the signed fields
+    SignatureMismatch,
+    /// The callback was issued for another merchant
+    ProductCodeMismatch { expected: String, actual: String },
+    /// The callback belongs to another transaction
+    TransactionUuidMismatch { expected: String, actual: String },
+    /// The paid total differs from the order total
+    AmountMismatch { expected: Npr, actual: Npr },
+    /// The library is misconfigured, e.g. a missing key or an invalid URL
+    Config(String),
+    /// An amount is negative, malformed or inconsistent with its parts
+    InvalidAmount(String),
+    /// The gateway does not offer the operation
+    Unsupported(String),
+    /// The payment cannot move between these states, e.g. `Completed -> Pending`
+    InvalidTransition {
+        from: PaymentState,
+        to: PaymentState,
+    },
+    /// A [`TransactionStore`](crate::store::TransactionStore) or
+    /// [`DedupStore`](crate::replay::DedupStore) failed
+    Storage(BoxError),
+    /// No payment is recorded under this transaction ID
+    UnknownTransaction(String),
+    /// A payment is already recorded under this transaction ID
+    DuplicateTransaction(String),
+}
+
+impl PaymentError {
+    /// Decode error caused by `source`
+    pub fn decode(context: impl Into<String>, source: impl Into<BoxError>) -> Self {
+        PaymentError::Decode {
+            context: context.into(),
+            source: Some(source.into()),
+        }
+    }
+
+    /// Returns `true` if repeating the request may succeed
+    ///
+    /// Timeouts, network failures and HTTP 408, 425, 429 and 5xx answers are
+    /// retryable. Whether repeating is *safe* depends on the operation: a
+    /// status check is, a payment initiation may not be.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            PaymentError::Timeout(_) | PaymentError::Network(_) => true,
+            PaymentError::Http { status, .. } => {
+                matches!(status, 408 | 425 | 429) || (500..600).contains(status)
+            }
+            PaymentError::Retried { last, .. } => last.is_retryable(),
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if a callback failed strict verification
+    pub fn is_verification_failure(&self) -> bool {
+        matches!(
+            self,
+            PaymentError::SignatureMismatch
+                | PaymentError::ProductCodeMismatch { .. }
+                | PaymentError::TransactionUuidMismatch { .. }
+                | PaymentError::AmountMismatch { .. }
+        )
+    }
+}
+
+impl fmt::Display for PaymentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaymentError::Timeout(source) => write!(f, "Request timed out: {}", source),
+            PaymentError::Network(source) => write!(f, "Network error: {}", source),
+            PaymentError::Http { status, body } if body.is_empty() => {
+                write!(f, "HTTP error: status {}", status)
+            }
+            PaymentError::Http { status, body } => {
+                write!(f, "HTTP error: status {}: {}", status, body)
+            }
+            PaymentError::Gateway { code, message } => {
+                write!(f, "Gateway error {}: {}", code, message)
+            }
+            PaymentError::Retried { attempts, last } => {
+                write!(f, "{} (after {} attempts)", last, attempts)
+            }
+            PaymentError::InvalidResponse(msg) => write!(f, "Invalid response: {}", msg),
+            PaymentError::Decode {
+                context,
+                source: Some(source),
+            } => write!(f, "Decode error: {}: {}", context, source),
+            PaymentError::Decode {
+                context,
+                source: None,
+            } => write!(f, "Decode error: {}", context),
+            PaymentError::Signature(msg) => write!(f, "Signature error: {}", msg),
+            PaymentError::MalformedSignature(source) => {
+                write!(f, "Malformed signature: {}", source)
+            }
+            PaymentError::SignatureMismatch => write!(f, "Verification failed: signature mismatch"),
+            PaymentError::ProductCodeMismatch { expected, actual } => write!(
+                f,
+                "Verification failed: product code mismatch: expected {}, got {}",
+                expected, actual
+            ),
+            PaymentError::TransactionUuidMismatch { expected, actual } => write!(
+                f,
+                "Verification failed: transaction UUID mismatch: expected {}, got {}",
+                expected, actual
+            ),
+            PaymentError::AmountMismatch { expected, actual } => write!(
+                f,
+                "Verification failed: total amount mismatch: expected {}, got {}",
+                expected, actual
+            ),
+            PaymentError::Config(msg) => write!(f, "Configuration error: {}", msg),
+            PaymentError::InvalidAmount(msg) => write!(f, "Invalid amount: {}", msg),
+            PaymentError::Unsupported(msg) => write!(f, "Unsupported operation: {}", msg),
+            PaymentError::InvalidTransition { from, to } => {
+                write!(f, "Invalid payment state transition: {} -> {}", from, to)
+            }
+            PaymentError::Storage(source) => write!(f, "Storage error: {}", source),
+            PaymentError::UnknownTransaction(id) => write!(f, "Unknown transaction: {}", id),
+            PaymentError::DuplicateTransaction(id) => {
+                write!(f, "Transaction already recorded: {}", id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PaymentError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PaymentError::Timeout(source)
+            | PaymentError::Network(source)
+            | PaymentError::MalformedSignature(source)
+            | PaymentError::Storage(source)
+            | PaymentError::Decode {
+                source: Some(source),
+                ..
+            } => Some(source.as_ref()),
+            PaymentError::Retried { last, .. } => Some(last.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// Sorts a failed request into [`PaymentError::Timeout`],
+/// [`PaymentError::Decode`] (unreadable body) or [`PaymentError::Network`]
+impl From<reqwest::Error> for PaymentError {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_timeout() {
+            PaymentError::Timeout(error.into())
+        } else if error.is_decode() {
+            PaymentError::decode("response body", error)
+        } else if error.is_builder() {
+            PaymentError::Config(error.to_string())
+        } else {
+            PaymentError::Network(error.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{engine::general_purpose, Engine};
+    use std::error::Error;
+
+    #[test]
+    fn test_source_tells_base64_from_json() {
+        let base64 = general_purpose::STANDARD.decode("not base64!").unwrap_err();
+        let json = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+
+        let base64 = PaymentError::decode("data parameter", base64);
+        let json = PaymentError::decode("data parameter", json);
+
+        assert!(base64.source().unwrap().is::<base64::DecodeError>());
+        assert!(json.source().unwrap().is::<serde_json::Error>());
+        assert!(base64
+            .to_string()
+            .starts_with("Decode error: data parameter: "));
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        let http = |status| PaymentError::Http {
+            status,
+            body: String::new(),
+        };
+
+        assert!(PaymentError::Timeout("deadline elapsed".into()).is_retryable());
+        assert!(http(503).is_retryable());
+        assert!(http(429).is_retryable());
+        assert!(!http(400).is_retryable());
+        assert!(!PaymentError::SignatureMismatch.is_retryable());
+        assert!(!PaymentError::Gateway {
+            code: "E01".to_string(),
+            message: "rejected".to_string(),
+        }
+        .is_retryable());
+    }
+}
@@ -2,22 +2,54 @@
 //!
 //! This file contains the implementation previously in `lib.rs`.
 
+pub use crate::error::PaymentError;
+
+use crate::form::CheckoutForm;
+use crate::gateway::{
+    required_param, CallbackParams, CustomerAction, Initiation, PaymentGateway, PaymentIntent, PaymentOutcome,
+    PaymentStatus,
+};
+use crate::http::{build_client, read_env, send_json, DEFAULT_CONNECT_TIMEOUT, DEFAULT_TIMEOUT};
+use crate::retry::{Idempotency, RetryPolicy};
+use crate::money::Npr;
+use crate::secret::{MatchedKey, MerchantSecret, SecretKeySet};
+use async_trait::async_trait;
 use base64::{engine::general_purpose, Engine};
 use hmac::{Hmac, Mac};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use sha2::Sha256;
+use std::time::{Duration, SystemTime};
+
+/// Field list signed when the caller does not declare one
+pub const DEFAULT_SIGNED_FIELD_NAMES: &str = "total_amount,transaction_uuid,product_code";
+
+/// Fields a callback's signature must cover before it is trusted
+///
+/// eSewa signs all of these; a callback declaring fewer could reuse the
+/// checkout form signature with a forged `status` or `transaction_code`.
+pub const CALLBACK_SIGNED_FIELDS: [&str; 6] = [
+    "transaction_code",
+    "status",
+    "total_amount",
+    "transaction_uuid",
+    "product_code",
+    "signed_field_names",
+];
 
 /// Represents the payment request data required by eSewa
+///
+/// Prefer [`EsewaPaymentRequest::builder`], which computes `total_amount`
+/// from its components.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EsewaPaymentRequest {
-    pub amount: String,
-    pub tax_amount: String,
-    pub total_amount: String,
+    pub amount: Npr,
+    pub tax_amount: Npr,
+    pub total_amount: Npr,
     pub transaction_uuid: String,
     pub product_code: String,
-    pub product_service_charge: String,
-    pub product_delivery_charge: String,
+    pub product_service_charge: Npr,
+    pub product_delivery_charge: Npr,
     pub success_url: String,
     pub failure_url: String,
     pub signed_field_names: String,
@@ -27,14 +59,237 @@ pub struct EsewaPaymentRequest {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EsewaPaymentResponse {
     pub transaction_code: String,
-    pub status: String,
-    pub total_amount: String,
+    pub status: EsewaStatus,
+    pub total_amount: Npr,
     pub transaction_uuid: String,
     pub product_code: String,
     pub signed_field_names: String,
     pub signature: String,
 }
 
+impl EsewaPaymentRequest {
+    /// Starts building a request for the given base amount
+    pub fn builder(amount: Npr) -> EsewaPaymentRequestBuilder {
+        EsewaPaymentRequestBuilder {
+            amount,
+            tax_amount: Npr::ZERO,
+            product_service_charge: Npr::ZERO,
+            product_delivery_charge: Npr::ZERO,
+            total_amount: None,
+            transaction_uuid: None,
+            product_code: String::new(),
+            success_url: None,
+            failure_url: None,
+            signed_field_names: DEFAULT_SIGNED_FIELD_NAMES.to_string(),
+        }
+    }
+
+    /// Sum of amount, tax, service charge and delivery charge
+    pub fn computed_total(&self) -> Result<Npr, PaymentError> {
+        sum_amounts(&[
+            self.amount,
+            self.tax_amount,
+            self.product_service_charge,
+            self.product_delivery_charge,
+        ])
+    }
+
+    /// Checks that `total_amount` equals the sum of its components
+    pub fn check_total(&self) -> Result<(), PaymentError> {
+        let computed = self.computed_total()?;
+        if computed != self.total_amount {
+            return Err(PaymentError::InvalidAmount(format!(
+                "total_amount {} does not match amount + tax + charges = {}",
+                self.total_amount, computed
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns the value of a form field by its eSewa field name
+    pub fn field(&self, name: &str) -> Option<String> {
+        let value = match name {
+            "amount" => self.amount.to_string(),
+            "tax_amount" => self.tax_amount.to_string(),
+            "total_amount" => self.total_amount.to_string(),
+            "transaction_uuid" => self.transaction_uuid.clone(),
+            "product_code" => self.product_code.clone(),
+            "product_service_charge" => self.product_service_charge.to_string(),
+            "product_delivery_charge" => self.product_delivery_charge.to_string(),
+            "success_url" => self.success_url.clone(),
+            "failure_url" => self.failure_url.clone(),
+            "signed_field_names" => self.signed_field_names.clone(),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Generates the signature over this request's `signed_field_names`
+    pub fn signature(&self, secret_key: impl AsRef<[u8]>) -> Result<String, PaymentError> {
+        generate_signature_for_fields(&self.signed_field_names, |name| self.field(name), secret_key)
+    }
+
+    /// Returns every form field eSewa expects, including the signature
+    ///
+    /// Fails if `total_amount` disagrees with its components.
+    pub fn form_fields(&self, secret_key: impl AsRef<[u8]>) -> Result<Vec<(String, String)>, PaymentError> {
+        // Reject totals that disagree with their components before signing
+        self.check_total()?;
+
+        // Generate signature over the declared field list
+        let signature = self.signature(secret_key)?;
+
+        // Amounts are formatted exactly as they were signed
+        Ok(vec![
+            ("amount".to_string(), self.amount.to_string()),
+            ("failure_url".to_string(), self.failure_url.clone()),
+            ("product_delivery_charge".to_string(), self.product_delivery_charge.to_string()),
+            ("product_service_charge".to_string(), self.product_service_charge.to_string()),
+            ("product_code".to_string(), self.product_code.clone()),
+            ("signature".to_string(), signature),
+            ("signed_field_names".to_string(), self.signed_field_names.clone()),
+            ("success_url".to_string(), self.success_url.clone()),
+            ("tax_amount".to_string(), self.tax_amount.to_string()),
+            ("total_amount".to_string(), self.total_amount.to_string()),
+            ("transaction_uuid".to_string(), self.transaction_uuid.clone()),
+        ])
+    }
+}
+
+/// Builder for [`EsewaPaymentRequest`]
+#[derive(Debug, Clone)]
+pub struct EsewaPaymentRequestBuilder {
+    amount: Npr,
+    tax_amount: Npr,
+    product_service_charge: Npr,
+    product_delivery_charge: Npr,
+    total_amount: Option<Npr>,
+    transaction_uuid: Option<String>,
+    product_code: String,
+    success_url: Option<String>,
+    failure_url: Option<String>,
+    signed_field_names: String,
+}
+
+impl EsewaPaymentRequestBuilder {
+    /// Sets the tax amount (defaults to zero)
+    pub fn tax_amount(mut self, tax_amount: Npr) -> Self {
+        self.tax_amount = tax_amount;
+        self
+    }
+
+    /// Sets the product service charge (defaults to zero)
+    pub fn product_service_charge(mut self, charge: Npr) -> Self {
+        self.product_service_charge = charge;
+        self
+    }
+
+    /// Sets the product delivery charge (defaults to zero)
+    pub fn product_delivery_charge(mut self, charge: Npr) -> Self {
+        self.product_delivery_charge = charge;
+        self
+    }
+
+    /// Sets the expected total; [`build`](Self::build) fails if it disagrees with the components
+    pub fn total_amount(mut self, total_amount: Npr) -> Self {
+        self.total_amount = Some(total_amount);
+        self
+    }
+
+    /// Sets the transaction UUID (defaults to [`generate_transaction_uuid`])
+    pub fn transaction_uuid(mut self, transaction_uuid: impl Into<String>) -> Self {
+        self.transaction_uuid = Some(transaction_uuid.into());
+        self
+    }
+
+    /// Sets the merchant product code (filled in by [`EsewaClient`] when left empty)
+    pub fn product_code(mut self, product_code: impl Into<String>) -> Self {
+        self.product_code = product_code.into();
+        self
+    }
+
+    /// Sets the URL eSewa redirects to after a successful payment
+    pub fn success_url(mut self, url: impl Into<String>) -> Self {
+        self.success_url = Some(url.into());
+        self
+    }
+
+    /// Sets the URL eSewa redirects to after a failed or canceled payment
+    pub fn failure_url(mut self, url: impl Into<String>) -> Self {
+        self.failure_url = Some(url.into());
+        self
+    }
+
+    /// Sets the signed field list (defaults to [`DEFAULT_SIGNED_FIELD_NAMES`])
+    pub fn signed_field_names(mut self, names: impl Into<String>) -> Self {
+        self.signed_field_names = names.into();
+        self
+    }
+
+    /// Builds the request, computing `total_amount` from its components
+    pub fn build(self) -> Result<EsewaPaymentRequest, PaymentError> {
+        let success_url = self
+            .success_url
+            .ok_or_else(|| PaymentError::Config("success_url is required".to_string()))?;
+        let failure_url = self
+            .failure_url
+            .ok_or_else(|| PaymentError::Config("failure_url is required".to_string()))?;
+
+        let mut request = EsewaPaymentRequest {
+            amount: self.amount,
+            tax_amount: self.tax_amount,
+            total_amount: Npr::ZERO,
+            transaction_uuid: self.transaction_uuid.unwrap_or_else(generate_transaction_uuid),
+            product_code: self.product_code,
+            product_service_charge: self.product_service_charge,
+            product_delivery_charge: self.product_delivery_charge,
+            success_url,
+            failure_url,
+            signed_field_names: self.signed_field_names,
+        };
+        request.total_amount = request.computed_total()?;
+
+        if let Some(expected) = self.total_amount {
+            if expected != request.total_amount {
+                return Err(PaymentError::InvalidAmount(format!(
+                    "total_amount {} does not match amount + tax + charges = {}",
+                    expected, request.total_amount
+                )));
+            }
+        }
+
+        Ok(request)
+    }
+}
+
+fn sum_amounts(amounts: &[Npr]) -> Result<Npr, PaymentError> {
+    amounts
+        .iter()
+        .try_fold(Npr::ZERO, |total, amount| total.checked_add(*amount))
+        .ok_or_else(|| PaymentError::InvalidAmount("total amount overflows".to_string()))
+}
+
+impl EsewaPaymentResponse {
+    /// Returns the value of a response field by its eSewa field name
+    pub fn field(&self, name: &str) -> Option<String> {
+        let value = match name {
+            "transaction_code" => self.transaction_code.clone(),
+            "status" => self.status.to_string(),
+            "total_amount" => self.total_amount.to_string(),
+            "transaction_uuid" => self.transaction_uuid.clone(),
+            "product_code" => self.product_code.clone(),
+            "signed_field_names" => self.signed_field_names.clone(),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Generates the signature over this response's `signed_field_names`
+    pub fn compute_signature(&self, secret_key: impl AsRef<[u8]>) -> Result<String, PaymentError> {
+        generate_signature_for_fields(&self.signed_field_names, |name| self.field(name), secret_key)
+    }
+}
+
 /// Represents the validation result including the decoded data and signature validity
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ValidationResult {
@@ -42,50 +297,379 @@ pub struct ValidationResult {
     pub response: EsewaPaymentResponse,
 }
 
-/// Error types for the payment library
-#[derive(Debug)]
-pub enum PaymentError {
-    NetworkError(String),
-    InvalidResponse(String),
-    SignatureError(String),
-    DecodeError(String),
+/// Validation result for a callback checked against a [`SecretKeySet`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeySetValidation {
+    /// The key the callback was signed with; `None` if no accepted key matches
+    pub matched_key: Option<MatchedKey>,
+    pub response: EsewaPaymentResponse,
 }
 
-impl std::fmt::Display for PaymentError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            PaymentError::NetworkError(msg) => write!(f, "Network error: {}", msg),
-            PaymentError::InvalidResponse(msg) => write!(f, "Invalid response: {}", msg),
-            PaymentError::SignatureError(msg) => write!(f, "Signature error: {}", msg),
-            PaymentError::DecodeError(msg) => write!(f, "Decode error: {}", msg),
+impl KeySetValidation {
+    /// Returns `true` if one of the accepted keys signed the callback
+    pub fn signature_valid(&self) -> bool {
+        self.matched_key.is_some()
+    }
+
+    /// Checks the callback strictly against the expected payment
+    ///
+    /// Does what [`verify_esewa_response_with_keys`] does after decoding, for
+    /// callers that read the decoded callback first, e.g. to find the stored
+    /// payment it belongs to.
+    pub fn verify(self, product_code: &str, expected: &ExpectedPayment) -> Result<VerifiedPayment, PaymentError> {
+        verified_payment(self.response, self.matched_key, product_code, expected)
+    }
+}
+
+impl From<KeySetValidation> for ValidationResult {
+    fn from(result: KeySetValidation) -> Self {
+        ValidationResult {
+            signature_valid: result.signature_valid(),
+            response: result.response,
+        }
+    }
+}
+
+/// The order a callback is expected to settle
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExpectedPayment {
+    pub transaction_uuid: String,
+    pub total_amount: Npr,
+}
+
+impl ExpectedPayment {
+    /// Expects a callback for the given transaction and total
+    pub fn new(transaction_uuid: impl Into<String>, total_amount: Npr) -> Self {
+        ExpectedPayment {
+            transaction_uuid: transaction_uuid.into(),
+            total_amount,
         }
     }
 }
 
-impl std::error::Error for PaymentError {}
+impl From<&EsewaPaymentRequest> for ExpectedPayment {
+    fn from(request: &EsewaPaymentRequest) -> Self {
+        ExpectedPayment::new(request.transaction_uuid.clone(), request.total_amount)
+    }
+}
+
+/// An eSewa callback that passed every check of [`verify_esewa_response`]
+///
+/// Can only be obtained through verification.
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifiedPayment {
+    response: EsewaPaymentResponse,
+    matched_key: MatchedKey,
+}
+
+impl VerifiedPayment {
+    /// eSewa transaction code
+    pub fn transaction_code(&self) -> &str {
+        &self.response.transaction_code
+    }
+
+    /// Payment status reported in the callback
+    pub fn status(&self) -> &EsewaStatus {
+        &self.response.status
+    }
+
+    /// Verified total amount
+    pub fn total_amount(&self) -> Npr {
+        self.response.total_amount
+    }
+
+    /// Verified transaction UUID
+    pub fn transaction_uuid(&self) -> &str {
+        &self.response.transaction_uuid
+    }
+
+    /// Verified merchant product code
+    pub fn product_code(&self) -> &str {
+        &self.response.product_code
+    }
+
+    /// The secret key the callback was signed with
+    pub fn matched_key(&self) -> MatchedKey {
+        self.matched_key
+    }
+
+    /// The full decoded callback
+    pub fn response(&self) -> &EsewaPaymentResponse {
+        &self.response
+    }
+
+    /// Consumes the verified payment, returning the decoded callback
+    pub fn into_response(self) -> EsewaPaymentResponse {
+        self.response
+    }
+}
+
+/// Environment variable selecting the eSewa environment
+pub const ENV_ENVIRONMENT: &str = "ESEWA_ENVIRONMENT";
+/// Environment variable overriding the eSewa base URL
+pub const ENV_BASE_URL: &str = "ESEWA_BASE_URL";
+/// Environment variable holding the merchant product code
+pub const ENV_PRODUCT_CODE: &str = "ESEWA_PRODUCT_CODE";
+/// Environment variable holding the merchant secret key
+pub const ENV_SECRET_KEY: &str = "ESEWA_SECRET_KEY";
+/// Environment variable holding the request timeout in seconds
+pub const ENV_TIMEOUT_SECS: &str = "ESEWA_TIMEOUT_SECS";
 
 /// Which eSewa environment to use for requests
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum EsewaEnvironment {
     /// Use the eSewa RC / sandbox endpoint (testing)
     Sandbox,
     /// Use the production eSewa endpoint (real-world)
     Production,
+    /// Use another host exposing eSewa's API paths, e.g. a local stand-in
+    /// server in CI, an egress proxy, or a new eSewa host
+    Custom { base_url: String },
+}
+
+impl EsewaEnvironment {
+    /// URL of the ePay v2 payment form endpoint
+    pub fn form_url(&self) -> String {
+        match self {
+            EsewaEnvironment::Sandbox => "https://rc-epay.esewa.com.np/api/epay/main/v2/form".to_string(),
+            EsewaEnvironment::Production => "https://epay.esewa.com.np/api/epay/main/v2/form".to_string(),
+            EsewaEnvironment::Custom { base_url } => {
+                format!("{}/api/epay/main/v2/form", base_url.trim_end_matches('/'))
+            }
+        }
+    }
+
+    /// URL of the ePay transaction status endpoint
+    pub fn status_url(&self) -> String {
+        match self {
+            EsewaEnvironment::Sandbox => "https://rc.esewa.com.np/api/epay/transaction/status/".to_string(),
+            EsewaEnvironment::Production => "https://epay.esewa.com.np/api/epay/transaction/status/".to_string(),
+            EsewaEnvironment::Custom { base_url } => {
+                format!("{}/api/epay/transaction/status/", base_url.trim_end_matches('/'))
+            }
+        }
+    }
+
+    /// Reads the environment from `ESEWA_BASE_URL` or `ESEWA_ENVIRONMENT`
+    ///
+    /// `ESEWA_BASE_URL` takes precedence and selects [`EsewaEnvironment::Custom`].
+    /// Otherwise `ESEWA_ENVIRONMENT` is parsed with [`str::parse`]; when
+    /// neither is set the sandbox is used.
+    pub fn from_env() -> Result<Self, PaymentError> {
+        if let Some(base_url) = read_env(ENV_BASE_URL)? {
+            return Ok(EsewaEnvironment::Custom { base_url });
+        }
+        match read_env(ENV_ENVIRONMENT)? {
+            Some(value) => value.parse(),
+            None => Ok(EsewaEnvironment::Sandbox),
+        }
+    }
+}
+
+impl std::str::FromStr for EsewaEnvironment {
+    type Err = PaymentError;
+
+    /// Parses `sandbox`, `production`, or an `http(s)://` base URL
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let value = s.trim();
+        match value.to_ascii_lowercase().as_str() {
+            "sandbox" | "test" | "rc" => Ok(EsewaEnvironment::Sandbox),
+            "production" | "prod" | "live" => Ok(EsewaEnvironment::Production),
+            lower if lower.starts_with("http://") || lower.starts_with("https://") => {
+                Ok(EsewaEnvironment::Custom {
+                    base_url: value.to_string(),
+                })
+            }
+            _ => Err(PaymentError::Config(format!(
+                "Unknown eSewa environment {:?}; expected sandbox, production or a base URL",
+                value
+            ))),
+        }
+    }
+}
+
+/// Transaction status reported by eSewa
+///
+/// Serialized as eSewa's wire string (`"COMPLETE"`, `"FULL_REFUND"`, ...).
+/// Statuses this crate does not know yet deserialize to [`EsewaStatus::Unknown`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum EsewaStatus {
+    /// Payment completed successfully
+    Complete,
+    /// Payment initiated but not yet completed
+    Pending,
+    /// Payment fully refunded to the customer
+    FullRefund,
+    /// Payment partially refunded to the customer
+    PartialRefund,
+    /// Payment stuck in a halt state; retry the inquiry later
+    Ambiguous,
+    /// Session expired or the transaction was never initiated
+    NotFound,
+    /// Canceled or reversed by eSewa
+    Canceled,
+    /// A status not known to this version of the crate
+    Unknown(String),
+}
+
+impl EsewaStatus {
+    /// The status string as eSewa sends it
+    pub fn as_str(&self) -> &str {
+        match self {
+            EsewaStatus::Complete => "COMPLETE",
+            EsewaStatus::Pending => "PENDING",
+            EsewaStatus::FullRefund => "FULL_REFUND",
+            EsewaStatus::PartialRefund => "PARTIAL_REFUND",
+            EsewaStatus::Ambiguous => "AMBIGUOUS",
+            EsewaStatus::NotFound => "NOT_FOUND",
+            EsewaStatus::Canceled => "CANCELED",
+            EsewaStatus::Unknown(status) => status,
+        }
+    }
+
+    /// Returns `true` if the payment attempt has a final outcome
+    ///
+    /// Only refunds can still change a terminal payment. Pending, ambiguous
+    /// and unknown statuses should be checked again later.
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            EsewaStatus::Complete
+                | EsewaStatus::FullRefund
+                | EsewaStatus::PartialRefund
+                | EsewaStatus::NotFound
+                | EsewaStatus::Canceled
+        )
+    }
+
+    /// Returns `true` if the customer has paid
+    pub fn is_success(&self) -> bool {
+        matches!(self, EsewaStatus::Complete)
+    }
+}
+
+impl From<&str> for EsewaStatus {
+    fn from(status: &str) -> Self {
+        match status {
+            "COMPLETE" => EsewaStatus::Complete,
+            "PENDING" => EsewaStatus::Pending,
+            "FULL_REFUND" => EsewaStatus::FullRefund,
+            "PARTIAL_REFUND" => EsewaStatus::PartialRefund,
+            "AMBIGUOUS" => EsewaStatus::Ambiguous,
+            "NOT_FOUND" => EsewaStatus::NotFound,
+            "CANCELED" => EsewaStatus::Canceled,
+            other => EsewaStatus::Unknown(other.to_string()),
+        }
+    }
+}
+
+impl From<&EsewaStatus> for PaymentStatus {
+    fn from(status: &EsewaStatus) -> Self {
+        match status {
+            EsewaStatus::Complete => PaymentStatus::Completed,
+            EsewaStatus::Pending => PaymentStatus::Pending,
+            EsewaStatus::FullRefund => PaymentStatus::Refunded,
+            EsewaStatus::PartialRefund => PaymentStatus::PartiallyRefunded,
+            EsewaStatus::Ambiguous | EsewaStatus::Unknown(_) => PaymentStatus::Ambiguous,
+            EsewaStatus::NotFound => PaymentStatus::Failed,
+            EsewaStatus::Canceled => PaymentStatus::Canceled,
+        }
+    }
+}
+
+impl std::fmt::Display for EsewaStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for EsewaStatus {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for EsewaStatus {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let status = String::deserialize(deserializer)?;
+        Ok(EsewaStatus::from(status.as_str()))
+    }
+}
+
+/// Response of the eSewa transaction status check API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EsewaStatusResponse {
+    pub product_code: String,
+    pub transaction_uuid: String,
+    pub total_amount: Npr,
+    pub status: EsewaStatus,
+    /// eSewa reference ID, present once the payment is complete
+    #[serde(default)]
+    pub ref_id: Option<String>,
 }
 
 /// Generates an HMAC-SHA256 signature for eSewa payment
+///
+/// Signs the default field list `total_amount,transaction_uuid,product_code`.
+/// Use [`generate_signature_for_fields`] when a different `signed_field_names`
+/// list is declared.
 pub fn generate_signature(
     total_amount: &str,
     transaction_uuid: &str,
     product_code: &str,
-    secret_key: &str,
+    secret_key: impl AsRef<[u8]>,
 ) -> String {
     let data = format!(
         "total_amount={},transaction_uuid={},product_code={}",
         total_amount, transaction_uuid, product_code
     );
 
-    let mut mac = Hmac::<Sha256>::new_from_slice(secret_key.as_bytes())
+    sign_message(&data, secret_key.as_ref())
+}
+
+/// Generates an HMAC-SHA256 signature over the fields listed in `signed_field_names`
+///
+/// The signed message is built as `name=value` pairs joined by `,`, in the
+/// order the names are declared. `value_of` is called once per declared name
+/// and must return the field's value exactly as it was sent or received.
+pub fn generate_signature_for_fields<F>(
+    signed_field_names: &str,
+    value_of: F,
+    secret_key: impl AsRef<[u8]>,
+) -> Result<String, PaymentError>
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    let data = build_signature_message(signed_field_names, value_of)?;
+    Ok(sign_message(&data, secret_key.as_ref()))
+}
+
+/// Builds the `name=value,...` message eSewa signs for the declared field list
+pub fn build_signature_message<F>(
+    signed_field_names: &str,
+    mut value_of: F,
+) -> Result<String, PaymentError>
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    let mut parts = Vec::new();
+    for name in signed_field_names.split(',').map(str::trim) {
+        if name.is_empty() {
+            return Err(PaymentError::Signature(format!(
+                "Empty field name in signed_field_names: {:?}",
+                signed_field_names
+            )));
+        }
+        let value = value_of(name).ok_or_else(|| {
+            PaymentError::Signature(format!("Signed field `{}` is missing", name))
+        })?;
+        parts.push(format!("{}={}", name, value));
+    }
+    Ok(parts.join(","))
+}
+
+fn sign_message(data: &str, secret_key: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret_key)
         .expect("HMAC can take key of any size");
     mac.update(data.as_bytes());
     let result = mac.finalize();
@@ -94,99 +678,592 @@ pub fn generate_signature(
 }
 
 /// Initiates a payment with eSewa and returns the redirect URL
+///
+/// The form is posted server-side, so the customer's browser session is not
+/// carried over; [`build_checkout_form`] is the more robust integration.
+/// Builds a new HTTP client on every call; prefer [`EsewaClient`] when making
+/// more than one request. Only connection failures are retried, with
+/// [`RetryPolicy::default`].
 pub async fn pay_with_esewa(
     request: EsewaPaymentRequest,
-    secret_key: &str,
+    secret_key: impl AsRef<[u8]>,
     env: EsewaEnvironment,
 ) -> Result<String, PaymentError> {
-    // Generate signature
-    let signature = generate_signature(
-        &request.total_amount,
-        &request.transaction_uuid,
-        &request.product_code,
-        secret_key,
-    );
+    post_payment_form(
+        &Client::new(),
+        &RetryPolicy::default(),
+        &env.form_url(),
+        &request,
+        secret_key.as_ref(),
+    )
+    .await
+}
 
-    // Build form parameters
-    let params = [
-        ("amount", request.amount.as_str()),
-        ("failure_url", request.failure_url.as_str()),
-        ("product_delivery_charge", request.product_delivery_charge.as_str()),
-        ("product_service_charge", request.product_service_charge.as_str()),
-        ("product_code", request.product_code.as_str()),
-        ("signature", &signature),
-        ("signed_field_names", request.signed_field_names.as_str()),
-        ("success_url", request.success_url.as_str()),
-        ("tax_amount", request.tax_amount.as_str()),
-        ("total_amount", request.total_amount.as_str()),
-        ("transaction_uuid", request.transaction_uuid.as_str()),
-    ];
-
-    // Send POST request
-    let client = Client::new();
-    // Choose endpoint based on environment
-    let url = match env {
-        EsewaEnvironment::Sandbox => "https://rc-epay.esewa.com.np/api/epay/main/v2/form",
-        EsewaEnvironment::Production => "https://epay.esewa.com.np/api/epay/main/v2/form",
-    };
+/// Builds the signed checkout form for the customer's browser to submit
+///
+/// This is eSewa's documented integration path: return
+/// [`CheckoutForm::to_html`] from your web handler and the browser posts the
+/// form to eSewa, keeping the customer's session intact.
+pub fn build_checkout_form(
+    request: &EsewaPaymentRequest,
+    secret_key: impl AsRef<[u8]>,
+    env: EsewaEnvironment,
+) -> Result<CheckoutForm, PaymentError> {
+    Ok(CheckoutForm {
+        action: env.form_url(),
+        fields: request.form_fields(secret_key)?,
+    })
+}
+
+/// Asks eSewa for the current status of a transaction
+///
+/// Useful when the customer never reached the success URL, e.g. because the
+/// browser was closed after paying. Failures are retried with
+/// [`RetryPolicy::default`].
+pub async fn check_transaction_status(
+    product_code: &str,
+    total_amount: Npr,
+    transaction_uuid: &str,
+    env: EsewaEnvironment,
+) -> Result<EsewaStatusResponse, PaymentError> {
+    fetch_transaction_status(
+        &Client::new(),
+        &RetryPolicy::default(),
+        &env.status_url(),
+        product_code,
+        total_amount,
+        transaction_uuid,
+    )
+    .await
+}
+
+async fn post_payment_form(
+    client: &Client,
+    retry: &RetryPolicy,
+    url: &str,
+    request: &EsewaPaymentRequest,
+    secret_key: &[u8],
+) -> Result<String, PaymentError> {
+    let params = request.form_fields(secret_key)?;
+
+    // A payment must not be created twice, so only unsent requests are retried
+    retry
+        .run(Idempotency::NotIdempotent, || async {
+            let response = client.post(url).form(&params).send().await?;
 
-    let response = client
-        .post(url)
-        .form(&params)
-        .send()
+            let status = response.status();
+            let final_url = response.url().to_string();
+
+            if status.as_u16() == 200 {
+                Ok(final_url)
+            } else {
+                Err(PaymentError::Http {
+                    status: status.as_u16(),
+                    body: response.text().await.unwrap_or_default(),
+                })
+            }
+        })
         .await
-        .map_err(|e| PaymentError::NetworkError(e.to_string()))?;
+}
 
-    let status = response.status();
-    let final_url = response.url().to_string();
+async fn fetch_transaction_status(
+    client: &Client,
+    retry: &RetryPolicy,
+    url: &str,
+    product_code: &str,
+    total_amount: Npr,
+    transaction_uuid: &str,
+) -> Result<EsewaStatusResponse, PaymentError> {
+    let request = client.get(url).query(&[
+        ("product_code", product_code),
+        ("total_amount", &total_amount.to_string()),
+        ("transaction_uuid", transaction_uuid),
+    ]);
 
-    if status.as_u16() == 200 {
-        Ok(final_url)
-    } else {
-        Err(PaymentError::InvalidResponse(format!(
-            "Expected status 200, got {}",
-            status
-        )))
-    }
+    send_json(retry, Idempotency::Idempotent, request).await
 }
 
 /// Validates and decodes eSewa payment response
+///
+/// The signature is only reported valid when it covers every field in
+/// [`CALLBACK_SIGNED_FIELDS`]; otherwise a checkout form signature could be
+/// replayed as a callback with a forged status.
 pub fn validate_esewa_response(
     encoded_data: &str,
-    secret_key: &str,
+    secret_key: impl AsRef<[u8]>,
 ) -> Result<ValidationResult, PaymentError> {
+    let (response, message) = decode_callback(encoded_data)?;
+    let signature_valid = match message {
+        Some(message) => verify_signature(&message, &response.signature, secret_key.as_ref())?,
+        None => false,
+    };
+
+    Ok(ValidationResult {
+        signature_valid,
+        response,
+    })
+}
+
+/// Validates and decodes eSewa payment response against a rotating key set
+///
+/// Like [`validate_esewa_response`], but the signature may come from any
+/// unexpired key of `keys`; [`KeySetValidation::matched_key`] reports which.
+pub fn validate_esewa_response_with_keys(
+    encoded_data: &str,
+    keys: &SecretKeySet,
+) -> Result<KeySetValidation, PaymentError> {
+    let (response, message) = decode_callback(encoded_data)?;
+
+    let mut matched_key = None;
+    if let Some(message) = message {
+        for (rank, key) in keys.active_keys(SystemTime::now()) {
+            if verify_signature(&message, &response.signature, key.as_ref())? {
+                matched_key = Some(rank);
+                break;
+            }
+        }
+    }
+
+    Ok(KeySetValidation {
+        matched_key,
+        response,
+    })
+}
+
+/// Decodes a callback's `data` parameter into the response and its signed message
+///
+/// The message is `None` when the callback does not sign all of
+/// [`CALLBACK_SIGNED_FIELDS`], so no key can verify it.
+fn decode_callback(
+    encoded_data: &str,
+) -> Result<(EsewaPaymentResponse, Option<String>), PaymentError> {
     // Decode base64
     let decoded_bytes = general_purpose::STANDARD
         .decode(encoded_data)
-        .map_err(|e| PaymentError::DecodeError(format!("Base64 decode failed: {}", e)))?;
+        .map_err(|e| PaymentError::decode("Base64 decode failed", e))?;
 
     let decoded_str = String::from_utf8(decoded_bytes)
-        .map_err(|e| PaymentError::DecodeError(format!("UTF-8 decode failed: {}", e)))?;
-
-    // Parse JSON
-    let response: EsewaPaymentResponse = serde_json::from_str(&decoded_str)
-        .map_err(|e| PaymentError::DecodeError(format!("JSON parse failed: {}", e)))?;
-
-    // Verify signature
-    let computed_signature = generate_signature(
-        &response.total_amount,
-        &response.transaction_uuid,
-        &response.product_code,
-        secret_key,
-    );
+        .map_err(|e| PaymentError::decode("UTF-8 decode failed", e))?;
 
-    let signature_valid = computed_signature == response.signature;
+    // Parse JSON, keeping the raw object so signed fields are read as received
+    let raw: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&decoded_str)
+        .map_err(|e| PaymentError::decode("JSON parse failed", e))?;
+    let response: EsewaPaymentResponse =
+        serde_json::from_value(serde_json::Value::Object(raw.clone()))
+            .map_err(|e| PaymentError::decode("JSON parse failed", e))?;
 
-    Ok(ValidationResult {
-        signature_valid,
+    // The signature covers the fields the callback declares as signed
+    let message = build_signature_message(&response.signed_field_names, |name| {
+        raw.get(name).and_then(json_field_value)
+    })?;
+
+    if !covers_callback_fields(&response.signed_field_names) {
+        return Ok((response, None));
+    }
+
+    Ok((response, Some(message)))
+}
+
+/// Whether `signed_field_names` lists every field a callback must sign
+fn covers_callback_fields(signed_field_names: &str) -> bool {
+    let signed: Vec<&str> = signed_field_names.split(',').map(str::trim).collect();
+    CALLBACK_SIGNED_FIELDS
+        .iter()
+        .all(|field| signed.contains(field))
+}
+
+/// Verifies an eSewa callback and fails closed
+///
+/// Unlike [`validate_esewa_response`], a callback is only accepted when its
+/// signature is valid, it was issued for `product_code`, and it matches the
+/// `expected` order. A failed check is reported as its own variant, e.g.
+/// [`PaymentError::AmountMismatch`]. The payment status is not checked;
+/// inspect [`VerifiedPayment::status`] before fulfilling the order.
+pub fn verify_esewa_response(
+    encoded_data: &str,
+    secret_key: impl AsRef<[u8]>,
+    product_code: &str,
+    expected: &ExpectedPayment,
+) -> Result<VerifiedPayment, PaymentError> {
+    let result = validate_esewa_response(encoded_data, secret_key)?;
+    let matched_key = result.signature_valid.then_some(MatchedKey::Current);
+    verified_payment(result.response, matched_key, product_code, expected)
+}
+
+/// Verifies an eSewa callback against a rotating key set and fails closed
+///
+/// Like [`verify_esewa_response`], but the signature may come from any
+/// unexpired key of `keys`; [`VerifiedPayment::matched_key`] reports which.
+pub fn verify_esewa_response_with_keys(
+    encoded_data: &str,
+    keys: &SecretKeySet,
+    product_code: &str,
+    expected: &ExpectedPayment,
+) -> Result<VerifiedPayment, PaymentError> {
+    validate_esewa_response_with_keys(encoded_data, keys)?.verify(product_code, expected)
+}
+
+fn verified_payment(
+    response: EsewaPaymentResponse,
+    matched_key: Option<MatchedKey>,
+    product_code: &str,
+    expected: &ExpectedPayment,
+) -> Result<VerifiedPayment, PaymentError> {
+    let Some(matched_key) = matched_key else {
+        return Err(PaymentError::SignatureMismatch);
+    };
+    // A key only vouches for the fields it signed, which must include the status
+    if !covers_callback_fields(&response.signed_field_names) {
+        return Err(PaymentError::SignatureMismatch);
+    }
+    if response.product_code != product_code {
+        return Err(PaymentError::ProductCodeMismatch {
+            expected: product_code.to_string(),
+            actual: response.product_code,
+        });
+    }
+    if response.transaction_uuid != expected.transaction_uuid {
+        return Err(PaymentError::TransactionUuidMismatch {
+            expected: expected.transaction_uuid.clone(),
+            actual: response.transaction_uuid,
+        });
+    }
+    if response.total_amount != expected.total_amount {
+        return Err(PaymentError::AmountMismatch {
+            expected: expected.total_amount,
+            actual: response.total_amount,
+        });
+    }
+
+    Ok(VerifiedPayment {
         response,
+        matched_key,
     })
 }
 
+/// Checks a base64 signature over `message` in constant time
+///
+/// Returns [`PaymentError::MalformedSignature`] if the signature is not valid
+/// base64, and `Ok(false)` if it is well-formed but does not match.
+fn verify_signature(message: &str, signature: &str, secret_key: &[u8]) -> Result<bool, PaymentError> {
+    let signature_bytes = general_purpose::STANDARD
+        .decode(signature)
+        .map_err(|e| PaymentError::MalformedSignature(e.into()))?;
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret_key)
+        .expect("HMAC can take key of any size");
+    mac.update(message.as_bytes());
+    Ok(mac.verify_slice(&signature_bytes).is_ok())
+}
+
+/// Renders a decoded JSON value the way it appears in the signed message
+fn json_field_value(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(s) => Some(s.clone()),
+        serde_json::Value::Number(n) => Some(n.to_string()),
+        serde_json::Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// Reusable eSewa client holding the merchant configuration and a pooled HTTP client
+///
+/// Create one per process with [`EsewaClient::builder`] and share it; cloning is cheap.
+#[derive(Clone)]
+pub struct EsewaClient {
+    product_code: String,
+    secret_keys: SecretKeySet,
+    environment: EsewaEnvironment,
+    http: Client,
+    retry: RetryPolicy,
+}
+
+impl std::fmt::Debug for EsewaClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EsewaClient")
+            .field("product_code", &self.product_code)
+            .field("environment", &self.environment)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EsewaClient {
+    /// Starts building a client for the given merchant credentials
+    pub fn builder(
+        product_code: impl Into<String>,
+        secret_key: impl Into<MerchantSecret>,
+    ) -> EsewaClientBuilder {
+        EsewaClientBuilder {
+            product_code: product_code.into(),
+            secret_keys: SecretKeySet::new(secret_key),
+            environment: EsewaEnvironment::Sandbox,
+            timeout: DEFAULT_TIMEOUT,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            user_agent: None,
+            http_client: None,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Merchant product code used for every request
+    pub fn product_code(&self) -> &str {
+        &self.product_code
+    }
+
+    /// Environment this client talks to
+    pub fn environment(&self) -> &EsewaEnvironment {
+        &self.environment
+    }
+
+    pub(crate) fn secret_keys(&self) -> &SecretKeySet {
+        &self.secret_keys
+    }
+
+    /// Generates the signature for a request with this client's current secret key
+    pub fn sign(&self, request: &EsewaPaymentRequest) -> Result<String, PaymentError> {
+        request.signature(self.secret_keys.current())
+    }
+
+    /// Builds the signed checkout form for the customer's browser to submit
+    ///
+    /// The request's `product_code` is replaced with the client's.
+    pub fn checkout_form(
+        &self,
+        mut request: EsewaPaymentRequest,
+    ) -> Result<CheckoutForm, PaymentError> {
+        request.product_code = self.product_code.clone();
+        build_checkout_form(&request, self.secret_keys.current(), self.environment.clone())
+    }
+
+    /// Initiates a payment server-side and returns the redirect URL
+    ///
+    /// The request's `product_code` is replaced with the client's.
+    pub async fn initiate_payment(
+        &self,
+        mut request: EsewaPaymentRequest,
+    ) -> Result<String, PaymentError> {
+        request.product_code = self.product_code.clone();
+        post_payment_form(
+            &self.http,
+            &self.retry,
+            &self.environment.form_url(),
+            &request,
+            self.secret_keys.current().as_ref(),
+        )
+        .await
+    }
+
+    /// Validates and decodes the `data` parameter of an eSewa callback
+    ///
+    /// The signature may come from the current or any unexpired previous key.
+    pub fn validate_response(&self, encoded_data: &str) -> Result<ValidationResult, PaymentError> {
+        validate_esewa_response_with_keys(encoded_data, &self.secret_keys).map(Into::into)
+    }
+
+    /// Verifies a callback against the expected order and this client's product code
+    pub fn verify_callback(
+        &self,
+        encoded_data: &str,
+        expected: &ExpectedPayment,
+    ) -> Result<VerifiedPayment, PaymentError> {
+        verify_esewa_response_with_keys(
+            encoded_data,
+            &self.secret_keys,
+            &self.product_code,
+            expected,
+        )
+    }
+
+    /// Asks eSewa for the current status of a transaction
+    pub async fn check_status(
+        &self,
+        total_amount: Npr,
+        transaction_uuid: &str,
+    ) -> Result<EsewaStatusResponse, PaymentError> {
+        fetch_transaction_status(
+            &self.http,
+            &self.retry,
+            &self.environment.status_url(),
+            &self.product_code,
+            total_amount,
+            transaction_uuid,
+        )
+        .await
+    }
+}
+
+/// eSewa as a [`PaymentGateway`]
+///
+/// Initiation returns a [`CheckoutForm`]; callbacks are verified strictly
+/// from their `data` parameter. eSewa has no merchant refund API.
+#[async_trait]
+impl PaymentGateway for EsewaClient {
+    fn name(&self) -> &'static str {
+        "esewa"
+    }
+
+    async fn initiate(&self, intent: &PaymentIntent) -> Result<Initiation, PaymentError> {
+        let request = EsewaPaymentRequest::builder(intent.amount)
+            .transaction_uuid(intent.transaction_id.clone())
+            .success_url(intent.success_url.clone())
+            .failure_url(intent.failure_url.clone())
+            .build()?;
+        Ok(Initiation {
+            action: CustomerAction::Form(self.checkout_form(request)?),
+            gateway_reference: None,
+        })
+    }
+
+    async fn verify_callback(
+        &self,
+        params: &CallbackParams,
+        intent: &PaymentIntent,
+    ) -> Result<PaymentOutcome, PaymentError> {
+        let data = required_param(params, "data")?;
+        let expected = ExpectedPayment::new(intent.transaction_id.clone(), intent.amount);
+        let verified = EsewaClient::verify_callback(self, data, &expected)?;
+
+        Ok(PaymentOutcome {
+            gateway: self.name().to_string(),
+            transaction_id: verified.transaction_uuid().to_string(),
+            gateway_reference: Some(verified.transaction_code().to_string()),
+            amount: Some(verified.total_amount()),
+            status: PaymentStatus::from(verified.status()),
+        })
+    }
+
+    async fn lookup_status(&self, intent: &PaymentIntent) -> Result<PaymentOutcome, PaymentError> {
+        let response = self.check_status(intent.amount, &intent.transaction_id).await?;
+
+        Ok(PaymentOutcome {
+            gateway: self.name().to_string(),
+            transaction_id: response.transaction_uuid,
+            gateway_reference: response.ref_id,
+            amount: Some(response.total_amount),
+            status: PaymentStatus::from(&response.status),
+        })
+    }
+}
+
+/// Builder for [`EsewaClient`]
+#[derive(Debug)]
+pub struct EsewaClientBuilder {
+    product_code: String,
+    secret_keys: SecretKeySet,
+    environment: EsewaEnvironment,
+    timeout: Duration,
+    connect_timeout: Duration,
+    user_agent: Option<String>,
+    http_client: Option<Client>,
+    retry: RetryPolicy,
+}
+
+impl EsewaClientBuilder {
+    /// Starts a builder from environment variables
+    ///
+    /// Reads `ESEWA_PRODUCT_CODE` and `ESEWA_SECRET_KEY` (both required), the
+    /// environment as described in [`EsewaEnvironment::from_env`], and an
+    /// optional `ESEWA_TIMEOUT_SECS`. Further builder calls override these values.
+    pub fn from_env() -> Result<Self, PaymentError> {
+        let product_code = read_env(ENV_PRODUCT_CODE)?
+            .ok_or_else(|| PaymentError::Config(format!("{} is not set", ENV_PRODUCT_CODE)))?;
+        let secret_key = MerchantSecret::from_env(ENV_SECRET_KEY)?;
+
+        let mut builder = EsewaClient::builder(product_code, secret_key)
+            .environment(EsewaEnvironment::from_env()?);
+
+        if let Some(secs) = read_env(ENV_TIMEOUT_SECS)? {
+            let secs: u64 = secs.trim().parse().map_err(|_| {
+                PaymentError::Config(format!("{} must be a whole number of seconds", ENV_TIMEOUT_SECS))
+            })?;
+            builder = builder.timeout(Duration::from_secs(secs));
+        }
+
+        Ok(builder)
+    }
+
+    /// Sets the eSewa environment (defaults to [`EsewaEnvironment::Sandbox`])
+    pub fn environment(mut self, environment: EsewaEnvironment) -> Self {
+        self.environment = environment;
+        self
+    }
+
+    /// Keeps accepting callbacks signed with a previous secret key until `expires_at`
+    ///
+    /// Requests are always signed with the key passed to [`EsewaClient::builder`].
+    pub fn previous_key(
+        mut self,
+        secret_key: impl Into<MerchantSecret>,
+        expires_at: SystemTime,
+    ) -> Self {
+        self.secret_keys = self.secret_keys.with_previous(secret_key, expires_at);
+        self
+    }
+
+    /// Replaces the secret keys, e.g. with a set loaded from a secret manager
+    pub fn secret_keys(mut self, keys: SecretKeySet) -> Self {
+        self.secret_keys = keys;
+        self
+    }
+
+    /// Sets the total timeout for each request (defaults to 30 seconds)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the connection timeout (defaults to 10 seconds)
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Sets the `User-Agent` header sent to eSewa
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Uses a preconfigured HTTP client; timeouts and user agent are then ignored
+    pub fn http_client(mut self, client: Client) -> Self {
+        self.http_client = Some(client);
+        self
+    }
+
+    /// Sets how failed requests are retried (defaults to [`RetryPolicy::default`])
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Builds the client
+    pub fn build(self) -> Result<EsewaClient, PaymentError> {
+        if self.product_code.is_empty() {
+            return Err(PaymentError::Config("product code is empty".to_string()));
+        }
+        if self.secret_keys.current().is_empty() {
+            return Err(PaymentError::Config("secret key is empty".to_string()));
+        }
+
+        let http = match self.http_client {
+            Some(client) => client,
+            None => build_client(self.timeout, self.connect_timeout, self.user_agent)?,
+        };
+
+        Ok(EsewaClient {
+            product_code: self.product_code,
+            secret_keys: self.secret_keys,
+            environment: self.environment,
+            http,
+            retry: self.retry,
+        })
+    }
+}
+
 /// Generates a transaction UUID in the format: `id-<milliseconds>-<random>`
 pub fn generate_transaction_uuid() -> String {
     use rand::Rng;
-    use std::time::{SystemTime, UNIX_EPOCH};
+    use std::time::UNIX_EPOCH;
 
     let now_ms = SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -205,8 +1282,55 @@ pub fn generate_transaction_uuid() -> String {
     format!("id-{}-{}", now_ms, rand_part)
 }
 
+/// Signed eSewa callbacks for tests in this and other modules
+#[cfg(test)]
+pub(crate) mod test_callbacks {
+    use super::*;
+
+    /// eSewa's sandbox secret key, which the callbacks are signed with
+    pub(crate) const SECRET: &str = "8gBm/:&EnhH.1/q";
+
+    /// A complete NPR 110 payment for `id-123-abc` on `EPAYTEST`, changed by
+    /// `edit` and then signed over all its fields
+    pub(crate) fn signed_response(edit: impl FnOnce(&mut EsewaPaymentResponse)) -> EsewaPaymentResponse {
+        let mut response = EsewaPaymentResponse {
+            transaction_code: "000D13A".to_string(),
+            status: EsewaStatus::Complete,
+            total_amount: Npr::from_rupees(110),
+            transaction_uuid: "id-123-abc".to_string(),
+            product_code: "EPAYTEST".to_string(),
+            signed_field_names: "transaction_code,status,total_amount,transaction_uuid,product_code,signed_field_names".to_string(),
+            signature: String::new(),
+        };
+        edit(&mut response);
+        response.signature = response.compute_signature(SECRET).unwrap();
+        response
+    }
+
+    /// Encodes `response` as the `data` parameter eSewa redirects with
+    pub(crate) fn encode(response: &EsewaPaymentResponse) -> String {
+        general_purpose::STANDARD.encode(serde_json::to_string(response).unwrap())
+    }
+
+    /// [`signed_response`] encoded as the `data` parameter
+    pub(crate) fn signed_callback(edit: impl FnOnce(&mut EsewaPaymentResponse)) -> String {
+        encode(&signed_response(edit))
+    }
+
+    /// A complete callback for the forged transaction code `FAKE` that reuses
+    /// the checkout form signature over [`DEFAULT_SIGNED_FIELD_NAMES`]
+    pub(crate) fn replayed_form_signature() -> EsewaPaymentResponse {
+        let mut forged = signed_response(|_| {});
+        forged.transaction_code = "FAKE".to_string();
+        forged.signed_field_names = DEFAULT_SIGNED_FIELD_NAMES.to_string();
+        forged.signature = generate_signature("110", "id-123-abc", "EPAYTEST", SECRET);
+        forged
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::test_callbacks::{self, signed_callback};
     use super::*;
 
     #[test]
@@ -235,63 +1359,399 @@ mod tests {
 
     #[test]
     fn test_validate_esewa_response_valid() {
-        // Create a test response
-        let test_data = EsewaPaymentResponse {
-            transaction_code: "000D13A".to_string(),
-            status: "COMPLETE".to_string(),
-            total_amount: "110.0".to_string(),
-            transaction_uuid: "id-123-abc".to_string(),
-            product_code: "EPAYTEST".to_string(),
-            signed_field_names: "transaction_code,status,total_amount,transaction_uuid,product_code,signed_field_names".to_string(),
-            signature: generate_signature("110.0", "id-123-abc", "EPAYTEST", "8gBm/:&EnhH.1/q"),
-        };
-
-        let json_str = serde_json::to_string(&test_data).unwrap();
-        let encoded = general_purpose::STANDARD.encode(json_str.as_bytes());
+        let encoded = signed_callback(|_| {});
 
         let result = validate_esewa_response(&encoded, "8gBm/:&EnhH.1/q").unwrap();
         
         assert!(result.signature_valid);
-        assert_eq!(result.response.status, "COMPLETE");
+        assert_eq!(result.response.status, EsewaStatus::Complete);
         assert_eq!(result.response.transaction_code, "000D13A");
     }
 
     #[test]
     fn test_validate_esewa_response_invalid_signature() {
-        let test_data = EsewaPaymentResponse {
-            transaction_code: "000D13A".to_string(),
-            status: "COMPLETE".to_string(),
-            total_amount: "110.0".to_string(),
-            transaction_uuid: "id-123-abc".to_string(),
-            product_code: "EPAYTEST".to_string(),
-            signed_field_names: "transaction_code,status,total_amount,transaction_uuid,product_code,signed_field_names".to_string(),
-            signature: "invalid_signature".to_string(),
-        };
-
-        let json_str = serde_json::to_string(&test_data).unwrap();
-        let encoded = general_purpose::STANDARD.encode(json_str.as_bytes());
+        let mut response = test_callbacks::signed_response(|_| {});
+        response.signature = sign_message("some other message", b"8gBm/:&EnhH.1/q");
+        let encoded = test_callbacks::encode(&response);
 
         let result = validate_esewa_response(&encoded, "8gBm/:&EnhH.1/q").unwrap();
         
         assert!(!result.signature_valid);
     }
 
+    #[test]
+    fn test_validate_esewa_response_malformed_signature() {
+        let mut response = test_callbacks::signed_response(|_| {});
+        response.signature = "invalid_signature".to_string();
+        let encoded = test_callbacks::encode(&response);
+
+        let result = validate_esewa_response(&encoded, "8gBm/:&EnhH.1/q");
+
+        assert!(matches!(result, Err(PaymentError::MalformedSignature(_))));
+    }
+
     #[test]
     fn test_validate_esewa_response_invalid_base64() {
         let result = validate_esewa_response("not-valid-base64!!!", "8gBm/:&EnhH.1/q");
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_validate_esewa_response_tampered_status() {
+        let mut response = test_callbacks::signed_response(|response| response.status = EsewaStatus::Pending);
+        response.status = EsewaStatus::Complete;
+        let encoded = test_callbacks::encode(&response);
+
+        let result = validate_esewa_response(&encoded, "8gBm/:&EnhH.1/q").unwrap();
+
+        assert!(!result.signature_valid, "Flipping status must break the signature");
+    }
+
+    #[test]
+    fn test_build_signature_message_uses_raw_json_values() {
+        let raw: serde_json::Map<String, serde_json::Value> = serde_json::from_str(
+            r#"{"status":"COMPLETE","total_amount":1000.0,"signed_field_names":"status,total_amount"}"#,
+        )
+        .unwrap();
+
+        let message = build_signature_message("status,total_amount", |name| {
+            raw.get(name).and_then(json_field_value)
+        })
+        .unwrap();
+
+        assert_eq!(message, "status=COMPLETE,total_amount=1000.0");
+    }
+
+    #[test]
+    fn test_generate_signature_for_fields_matches_default() {
+        let fields = generate_signature_for_fields(
+            "total_amount,transaction_uuid,product_code",
+            |name| match name {
+                "total_amount" => Some("110".to_string()),
+                "transaction_uuid" => Some("id-123".to_string()),
+                "product_code" => Some("EPAYTEST".to_string()),
+                _ => None,
+            },
+            "8gBm/:&EnhH.1/q",
+        )
+        .unwrap();
+
+        assert_eq!(fields, generate_signature("110", "id-123", "EPAYTEST", "8gBm/:&EnhH.1/q"));
+    }
+
+    #[test]
+    fn test_generate_signature_for_fields_missing_field() {
+        let result = generate_signature_for_fields("total_amount,status", |_| None, "key");
+        assert!(matches!(result, Err(PaymentError::Signature(_))));
+    }
+
+    #[test]
+    fn test_status_response_deserialization() {
+        let body = r#"{"product_code":"EPAYTEST","transaction_uuid":"123","total_amount":100.0,"status":"COMPLETE","ref_id":"0001TS9"}"#;
+        let parsed: EsewaStatusResponse = serde_json::from_str(body).unwrap();
+
+        assert_eq!(parsed.status, EsewaStatus::Complete);
+        assert_eq!(parsed.total_amount, Npr::from_rupees(100));
+        assert_eq!(parsed.ref_id.as_deref(), Some("0001TS9"));
+
+        let body = r#"{"product_code":"EPAYTEST","transaction_uuid":"123","total_amount":100.0,"status":"NOT_FOUND","ref_id":null}"#;
+        let parsed: EsewaStatusResponse = serde_json::from_str(body).unwrap();
+
+        assert_eq!(parsed.status, EsewaStatus::NotFound);
+        assert!(parsed.ref_id.is_none());
+    }
+
+    #[test]
+    fn test_build_checkout_form() {
+        let request = EsewaPaymentRequest::builder(Npr::from_rupees(100))
+            .tax_amount(Npr::from_rupees(10))
+            .transaction_uuid("id-123")
+            .product_code("EPAYTEST")
+            .success_url("http://test.com/success")
+            .failure_url("http://test.com/failure")
+            .build()
+            .unwrap();
+
+        let form = build_checkout_form(&request, "8gBm/:&EnhH.1/q", EsewaEnvironment::Sandbox).unwrap();
+
+        assert_eq!(form.action, "https://rc-epay.esewa.com.np/api/epay/main/v2/form");
+        assert_eq!(form.field("total_amount"), Some("110"));
+        assert_eq!(
+            form.field("signature"),
+            Some(generate_signature("110", "id-123", "EPAYTEST", "8gBm/:&EnhH.1/q").as_str())
+        );
+        assert!(form.to_html().contains("name=\"signed_field_names\""));
+    }
+
+    #[test]
+    fn test_verify_esewa_response_accepts_matching_callback() {
+        let encoded = signed_callback(|_| {});
+        let expected = ExpectedPayment::new("id-123-abc", Npr::from_rupees(110));
+
+        let verified = verify_esewa_response(&encoded, "8gBm/:&EnhH.1/q", "EPAYTEST", &expected).unwrap();
+
+        assert_eq!(verified.transaction_code(), "000D13A");
+        assert!(verified.status().is_success());
+    }
+
+    #[test]
+    fn test_verify_esewa_response_reports_failed_check() {
+        let encoded = signed_callback(|response| response.product_code = "OTHER".to_string());
+        let expected = ExpectedPayment::new("id-123-abc", Npr::from_rupees(110));
+        let result = verify_esewa_response(&encoded, "8gBm/:&EnhH.1/q", "EPAYTEST", &expected);
+        assert!(matches!(
+            result,
+            Err(PaymentError::ProductCodeMismatch { .. })
+        ));
+
+        let encoded = signed_callback(|_| {});
+        let result = verify_esewa_response(&encoded, "wrong-key", "EPAYTEST", &expected);
+        assert!(matches!(
+            result,
+            Err(PaymentError::SignatureMismatch)
+        ));
+
+        let expected = ExpectedPayment::new("id-123-abc", Npr::from_rupees(10));
+        let result = verify_esewa_response(&encoded, "8gBm/:&EnhH.1/q", "EPAYTEST", &expected);
+        assert!(matches!(
+            result,
+            Err(PaymentError::AmountMismatch { .. })
+        ));
+
+        let expected = ExpectedPayment::new("id-other", Npr::from_rupees(110));
+        let result = verify_esewa_response(&encoded, "8gBm/:&EnhH.1/q", "EPAYTEST", &expected);
+        assert!(matches!(
+            result,
+            Err(PaymentError::TransactionUuidMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_verify_esewa_response_rejects_replayed_form_signature() {
+        // The checkout form signs only the default fields, so its signature
+        // must not vouch for a callback's status or transaction code
+        let forged = test_callbacks::replayed_form_signature();
+        let encoded = test_callbacks::encode(&forged);
+        let expected = ExpectedPayment::new("id-123-abc", Npr::from_rupees(110));
+
+        assert!(!validate_esewa_response(&encoded, "8gBm/:&EnhH.1/q").unwrap().signature_valid);
+        let keys = SecretKeySet::new("8gBm/:&EnhH.1/q");
+        assert_eq!(validate_esewa_response_with_keys(&encoded, &keys).unwrap().matched_key, None);
+        assert!(matches!(
+            verify_esewa_response(&encoded, "8gBm/:&EnhH.1/q", "EPAYTEST", &expected),
+            Err(PaymentError::SignatureMismatch)
+        ));
+
+        // A hand-built validation cannot vouch for the missing fields either
+        let validation = KeySetValidation {
+            matched_key: Some(MatchedKey::Current),
+            response: forged,
+        };
+        assert!(matches!(
+            validation.verify("EPAYTEST", &expected),
+            Err(PaymentError::SignatureMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_client_accepts_previous_key_until_it_expires() {
+        let encoded = signed_callback(|_| {});
+        let expected = ExpectedPayment::new("id-123-abc", Npr::from_rupees(110));
+        let expires_at = SystemTime::now() + Duration::from_secs(3600);
+        let client = EsewaClient::builder("EPAYTEST", "new-key")
+            .previous_key("8gBm/:&EnhH.1/q", expires_at)
+            .build()
+            .unwrap();
+
+        let verified = client.verify_callback(&encoded, &expected).unwrap();
+        assert_eq!(verified.matched_key(), MatchedKey::Previous(0));
+        assert!(client.validate_response(&encoded).unwrap().signature_valid);
+
+        let request = EsewaPaymentRequest::builder(Npr::from_rupees(110))
+            .transaction_uuid("id-123-abc")
+            .product_code("EPAYTEST")
+            .success_url("http://test.com/success")
+            .failure_url("http://test.com/failure")
+            .build()
+            .unwrap();
+        assert_eq!(client.sign(&request).unwrap(), request.signature("new-key").unwrap());
+
+        let expired = SecretKeySet::new("new-key").with_previous("8gBm/:&EnhH.1/q", SystemTime::UNIX_EPOCH);
+        let result = validate_esewa_response_with_keys(&encoded, &expired).unwrap();
+        assert_eq!(result.matched_key, None);
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        let signature = sign_message("total_amount=110", b"8gBm/:&EnhH.1/q");
+
+        assert!(verify_signature("total_amount=110", &signature, b"8gBm/:&EnhH.1/q").unwrap());
+        assert!(!verify_signature("total_amount=100", &signature, b"8gBm/:&EnhH.1/q").unwrap());
+        // Well-formed but truncated signatures are mismatches, not errors
+        assert!(!verify_signature("total_amount=110", "AAAA", b"8gBm/:&EnhH.1/q").unwrap());
+        assert!(matches!(
+            verify_signature("total_amount=110", "not base64!", b"8gBm/:&EnhH.1/q"),
+            Err(PaymentError::MalformedSignature(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_gateway_initiate_and_verify() {
+        let client = EsewaClient::builder("EPAYTEST", "8gBm/:&EnhH.1/q").build().unwrap();
+        let intent = PaymentIntent::new(
+            "id-123-abc",
+            Npr::from_rupees(110),
+            "http://test.com/success",
+            "http://test.com/failure",
+        );
+
+        let form = match client.initiate(&intent).await.unwrap().action {
+            CustomerAction::Form(form) => form,
+            other => panic!("expected a form, got {:?}", other),
+        };
+        assert_eq!(form.field("transaction_uuid"), Some("id-123-abc"));
+        assert_eq!(form.field("total_amount"), Some("110"));
+
+        let mut params = CallbackParams::new();
+        params.insert("data".to_string(), signed_callback(|_| {}));
+        let outcome = PaymentGateway::verify_callback(&client, &params, &intent).await.unwrap();
+        assert_eq!(outcome.status, PaymentStatus::Completed);
+        assert_eq!(outcome.gateway_reference.as_deref(), Some("000D13A"));
+
+        let result = PaymentGateway::verify_callback(&client, &CallbackParams::new(), &intent).await;
+        assert!(matches!(result, Err(PaymentError::InvalidResponse(_))));
+
+        let result = client.refund(&intent, None).await;
+        assert!(matches!(result, Err(PaymentError::Unsupported(_))));
+    }
+
+    #[test]
+    fn test_esewa_status_serde() {
+        let status: EsewaStatus = serde_json::from_str("\"PARTIAL_REFUND\"").unwrap();
+        assert_eq!(status, EsewaStatus::PartialRefund);
+        assert_eq!(serde_json::to_string(&status).unwrap(), "\"PARTIAL_REFUND\"");
+
+        let status: EsewaStatus = serde_json::from_str("\"ON_HOLD\"").unwrap();
+        assert_eq!(status, EsewaStatus::Unknown("ON_HOLD".to_string()));
+        assert_eq!(serde_json::to_string(&status).unwrap(), "\"ON_HOLD\"");
+    }
+
+    #[test]
+    fn test_esewa_status_helpers() {
+        assert!(EsewaStatus::Complete.is_success());
+        assert!(EsewaStatus::Complete.is_terminal());
+        assert!(EsewaStatus::Canceled.is_terminal());
+        assert!(!EsewaStatus::Canceled.is_success());
+        assert!(!EsewaStatus::Pending.is_terminal());
+        assert!(!EsewaStatus::Ambiguous.is_terminal());
+        assert!(!EsewaStatus::Unknown("ON_HOLD".to_string()).is_terminal());
+    }
+
+    #[test]
+    fn test_custom_environment_urls() {
+        let env = EsewaEnvironment::Custom {
+            base_url: "http://127.0.0.1:8080/".to_string(),
+        };
+
+        assert_eq!(env.form_url(), "http://127.0.0.1:8080/api/epay/main/v2/form");
+        assert_eq!(env.status_url(), "http://127.0.0.1:8080/api/epay/transaction/status/");
+    }
+
+    #[test]
+    fn test_environment_from_str() {
+        assert_eq!("Sandbox".parse::<EsewaEnvironment>().unwrap(), EsewaEnvironment::Sandbox);
+        assert_eq!("production".parse::<EsewaEnvironment>().unwrap(), EsewaEnvironment::Production);
+        assert_eq!(
+            "https://proxy.internal/esewa".parse::<EsewaEnvironment>().unwrap(),
+            EsewaEnvironment::Custom {
+                base_url: "https://proxy.internal/esewa".to_string()
+            }
+        );
+        assert!(matches!(
+            "staging".parse::<EsewaEnvironment>(),
+            Err(PaymentError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_client_builder() {
+        let client = EsewaClient::builder("EPAYTEST", "8gBm/:&EnhH.1/q")
+            .environment(EsewaEnvironment::Production)
+            .timeout(Duration::from_secs(5))
+            .user_agent("shop/1.0")
+            .build()
+            .unwrap();
+
+        assert_eq!(client.product_code(), "EPAYTEST");
+        assert!(matches!(client.environment(), EsewaEnvironment::Production));
+        assert!(!format!("{:?}", client).contains("8gBm"));
+
+        let result = EsewaClient::builder("EPAYTEST", "").build();
+        assert!(matches!(result, Err(PaymentError::Config(_))));
+    }
+
+    #[test]
+    fn test_validate_esewa_response_numeric_amount() {
+        let message = "transaction_code=000AWEO,status=COMPLETE,total_amount=1000.0,transaction_uuid=250610-162413,product_code=EPAYTEST,signed_field_names=transaction_code,status,total_amount,transaction_uuid,product_code,signed_field_names";
+        let json_str = format!(
+            r#"{{"transaction_code":"000AWEO","status":"COMPLETE","total_amount":1000.0,"transaction_uuid":"250610-162413","product_code":"EPAYTEST","signed_field_names":"transaction_code,status,total_amount,transaction_uuid,product_code,signed_field_names","signature":"{}"}}"#,
+            sign_message(message, b"8gBm/:&EnhH.1/q")
+        );
+        let encoded = general_purpose::STANDARD.encode(json_str.as_bytes());
+
+        let result = validate_esewa_response(&encoded, "8gBm/:&EnhH.1/q").unwrap();
+
+        assert!(result.signature_valid);
+        assert_eq!(result.response.total_amount, Npr::from_rupees(1000));
+    }
+
+    #[test]
+    fn test_request_builder_computes_total() {
+        let request = EsewaPaymentRequest::builder(Npr::from_rupees(100))
+            .tax_amount(Npr::from_rupees(10))
+            .product_delivery_charge(Npr::from_paisa(550))
+            .transaction_uuid("id-123")
+            .product_code("EPAYTEST")
+            .success_url("http://test.com/success")
+            .failure_url("http://test.com/failure")
+            .build()
+            .unwrap();
+
+        assert_eq!(request.total_amount, Npr::from_paisa(11550));
+        assert_eq!(request.field("total_amount").as_deref(), Some("115.50"));
+        assert!(request.check_total().is_ok());
+    }
+
+    #[test]
+    fn test_request_builder_rejects_inconsistent_total() {
+        let result = EsewaPaymentRequest::builder(Npr::from_rupees(100))
+            .tax_amount(Npr::from_rupees(10))
+            .total_amount(Npr::from_rupees(100))
+            .success_url("http://test.com/success")
+            .failure_url("http://test.com/failure")
+            .build();
+
+        assert!(matches!(result, Err(PaymentError::InvalidAmount(_))));
+
+        let mut request = EsewaPaymentRequest::builder(Npr::from_rupees(100))
+            .success_url("http://test.com/success")
+            .failure_url("http://test.com/failure")
+            .build()
+            .unwrap();
+        request.total_amount = Npr::from_rupees(1);
+        assert!(request.check_total().is_err());
+    }
+
     #[test]
     fn test_esewa_payment_request_serialization() {
         let request = EsewaPaymentRequest {
-            amount: "100".to_string(),
-            tax_amount: "10".to_string(),
-            total_amount: "110".to_string(),
+            amount: Npr::from_rupees(100),
+            tax_amount: Npr::from_rupees(10),
+            total_amount: Npr::from_rupees(110),
             transaction_uuid: "id-123".to_string(),
             product_code: "EPAYTEST".to_string(),
-            product_service_charge: "0".to_string(),
-            product_delivery_charge: "0".to_string(),
+            product_service_charge: Npr::from_rupees(0),
+            product_delivery_charge: Npr::from_rupees(0),
             success_url: "http://test.com/success".to_string(),
             failure_url: "http://test.com/failure".to_string(),
             signed_field_names: "total_amount,transaction_uuid,product_code".to_string(),
@@ -0,0 +1,954 @@
+//! Fonepay module implementing the web redirect and dynamic QR flows.
+//!
+//! Both flows sign requests with HMAC-SHA512 over comma-separated values
+//! and send the hex digest as the data validation (`DV`) parameter. Return
+//! parameters are signed the same way and are verified in constant time.
+
+use crate::error::PaymentError;
+use crate::esewa::ExpectedPayment;
+use crate::gateway::{
+    nepal_date, required_param, CallbackParams, CustomerAction, Initiation, PaymentGateway, PaymentIntent,
+    PaymentOutcome, PaymentStatus,
+};
+use crate::http::{build_client, read_env, send_json, DEFAULT_CONNECT_TIMEOUT, DEFAULT_TIMEOUT};
+use crate::retry::{Idempotency, RetryPolicy};
+use crate::money::Npr;
+use crate::secret::MerchantSecret;
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sha2::Sha512;
+use std::time::{Duration, SystemTime};
+
+/// Environment variable selecting the Fonepay environment
+pub const ENV_ENVIRONMENT: &str = "FONEPAY_ENVIRONMENT";
+/// Environment variable overriding the Fonepay base URL
+pub const ENV_BASE_URL: &str = "FONEPAY_BASE_URL";
+/// Environment variable holding the merchant code (`PID`)
+pub const ENV_MERCHANT_CODE: &str = "FONEPAY_MERCHANT_CODE";
+/// Environment variable holding the merchant secret key
+pub const ENV_SECRET_KEY: &str = "FONEPAY_SECRET_KEY";
+/// Environment variable holding the dynamic QR API username
+pub const ENV_USERNAME: &str = "FONEPAY_USERNAME";
+/// Environment variable holding the dynamic QR API password
+pub const ENV_PASSWORD: &str = "FONEPAY_PASSWORD";
+
+/// Fields signed in the web redirect request, in order
+const REDIRECT_SIGNED_FIELDS: [&str; 9] =
+    ["PID", "MD", "PRN", "AMT", "CRN", "DT", "R1", "R2", "RU"];
+/// Fields signed in the return parameters, in order
+const RETURN_SIGNED_FIELDS: [&str; 9] = [
+    "PRN", "PID", "PS", "RC", "UID", "BC", "INI", "P_AMT", "R_AMT",
+];
+
+const QR_DOWNLOAD_PATH: &str =
+    "/api/merchant/merchantDetailsForThirdParty/thirdPartyDynamicQrDownload";
+const QR_STATUS_PATH: &str =
+    "/api/merchant/merchantDetailsForThirdParty/thirdPartyDynamicQrGetStatus";
+
+/// Which Fonepay environment to use for requests
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FonepayEnvironment {
+    /// Use the Fonepay test hosts (`dev-clientapi` / `dev-merchantapi`)
+    Sandbox,
+    /// Use the production Fonepay hosts
+    Production,
+    /// Use one host serving both the redirect and the merchant API paths
+    Custom { base_url: String },
+}
+
+impl FonepayEnvironment {
+    fn client_base(&self) -> &str {
+        match self {
+            FonepayEnvironment::Sandbox => "https://dev-clientapi.fonepay.com",
+            FonepayEnvironment::Production => "https://clientapi.fonepay.com",
+            FonepayEnvironment::Custom { base_url } => base_url.trim_end_matches('/'),
+        }
+    }
+
+    fn merchant_base(&self) -> &str {
+        match self {
+            FonepayEnvironment::Sandbox => "https://dev-merchantapi.fonepay.com",
+            FonepayEnvironment::Production => "https://merchantapi.fonepay.com",
+            FonepayEnvironment::Custom { base_url } => base_url.trim_end_matches('/'),
+        }
+    }
+
+    /// URL the customer is redirected to for a web payment
+    pub fn redirect_url(&self) -> String {
+        format!("{}/api/merchantRequest", self.client_base())
+    }
+
+    /// URL of the dynamic QR creation endpoint
+    pub fn qr_download_url(&self) -> String {
+        format!("{}{}", self.merchant_base(), QR_DOWNLOAD_PATH)
+    }
+
+    /// URL of the dynamic QR status endpoint
+    pub fn qr_status_url(&self) -> String {
+        format!("{}{}", self.merchant_base(), QR_STATUS_PATH)
+    }
+
+    /// Reads the environment from `FONEPAY_BASE_URL` or `FONEPAY_ENVIRONMENT`
+    ///
+    /// Behaves like [`EsewaEnvironment::from_env`](crate::EsewaEnvironment::from_env).
+    pub fn from_env() -> Result<Self, PaymentError> {
+        if let Some(base_url) = read_env(ENV_BASE_URL)? {
+            return Ok(FonepayEnvironment::Custom { base_url });
+        }
+        match read_env(ENV_ENVIRONMENT)? {
+            Some(value) => value.parse(),
+            None => Ok(FonepayEnvironment::Sandbox),
+        }
+    }
+}
+
+impl std::str::FromStr for FonepayEnvironment {
+    type Err = PaymentError;
+
+    /// Parses `sandbox`, `production`, or an `http(s)://` base URL
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let value = s.trim();
+        match value.to_ascii_lowercase().as_str() {
+            "sandbox" | "test" | "dev" => Ok(FonepayEnvironment::Sandbox),
+            "production" | "prod" | "live" => Ok(FonepayEnvironment::Production),
+            lower if lower.starts_with("http://") || lower.starts_with("https://") => {
+                Ok(FonepayEnvironment::Custom {
+                    base_url: value.to_string(),
+                })
+            }
+            _ => Err(PaymentError::Config(format!(
+                "Unknown Fonepay environment {:?}; expected sandbox, production or a base URL",
+                value
+            ))),
+        }
+    }
+}
+
+/// A web redirect payment request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FonepayPaymentRequest {
+    /// Product reference number, the merchant's unique transaction ID
+    pub prn: String,
+    /// Amount to collect
+    pub amount: Npr,
+    /// Transaction date as `MM/DD/YYYY`
+    pub date: String,
+    /// First remark (`R1`), shown to the customer
+    pub remarks1: String,
+    /// Second remark (`R2`)
+    pub remarks2: String,
+    /// URL Fonepay returns the customer to
+    pub return_url: String,
+}
+
+impl FonepayPaymentRequest {
+    /// Creates a request dated today (Nepal time) with `N/A` remarks
+    pub fn new(prn: impl Into<String>, amount: Npr, return_url: impl Into<String>) -> Self {
+        FonepayPaymentRequest {
+            prn: prn.into(),
+            amount,
+            date: fonepay_date(SystemTime::now()),
+            remarks1: "N/A".to_string(),
+            remarks2: "N/A".to_string(),
+            return_url: return_url.into(),
+        }
+    }
+
+    /// Sets both remarks
+    pub fn with_remarks(
+        mut self,
+        remarks1: impl Into<String>,
+        remarks2: impl Into<String>,
+    ) -> Self {
+        self.remarks1 = remarks1.into();
+        self.remarks2 = remarks2.into();
+        self
+    }
+
+    /// Returns the redirect query parameters, including `DV`, in signing order
+    pub fn query_params(
+        &self,
+        merchant_code: &str,
+        secret_key: impl AsRef<[u8]>,
+    ) -> Vec<(String, String)> {
+        let amount = self.amount.to_string();
+        let values = [
+            merchant_code,
+            "P",
+            &self.prn,
+            &amount,
+            "NPR",
+            &self.date,
+            &self.remarks1,
+            &self.remarks2,
+            &self.return_url,
+        ];
+
+        let mut params: Vec<(String, String)> = REDIRECT_SIGNED_FIELDS
+            .iter()
+            .zip(values)
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        params.push(("DV".to_string(), sign_values(&values, secret_key.as_ref())));
+        params
+    }
+
+    /// Builds the signed URL to redirect the customer to
+    pub fn redirect_url(
+        &self,
+        merchant_code: &str,
+        secret_key: impl AsRef<[u8]>,
+        environment: &FonepayEnvironment,
+    ) -> Result<String, PaymentError> {
+        let mut url = reqwest::Url::parse(&environment.redirect_url())
+            .map_err(|e| PaymentError::Config(format!("Invalid Fonepay URL: {}", e)))?;
+        url.query_pairs_mut()
+            .extend_pairs(self.query_params(merchant_code, secret_key));
+        Ok(url.into())
+    }
+}
+
+/// Parameters Fonepay appends to the return URL
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FonepayReturn {
+    /// Product reference number sent in the request
+    pub prn: String,
+    /// Merchant code
+    pub pid: String,
+    /// Whether the payment succeeded (`PS`)
+    pub paid: bool,
+    /// Response code (`RC`), e.g. `successful`, `failed` or `cancel`
+    pub response_code: String,
+    /// Fonepay trace ID (`UID`)
+    pub uid: String,
+    /// Bank code (`BC`)
+    pub bank_code: String,
+    /// Initiator (`INI`)
+    pub initiator: String,
+    /// Amount paid (`P_AMT`)
+    pub paid_amount: Npr,
+    /// Amount requested (`R_AMT`)
+    pub requested_amount: Npr,
+}
+
+impl FonepayReturn {
+    /// Parses the return parameters without checking `DV`
+    ///
+    /// Use [`verify_fonepay_return`] or [`FonepayClient::verify_return`]
+    /// before acting on the result.
+    pub fn from_params(params: &CallbackParams) -> Result<Self, PaymentError> {
+        let amount = |name: &str| -> Result<Npr, PaymentError> {
+            match params.get(name).map(|value| value.trim()) {
+                None | Some("") => Ok(Npr::ZERO),
+                Some(value) => value.parse(),
+            }
+        };
+        let optional = |name: &str| params.get(name).cloned().unwrap_or_default();
+
+        Ok(FonepayReturn {
+            prn: required_param(params, "PRN")?.to_string(),
+            pid: required_param(params, "PID")?.to_string(),
+            paid: required_param(params, "PS")?.eq_ignore_ascii_case("true"),
+            response_code: optional("RC"),
+            uid: optional("UID"),
+            bank_code: optional("BC"),
+            initiator: optional("INI"),
+            paid_amount: amount("P_AMT")?,
+            requested_amount: amount("R_AMT")?,
+        })
+    }
+
+    /// Normalized payment status
+    pub fn status(&self) -> PaymentStatus {
+        match self.response_code.to_ascii_lowercase().as_str() {
+            "successful" | "success" if self.paid => PaymentStatus::Completed,
+            "cancel" | "canceled" | "cancelled" => PaymentStatus::Canceled,
+            _ => PaymentStatus::Failed,
+        }
+    }
+}
+
+/// Verifies Fonepay return parameters against the expected payment
+///
+/// Fails closed like [`verify_esewa_response`](crate::verify_esewa_response):
+/// the `DV` signature must match, `PID` must be `merchant_code`, `PRN` must be
+/// the expected transaction ID and, for paid returns, `P_AMT` must equal the
+/// expected amount. Unpaid returns verify successfully and report a failed or
+/// canceled [`FonepayReturn::status`].
+pub fn verify_fonepay_return(
+    params: &CallbackParams,
+    secret_key: impl AsRef<[u8]>,
+    merchant_code: &str,
+    expected: &ExpectedPayment,
+) -> Result<FonepayReturn, PaymentError> {
+    let signature = required_param(params, "DV")?;
+    let values: Vec<&str> = RETURN_SIGNED_FIELDS
+        .iter()
+        .map(|name| params.get(*name).map(String::as_str).unwrap_or(""))
+        .collect();
+    if !verify_values(&values, signature, secret_key.as_ref())? {
+        return Err(PaymentError::SignatureMismatch);
+    }
+
+    let result = FonepayReturn::from_params(params)?;
+    if result.pid != merchant_code {
+        return Err(PaymentError::ProductCodeMismatch {
+            expected: merchant_code.to_string(),
+            actual: result.pid,
+        });
+    }
+    if result.prn != expected.transaction_uuid {
+        return Err(PaymentError::TransactionUuidMismatch {
+            expected: expected.transaction_uuid.clone(),
+            actual: result.prn,
+        });
+    }
+    if result.paid && result.paid_amount != expected.total_amount {
+        return Err(PaymentError::AmountMismatch {
+            expected: expected.total_amount,
+            actual: result.paid_amount,
+        });
+    }
+
+    Ok(result)
+}
+
+/// A dynamic QR request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FonepayQrRequest {
+    /// Product reference number, the merchant's unique transaction ID
+    pub prn: String,
+    /// Amount to collect
+    pub amount: Npr,
+    /// First remark
+    pub remarks1: String,
+    /// Second remark
+    pub remarks2: String,
+}
+
+impl FonepayQrRequest {
+    /// Creates a request with `N/A` remarks
+    pub fn new(prn: impl Into<String>, amount: Npr) -> Self {
+        FonepayQrRequest {
+            prn: prn.into(),
+            amount,
+            remarks1: "N/A".to_string(),
+            remarks2: "N/A".to_string(),
+        }
+    }
+}
+
+/// Response of the dynamic QR creation endpoint
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FonepayQrResponse {
+    /// `true` when the QR was created
+    pub success: bool,
+    #[serde(default)]
+    pub message: String,
+    /// Payload to render as a QR code
+    #[serde(default)]
+    pub qr_message: String,
+    #[serde(default)]
+    pub status: String,
+    #[serde(default)]
+    pub status_code: Option<u32>,
+    /// WebSocket URL that reports scan and payment events
+    #[serde(default, rename = "thirdpartyQrWebSocketUrl")]
+    pub websocket_url: Option<String>,
+}
+
+/// Payment status of a dynamic QR
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum FonepayQrStatus {
+    Success,
+    Pending,
+    Failed,
+    /// A status not known to this version of the crate
+    Unknown(String),
+}
+
+impl FonepayQrStatus {
+    /// The status string as Fonepay sends it
+    pub fn as_str(&self) -> &str {
+        match self {
+            FonepayQrStatus::Success => "success",
+            FonepayQrStatus::Pending => "pending",
+            FonepayQrStatus::Failed => "failed",
+            FonepayQrStatus::Unknown(status) => status,
+        }
+    }
+}
+
+impl From<&str> for FonepayQrStatus {
+    fn from(status: &str) -> Self {
+        match status.to_ascii_lowercase().as_str() {
+            "success" => FonepayQrStatus::Success,
+            "pending" => FonepayQrStatus::Pending,
+            "failed" => FonepayQrStatus::Failed,
+            _ => FonepayQrStatus::Unknown(status.to_string()),
+        }
+    }
+}
+
+impl From<&FonepayQrStatus> for PaymentStatus {
+    fn from(status: &FonepayQrStatus) -> Self {
+        match status {
+            FonepayQrStatus::Success => PaymentStatus::Completed,
+            FonepayQrStatus::Pending => PaymentStatus::Pending,
+            FonepayQrStatus::Failed => PaymentStatus::Failed,
+            FonepayQrStatus::Unknown(_) => PaymentStatus::Ambiguous,
+        }
+    }
+}
+
+impl std::fmt::Display for FonepayQrStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for FonepayQrStatus {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for FonepayQrStatus {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let status = String::deserialize(deserializer)?;
+        Ok(FonepayQrStatus::from(status.as_str()))
+    }
+}
+
+/// Response of the dynamic QR status endpoint
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FonepayQrStatusResponse {
+    pub prn: String,
+    pub payment_status: FonepayQrStatus,
+    /// Fonepay trace ID, present once the QR has been paid
+    #[serde(default, deserialize_with = "string_or_number")]
+    pub fonepay_trace_id: Option<String>,
+}
+
+/// Reusable Fonepay client holding the merchant configuration and a pooled HTTP client
+///
+/// Create one per process with [`FonepayClient::builder`] and share it; cloning is cheap.
+#[derive(Clone)]
+pub struct FonepayClient {
+    merchant_code: String,
+    secret_key: MerchantSecret,
+    qr_credentials: Option<(String, MerchantSecret)>,
+    environment: FonepayEnvironment,
+    http: Client,
+    retry: RetryPolicy,
+}
+
+impl std::fmt::Debug for FonepayClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FonepayClient")
+            .field("merchant_code", &self.merchant_code)
+            .field("environment", &self.environment)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FonepayClient {
+    /// Starts building a client for the given merchant code and secret key
+    pub fn builder(
+        merchant_code: impl Into<String>,
+        secret_key: impl Into<MerchantSecret>,
+    ) -> FonepayClientBuilder {
+        FonepayClientBuilder {
+            merchant_code: merchant_code.into(),
+            secret_key: secret_key.into(),
+            qr_credentials: None,
+            environment: FonepayEnvironment::Sandbox,
+            timeout: DEFAULT_TIMEOUT,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            user_agent: None,
+            http_client: None,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Merchant code (`PID`) used for every request
+    pub fn merchant_code(&self) -> &str {
+        &self.merchant_code
+    }
+
+    /// Environment this client talks to
+    pub fn environment(&self) -> &FonepayEnvironment {
+        &self.environment
+    }
+
+    /// Builds the signed URL to redirect the customer to
+    pub fn redirect_url(&self, request: &FonepayPaymentRequest) -> Result<String, PaymentError> {
+        request.redirect_url(&self.merchant_code, &self.secret_key, &self.environment)
+    }
+
+    /// Strictly verifies return parameters; see [`verify_fonepay_return`]
+    pub fn verify_return(
+        &self,
+        params: &CallbackParams,
+        expected: &ExpectedPayment,
+    ) -> Result<FonepayReturn, PaymentError> {
+        verify_fonepay_return(params, &self.secret_key, &self.merchant_code, expected)
+    }
+
+    /// Creates a dynamic QR for the request
+    ///
+    /// Needs the QR API credentials set with [`FonepayClientBuilder::qr_credentials`].
+    pub async fn create_qr(
+        &self,
+        request: &FonepayQrRequest,
+    ) -> Result<FonepayQrResponse, PaymentError> {
+        let (username, password) = self.qr_credentials()?;
+        let amount = request.amount.to_string();
+        let data_validation = sign_values(
+            &[
+                &amount,
+                &request.prn,
+                &self.merchant_code,
+                &request.remarks1,
+                &request.remarks2,
+            ],
+            self.secret_key.as_ref(),
+        );
+        let body = serde_json::json!({
+            "amount": amount,
+            "remarks1": request.remarks1,
+            "remarks2": request.remarks2,
+            "prn": request.prn,
+            "merchantCode": self.merchant_code,
+            "dataValidation": data_validation,
+            "username": username,
+            "password": password,
+        });
+
+        let response: FonepayQrResponse = self
+            .post_json(
+                self.environment.qr_download_url(),
+                &body,
+                Idempotency::NotIdempotent,
+            )
+            .await?;
+        if !response.success {
+            return Err(PaymentError::Gateway {
+                code: response
+                    .status_code
+                    .map_or_else(|| response.status.clone(), |code| code.to_string()),
+                message: format!("Fonepay did not create the QR: {}", response.message),
+            });
+        }
+        Ok(response)
+    }
+
+    /// Asks Fonepay for the payment status of a dynamic QR
+    pub async fn qr_status(&self, prn: &str) -> Result<FonepayQrStatusResponse, PaymentError> {
+        let (username, password) = self.qr_credentials()?;
+        let data_validation = sign_values(&[prn, &self.merchant_code], self.secret_key.as_ref());
+        let body = serde_json::json!({
+            "prn": prn,
+            "merchantCode": self.merchant_code,
+            "dataValidation": data_validation,
+            "username": username,
+            "password": password,
+        });
+
+        self.post_json(self.environment.qr_status_url(), &body, Idempotency::Idempotent)
+            .await
+    }
+
+    fn qr_credentials(&self) -> Result<(&str, &str), PaymentError> {
+        self.qr_credentials
+            .as_ref()
+            .map(|(username, password)| (username.as_str(), password.expose_secret()))
+            .ok_or_else(|| {
+                PaymentError::Config(
+                    "Fonepay QR API username and password are not set".to_string(),
+                )
+            })
+    }
+
+    async fn post_json<T: serde::de::DeserializeOwned>(
+        &self,
+        url: String,
+        body: &serde_json::Value,
+        idempotency: Idempotency,
+    ) -> Result<T, PaymentError> {
+        let request = self.http.post(url).json(body);
+        send_json(&self.retry, idempotency, request).await
+    }
+}
+
+/// Fonepay web redirect as a [`PaymentGateway`]
+///
+/// Status lookups go through the dynamic QR status API, so they need QR
+/// credentials and only find payments made with [`FonepayClient::create_qr`].
+#[async_trait]
+impl PaymentGateway for FonepayClient {
+    fn name(&self) -> &'static str {
+        "fonepay"
+    }
+
+    async fn initiate(&self, intent: &PaymentIntent) -> Result<Initiation, PaymentError> {
+        let mut request = FonepayPaymentRequest::new(
+            intent.transaction_id.clone(),
+            intent.amount,
+            intent.success_url.clone(),
+        );
+        if let Some(description) = &intent.description {
+            request.remarks1 = description.clone();
+        }
+
+        Ok(Initiation {
+            action: CustomerAction::Redirect(self.redirect_url(&request)?),
+            gateway_reference: None,
+        })
+    }
+
+    async fn verify_callback(
+        &self,
+        params: &CallbackParams,
+        intent: &PaymentIntent,
+    ) -> Result<PaymentOutcome, PaymentError> {
+        let expected = ExpectedPayment::new(intent.transaction_id.clone(), intent.amount);
+        let result = self.verify_return(params, &expected)?;
+
+        Ok(PaymentOutcome {
+            gateway: self.name().to_string(),
+            transaction_id: result.prn.clone(),
+            gateway_reference: Some(result.uid.clone()).filter(|uid| !uid.is_empty()),
+            amount: Some(result.paid_amount),
+            status: result.status(),
+        })
+    }
+
+    async fn lookup_status(&self, intent: &PaymentIntent) -> Result<PaymentOutcome, PaymentError> {
+        let status = self.qr_status(&intent.transaction_id).await?;
+
+        Ok(PaymentOutcome {
+            gateway: self.name().to_string(),
+            transaction_id: status.prn,
+            gateway_reference: status.fonepay_trace_id,
+            amount: None,
+            status: PaymentStatus::from(&status.payment_status),
+        })
+    }
+}
+
+/// Builder for [`FonepayClient`]
+#[derive(Debug)]
+pub struct FonepayClientBuilder {
+    merchant_code: String,
+    secret_key: MerchantSecret,
+    qr_credentials: Option<(String, MerchantSecret)>,
+    environment: FonepayEnvironment,
+    timeout: Duration,
+    connect_timeout: Duration,
+    user_agent: Option<String>,
+    http_client: Option<Client>,
+    retry: RetryPolicy,
+}
+
+impl FonepayClientBuilder {
+    /// Starts a builder from environment variables
+    ///
+    /// Reads `FONEPAY_MERCHANT_CODE` and `FONEPAY_SECRET_KEY` (both required),
+    /// `FONEPAY_USERNAME` / `FONEPAY_PASSWORD` for the QR API, and the
+    /// environment as described in [`FonepayEnvironment::from_env`].
+    pub fn from_env() -> Result<Self, PaymentError> {
+        let merchant_code = read_env(ENV_MERCHANT_CODE)?.ok_or_else(|| {
+            PaymentError::Config(format!("{} is not set", ENV_MERCHANT_CODE))
+        })?;
+        let secret_key = MerchantSecret::from_env(ENV_SECRET_KEY)?;
+
+        let mut builder = FonepayClient::builder(merchant_code, secret_key)
+            .environment(FonepayEnvironment::from_env()?);
+        if let (Some(username), Some(password)) = (read_env(ENV_USERNAME)?, read_env(ENV_PASSWORD)?)
+        {
+            builder = builder.qr_credentials(username, password);
+        }
+        Ok(builder)
+    }
+
+    /// Sets the Fonepay environment (defaults to [`FonepayEnvironment::Sandbox`])
+    pub fn environment(mut self, environment: FonepayEnvironment) -> Self {
+        self.environment = environment;
+        self
+    }
+
+    /// Sets the username and password of the dynamic QR API
+    pub fn qr_credentials(
+        mut self,
+        username: impl Into<String>,
+        password: impl Into<MerchantSecret>,
+    ) -> Self {
+        self.qr_credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    /// Sets the total timeout for each request (defaults to 30 seconds)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the connection timeout (defaults to 10 seconds)
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Sets the `User-Agent` header sent to Fonepay
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Uses a preconfigured HTTP client; timeouts and user agent are then ignored
+    pub fn http_client(mut self, client: Client) -> Self {
+        self.http_client = Some(client);
+        self
+    }
+
+    /// Sets how failed requests are retried (defaults to [`RetryPolicy::default`])
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Builds the client
+    pub fn build(self) -> Result<FonepayClient, PaymentError> {
+        if self.merchant_code.is_empty() {
+            return Err(PaymentError::Config("merchant code is empty".to_string()));
+        }
+        if self.secret_key.is_empty() {
+            return Err(PaymentError::Config("secret key is empty".to_string()));
+        }
+
+        let http = match self.http_client {
+            Some(client) => client,
+            None => build_client(self.timeout, self.connect_timeout, self.user_agent)?,
+        };
+
+        Ok(FonepayClient {
+            merchant_code: self.merchant_code,
+            secret_key: self.secret_key,
+            qr_credentials: self.qr_credentials,
+            environment: self.environment,
+            http,
+            retry: self.retry,
+        })
+    }
+}
+
+/// Signs comma-joined values with HMAC-SHA512 and returns the lowercase hex digest
+fn sign_values(values: &[&str], secret_key: &[u8]) -> String {
+    let mut mac = Hmac::<Sha512>::new_from_slice(secret_key)
+        .expect("HMAC can take key of any size");
+    mac.update(values.join(",").as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Checks a hex `DV` over comma-joined values in constant time
+///
+/// Returns [`PaymentError::MalformedSignature`] if `signature` is not hex.
+fn verify_values(values: &[&str], signature: &str, secret_key: &[u8]) -> Result<bool, PaymentError> {
+    let signature_bytes = hex::decode(signature.trim())
+        .map_err(|e| PaymentError::MalformedSignature(e.into()))?;
+
+    let mut mac = Hmac::<Sha512>::new_from_slice(secret_key)
+        .expect("HMAC can take key of any size");
+    mac.update(values.join(",").as_bytes());
+    Ok(mac.verify_slice(&signature_bytes).is_ok())
+}
+
+/// Formats a time as Fonepay's `MM/DD/YYYY` in Nepal time
+fn fonepay_date(time: SystemTime) -> String {
+    let (year, month, day) = nepal_date(time);
+    format!("{:02}/{:02}/{}", month, day, year)
+}
+
+/// Deserializes an optional ID that Fonepay sends as either a number or a string
+fn string_or_number<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    Ok(
+        match Option::<serde_json::Value>::deserialize(deserializer)? {
+            Some(serde_json::Value::String(s)) => Some(s),
+            Some(serde_json::Value::Number(n)) => Some(n.to_string()),
+            _ => None,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    const SECRET: &str = "a7e3512f5032480a83137793cb2021dc";
+
+    fn signed_return(paid: bool, amount: &str) -> CallbackParams {
+        let ps = paid.to_string();
+        let rc = if paid { "successful" } else { "cancel" };
+        let values = [
+            "PRN-1",
+            "fonepay123",
+            &ps,
+            rc,
+            "12345",
+            "NCHL",
+            "9800000000",
+            amount,
+            "100",
+        ];
+        let mut params: CallbackParams = RETURN_SIGNED_FIELDS
+            .iter()
+            .zip(values)
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        params.insert("DV".to_string(), sign_values(&values, SECRET.as_bytes()));
+        params
+    }
+
+    #[test]
+    fn test_sign_values_is_hex_sha512() {
+        let dv = sign_values(&["a", "b"], SECRET.as_bytes());
+
+        assert_eq!(dv.len(), 128);
+        assert!(dv.bytes().all(|b| b.is_ascii_hexdigit()));
+        assert!(verify_values(&["a", "b"], &dv.to_uppercase(), SECRET.as_bytes()).unwrap());
+        assert!(!verify_values(&["a", "c"], &dv, SECRET.as_bytes()).unwrap());
+        assert!(matches!(
+            verify_values(&["a"], "not-hex", SECRET.as_bytes()),
+            Err(PaymentError::MalformedSignature(_))
+        ));
+    }
+
+    #[test]
+    fn test_redirect_url_signs_fields_in_order() {
+        let mut request =
+            FonepayPaymentRequest::new("PRN-1", Npr::from_rupees(100), "http://shop.test/return");
+        request.date = "05/28/2024".to_string();
+
+        let params = request.query_params("fonepay123", SECRET);
+        let names: Vec<&str> = params.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["PID", "MD", "PRN", "AMT", "CRN", "DT", "R1", "R2", "RU", "DV"]
+        );
+
+        let expected_dv = sign_values(
+            &[
+                "fonepay123",
+                "P",
+                "PRN-1",
+                "100",
+                "NPR",
+                "05/28/2024",
+                "N/A",
+                "N/A",
+                "http://shop.test/return",
+            ],
+            SECRET.as_bytes(),
+        );
+        assert_eq!(params[9].1, expected_dv);
+
+        let url = request
+            .redirect_url("fonepay123", SECRET, &FonepayEnvironment::Sandbox)
+            .unwrap();
+        assert!(url.starts_with(
+            "https://dev-clientapi.fonepay.com/api/merchantRequest?PID=fonepay123&MD=P"
+        ));
+        assert!(url.contains("DT=05%2F28%2F2024"));
+    }
+
+    #[test]
+    fn test_verify_return() {
+        let expected = ExpectedPayment::new("PRN-1", Npr::from_rupees(100));
+
+        let result =
+            verify_fonepay_return(&signed_return(true, "100"), SECRET, "fonepay123", &expected)
+                .unwrap();
+        assert_eq!(result.status(), PaymentStatus::Completed);
+        assert_eq!(result.paid_amount, Npr::from_rupees(100));
+
+        let canceled =
+            verify_fonepay_return(&signed_return(false, "0"), SECRET, "fonepay123", &expected)
+                .unwrap();
+        assert_eq!(canceled.status(), PaymentStatus::Canceled);
+    }
+
+    #[test]
+    fn test_verify_return_fails_closed() {
+        let expected = ExpectedPayment::new("PRN-1", Npr::from_rupees(100));
+
+        let mut tampered = signed_return(true, "100");
+        tampered.insert("P_AMT".to_string(), "1".to_string());
+        assert!(matches!(
+            verify_fonepay_return(&tampered, SECRET, "fonepay123", &expected),
+            Err(PaymentError::SignatureMismatch)
+        ));
+
+        assert!(matches!(
+            verify_fonepay_return(&signed_return(true, "50"), SECRET, "fonepay123", &expected),
+            Err(PaymentError::AmountMismatch { .. })
+        ));
+        assert!(matches!(
+            verify_fonepay_return(&signed_return(true, "100"), SECRET, "other", &expected),
+            Err(PaymentError::ProductCodeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_qr_status_deserialization() {
+        let body = r#"{"fonepayTraceId":1234567,"paymentStatus":"success","prn":"PRN-1"}"#;
+        let status: FonepayQrStatusResponse = serde_json::from_str(body).unwrap();
+
+        assert_eq!(status.payment_status, FonepayQrStatus::Success);
+        assert_eq!(status.fonepay_trace_id.as_deref(), Some("1234567"));
+        assert_eq!(
+            PaymentStatus::from(&status.payment_status),
+            PaymentStatus::Completed
+        );
+    }
+
+    #[test]
+    fn test_fonepay_date_uses_nepal_time() {
+        // 2024-02-29 18:15 UTC is already 1 March in Nepal
+        let time = UNIX_EPOCH + Duration::from_secs(1_709_230_500);
+        assert_eq!(fonepay_date(time), "03/01/2024");
+        assert_eq!(fonepay_date(UNIX_EPOCH), "01/01/1970");
+    }
+
+    #[tokio::test]
+    async fn test_qr_needs_credentials() {
+        let client = FonepayClient::builder("fonepay123", SECRET)
+            .build()
+            .unwrap();
+
+        let result = client.qr_status("PRN-1").await;
+
+        assert!(matches!(result, Err(PaymentError::Config(_))));
+    }
+
+    #[test]
+    fn test_debug_redacts_credentials() {
+        let builder = FonepayClient::builder("fonepay123", SECRET)
+            .qr_credentials("qr-user", "qr-password");
+        let printed = format!("{:?}", builder);
+        let client = format!("{:?}", builder.build().unwrap());
+
+        assert!(printed.contains("qr-user"));
+        for output in [printed, client] {
+            assert!(!output.contains(SECRET), "{}", output);
+            assert!(!output.contains("qr-password"), "{}", output);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! Browser-submitted checkout forms.
+//!
+//! Gateways such as eSewa expect the customer's browser to POST the signed
+//! payment fields. [`CheckoutForm`] holds those fields and renders them as a
+//! self-submitting HTML page that a web handler can return directly.
+
+use serde::{Deserialize, Serialize};
+
+/// A signed form to be posted by the customer's browser
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckoutForm {
+    /// URL the form is posted to
+    pub action: String,
+    /// Form fields in submission order, including the signature
+    pub fields: Vec<(String, String)>,
+}
+
+impl CheckoutForm {
+    /// Returns the value of a field by name
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Renders only the `<form>` element, for embedding in an existing page
+    pub fn to_form_html(&self) -> String {
+        let mut html = format!(
+            "<form id=\"rustpayment-checkout\" action=\"{}\" method=\"POST\">\n",
+            escape_html(&self.action)
+        );
+        for (name, value) in &self.fields {
+            html.push_str(&format!(
+                "  <input type=\"hidden\" name=\"{}\" value=\"{}\">\n",
+                escape_html(name),
+                escape_html(value)
+            ));
+        }
+        html.push_str("  <noscript><button type=\"submit\">Continue to payment</button></noscript>\n");
+        html.push_str("</form>");
+        html
+    }
+
+    /// Renders a complete HTML page that submits the form on load
+    pub fn to_html(&self) -> String {
+        format!(
+            "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Redirecting to payment</title></head>\n<body>\n{}\n<script>document.getElementById(\"rustpayment-checkout\").submit();</script>\n</body>\n</html>\n",
+            self.to_form_html()
+        )
+    }
+}
+
+/// Escapes text for use in HTML element content and quoted attributes
+pub(crate) fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_html_escapes_values() {
+        let form = CheckoutForm {
+            action: "https://example.com/pay?a=1&b=2".to_string(),
+            fields: vec![("note".to_string(), "\"><script>".to_string())],
+        };
+
+        let html = form.to_html();
+
+        assert!(html.contains("action=\"https://example.com/pay?a=1&amp;b=2\""));
+        assert!(html.contains("value=\"&quot;&gt;&lt;script&gt;\""));
+        assert!(html.contains(".submit();"));
+        assert_eq!(form.field("note"), Some("\"><script>"));
+    }
+}
@@ -0,0 +1,200 @@
+//! Provider-agnostic payment gateway interface.
+//!
+//! [`PaymentGateway`] lets checkout code initiate payments, verify callbacks
+//! and look up statuses without knowing which wallet or bank is behind it.
+//! Each provider module implements it for its client type, e.g.
+//! [`EsewaClient`](crate::EsewaClient).
+
+use crate::error::PaymentError;
+use crate::form::CheckoutForm;
+use crate::money::Npr;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Query or form parameters a gateway sent to a callback URL
+pub type CallbackParams = HashMap<String, String>;
+
+/// A payment to be collected, independent of the gateway
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaymentIntent {
+    /// Merchant-side transaction identifier, unique per attempt
+    pub transaction_id: String,
+    /// Total amount to collect
+    pub amount: Npr,
+    /// Human-readable description shown by gateways that support one
+    pub description: Option<String>,
+    /// URL the customer returns to after paying
+    pub success_url: String,
+    /// URL the customer returns to after a failed or canceled payment
+    pub failure_url: String,
+    /// The gateway's reference returned by [`PaymentGateway::initiate`], for
+    /// gateways that look payments up by it (e.g. Khalti's `pidx`)
+    pub gateway_reference: Option<String>,
+}
+
+impl PaymentIntent {
+    /// Creates an intent without a description
+    pub fn new(
+        transaction_id: impl Into<String>,
+        amount: Npr,
+        success_url: impl Into<String>,
+        failure_url: impl Into<String>,
+    ) -> Self {
+        PaymentIntent {
+            transaction_id: transaction_id.into(),
+            amount,
+            description: None,
+            success_url: success_url.into(),
+            failure_url: failure_url.into(),
+            gateway_reference: None,
+        }
+    }
+
+    /// Sets the description
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Sets the gateway's reference for this payment
+    pub fn with_gateway_reference(mut self, reference: impl Into<String>) -> Self {
+        self.gateway_reference = Some(reference.into());
+        self
+    }
+}
+
+/// A started payment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Initiation {
+    /// Where to send the customer
+    pub action: CustomerAction,
+    /// The gateway's reference for the payment, if it issues one at initiation;
+    /// store it on the [`PaymentIntent`] for later lookups
+    pub gateway_reference: Option<String>,
+}
+
+/// How the customer is sent to the gateway
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CustomerAction {
+    /// Redirect the browser to this URL
+    Redirect(String),
+    /// Have the browser submit this form
+    Form(CheckoutForm),
+}
+
+/// Payment status shared by all gateways
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PaymentStatus {
+    /// Started but not yet settled
+    Pending,
+    /// Paid in full
+    Completed,
+    /// Failed, expired or never started at the gateway
+    Failed,
+    /// Canceled by the customer or the gateway
+    Canceled,
+    /// Fully refunded
+    Refunded,
+    /// Partially refunded
+    PartiallyRefunded,
+    /// The gateway cannot currently tell; check again later
+    Ambiguous,
+}
+
+impl PaymentStatus {
+    /// Returns `true` if the customer has paid and nothing was refunded
+    pub fn is_success(&self) -> bool {
+        matches!(self, PaymentStatus::Completed)
+    }
+}
+
+/// Result of verifying a callback or looking up a payment
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaymentOutcome {
+    /// Name of the gateway that reported the outcome
+    pub gateway: String,
+    /// Merchant-side transaction identifier
+    pub transaction_id: String,
+    /// The gateway's own reference for the payment, once known
+    pub gateway_reference: Option<String>,
+    /// Amount the gateway reported, if any
+    pub amount: Option<Npr>,
+    /// Normalized payment status
+    pub status: PaymentStatus,
+}
+
+/// Common operations of a payment gateway
+#[async_trait]
+pub trait PaymentGateway: Send + Sync {
+    /// Short lowercase gateway name, e.g. `"esewa"`
+    fn name(&self) -> &'static str;
+
+    /// Starts a payment and tells the caller where to send the customer
+    async fn initiate(&self, intent: &PaymentIntent) -> Result<Initiation, PaymentError>;
+
+    /// Verifies the parameters the gateway sent to the success URL
+    ///
+    /// Fails unless the callback is authentic and matches `intent`.
+    async fn verify_callback(
+        &self,
+        params: &CallbackParams,
+        intent: &PaymentIntent,
+    ) -> Result<PaymentOutcome, PaymentError>;
+
+    /// Asks the gateway for the current status of a payment
+    async fn lookup_status(&self, intent: &PaymentIntent) -> Result<PaymentOutcome, PaymentError>;
+
+    /// Refunds a payment, fully when `amount` is `None`
+    ///
+    /// Gateways without a refund API return [`PaymentError::Unsupported`].
+    async fn refund(
+        &self,
+        intent: &PaymentIntent,
+        amount: Option<Npr>,
+    ) -> Result<PaymentOutcome, PaymentError> {
+        let _ = (intent, amount);
+        Err(PaymentError::Unsupported(format!(
+            "{} does not support refunds",
+            self.name()
+        )))
+    }
+}
+
+/// Returns a required callback parameter
+pub(crate) fn required_param<'a>(
+    params: &'a CallbackParams,
+    name: &str,
+) -> Result<&'a str, PaymentError> {
+    params
+        .get(name)
+        .map(String::as_str)
+        .ok_or_else(|| PaymentError::InvalidResponse(format!("Callback is missing `{}`", name)))
+}
+
+/// Returns the calendar date `(year, month, day)` of `time` in Nepal (UTC+05:45)
+///
+/// Several gateways expect the transaction date in local time.
+pub(crate) fn nepal_date(time: SystemTime) -> (i64, u32, u32) {
+    const NEPAL_OFFSET_SECS: u64 = 5 * 3600 + 45 * 60;
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+        + NEPAL_OFFSET_SECS;
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let days = (secs / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
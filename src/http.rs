@@ -0,0 +1,72 @@
+//! HTTP plumbing shared by the gateway clients.
+
+use crate::error::PaymentError;
+use crate::retry::{Idempotency, RetryPolicy};
+use reqwest::{Client, RequestBuilder, Response};
+use serde::de::DeserializeOwned;
+use std::time::Duration;
+
+/// Default timeout for a whole request to a gateway
+pub(crate) const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default timeout for establishing a connection to a gateway
+pub(crate) const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Builds an HTTP client with the crate's defaults
+pub(crate) fn build_client(
+    timeout: Duration,
+    connect_timeout: Duration,
+    user_agent: Option<String>,
+) -> Result<Client, PaymentError> {
+    Client::builder()
+        .timeout(timeout)
+        .connect_timeout(connect_timeout)
+        .user_agent(user_agent.unwrap_or_else(|| {
+            concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")).to_string()
+        }))
+        .build()
+        .map_err(|e| PaymentError::Config(e.to_string()))
+}
+
+/// Reads a JSON body, turning non-success statuses into [`PaymentError::Http`]
+pub(crate) async fn read_json<T: DeserializeOwned>(response: Response) -> Result<T, PaymentError> {
+    let status = response.status();
+    let body = response.text().await?;
+
+    if !status.is_success() {
+        return Err(PaymentError::Http {
+            status: status.as_u16(),
+            body,
+        });
+    }
+
+    serde_json::from_str(&body).map_err(|e| PaymentError::decode("JSON parse failed", e))
+}
+
+/// Sends `request` under `retry` and reads its JSON body with [`read_json`]
+pub(crate) async fn send_json<T: DeserializeOwned>(
+    retry: &RetryPolicy,
+    idempotency: Idempotency,
+    request: RequestBuilder,
+) -> Result<T, PaymentError> {
+    retry
+        .run(idempotency, || async {
+            let request = request
+                .try_clone()
+                .expect("gateway requests have buffered bodies");
+            read_json(request.send().await?).await
+        })
+        .await
+}
+
+/// Reads a non-empty environment variable
+pub(crate) fn read_env(name: &str) -> Result<Option<String>, PaymentError> {
+    match std::env::var(name) {
+        Ok(value) if value.trim().is_empty() => Ok(None),
+        Ok(value) => Ok(Some(value)),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(std::env::VarError::NotUnicode(_)) => {
+            Err(PaymentError::Config(format!("{} is not valid UTF-8", name)))
+        }
+    }
+}
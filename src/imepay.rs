@@ -0,0 +1,961 @@
+//! IME Pay module implementing the web checkout API.
+//!
+//! A payment starts with a token from `GetToken`, continues on IME Pay's
+//! checkout page, and returns to the merchant with a base64, pipe-delimited
+//! `data` parameter (or the same fields posted individually). The response is
+//! not signed, so successful payments are confirmed with the `Confirm` API;
+//! `Recheck` reports the status of a token at any time.
+
+use crate::error::PaymentError;
+use crate::esewa::ExpectedPayment;
+use crate::form::CheckoutForm;
+use crate::gateway::{
+    required_param, CallbackParams, CustomerAction, Initiation, PaymentGateway, PaymentIntent,
+    PaymentOutcome, PaymentStatus,
+};
+use crate::http::{build_client, read_env, send_json, DEFAULT_CONNECT_TIMEOUT, DEFAULT_TIMEOUT};
+use crate::money::Npr;
+use crate::retry::{Idempotency, RetryPolicy};
+use crate::secret::MerchantSecret;
+use async_trait::async_trait;
+use base64::{engine::general_purpose, Engine};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Environment variable selecting the IME Pay environment
+pub const ENV_ENVIRONMENT: &str = "IMEPAY_ENVIRONMENT";
+/// Environment variable overriding the IME Pay base URL
+pub const ENV_BASE_URL: &str = "IMEPAY_BASE_URL";
+/// Environment variable holding the merchant code
+pub const ENV_MERCHANT_CODE: &str = "IMEPAY_MERCHANT_CODE";
+/// Environment variable holding the API user
+pub const ENV_API_USER: &str = "IMEPAY_API_USER";
+/// Environment variable holding the API password
+pub const ENV_PASSWORD: &str = "IMEPAY_PASSWORD";
+/// Environment variable holding the module name
+pub const ENV_MODULE: &str = "IMEPAY_MODULE";
+
+/// Which IME Pay environment to use for requests
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImePayEnvironment {
+    /// Use the IME Pay staging host (`stg.imepay.com.np`)
+    Sandbox,
+    /// Use the production IME Pay host
+    Production,
+    /// Use another host exposing IME Pay's API paths
+    Custom { base_url: String },
+}
+
+impl ImePayEnvironment {
+    fn base_url(&self) -> &str {
+        match self {
+            ImePayEnvironment::Sandbox => "https://stg.imepay.com.np:7979",
+            ImePayEnvironment::Production => "https://payment.imepay.com.np:7979",
+            ImePayEnvironment::Custom { base_url } => base_url.trim_end_matches('/'),
+        }
+    }
+
+    /// URL of the token endpoint
+    pub fn token_url(&self) -> String {
+        format!("{}/api/Web/GetToken", self.base_url())
+    }
+
+    /// URL the checkout form posts to
+    pub fn checkout_url(&self) -> String {
+        format!("{}/WebCheckout/Checkout", self.base_url())
+    }
+
+    /// URL of the confirmation endpoint
+    pub fn confirm_url(&self) -> String {
+        format!("{}/api/Web/Confirm", self.base_url())
+    }
+
+    /// URL of the recheck endpoint
+    pub fn recheck_url(&self) -> String {
+        format!("{}/api/Web/Recheck", self.base_url())
+    }
+
+    /// Reads the environment from `IMEPAY_BASE_URL` or `IMEPAY_ENVIRONMENT`
+    ///
+    /// Behaves like [`EsewaEnvironment::from_env`](crate::EsewaEnvironment::from_env).
+    pub fn from_env() -> Result<Self, PaymentError> {
+        if let Some(base_url) = read_env(ENV_BASE_URL)? {
+            return Ok(ImePayEnvironment::Custom { base_url });
+        }
+        match read_env(ENV_ENVIRONMENT)? {
+            Some(value) => value.parse(),
+            None => Ok(ImePayEnvironment::Sandbox),
+        }
+    }
+}
+
+impl std::str::FromStr for ImePayEnvironment {
+    type Err = PaymentError;
+
+    /// Parses `sandbox`, `production`, or an `http(s)://` base URL
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let value = s.trim();
+        match value.to_ascii_lowercase().as_str() {
+            "sandbox" | "test" | "staging" => Ok(ImePayEnvironment::Sandbox),
+            "production" | "prod" | "live" => Ok(ImePayEnvironment::Production),
+            lower if lower.starts_with("http://") || lower.starts_with("https://") => {
+                Ok(ImePayEnvironment::Custom {
+                    base_url: value.to_string(),
+                })
+            }
+            _ => Err(PaymentError::Config(format!(
+                "Unknown IME Pay environment {:?}; expected sandbox, production or a base URL",
+                value
+            ))),
+        }
+    }
+}
+
+/// IME Pay response code
+///
+/// Sent as a number (or numeric string); unknown codes deserialize to
+/// [`ImePayStatus::Unknown`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ImePayStatus {
+    /// `0`: the request or payment succeeded
+    Success,
+    /// `1`: the payment failed
+    Failed,
+    /// `2`: IME Pay reported an error
+    Error,
+    /// `3`: the customer canceled the payment
+    Canceled,
+    /// A code not known to this version of the crate
+    Unknown(i64),
+}
+
+impl ImePayStatus {
+    /// The numeric code as IME Pay sends it
+    pub fn code(&self) -> i64 {
+        match self {
+            ImePayStatus::Success => 0,
+            ImePayStatus::Failed => 1,
+            ImePayStatus::Error => 2,
+            ImePayStatus::Canceled => 3,
+            ImePayStatus::Unknown(code) => *code,
+        }
+    }
+
+    /// Returns `true` for [`ImePayStatus::Success`]
+    pub fn is_success(&self) -> bool {
+        matches!(self, ImePayStatus::Success)
+    }
+}
+
+impl From<i64> for ImePayStatus {
+    fn from(code: i64) -> Self {
+        match code {
+            0 => ImePayStatus::Success,
+            1 => ImePayStatus::Failed,
+            2 => ImePayStatus::Error,
+            3 => ImePayStatus::Canceled,
+            other => ImePayStatus::Unknown(other),
+        }
+    }
+}
+
+impl From<&ImePayStatus> for PaymentStatus {
+    fn from(status: &ImePayStatus) -> Self {
+        match status {
+            ImePayStatus::Success => PaymentStatus::Completed,
+            ImePayStatus::Failed => PaymentStatus::Failed,
+            ImePayStatus::Canceled => PaymentStatus::Canceled,
+            ImePayStatus::Error | ImePayStatus::Unknown(_) => PaymentStatus::Ambiguous,
+        }
+    }
+}
+
+impl std::fmt::Display for ImePayStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+impl std::str::FromStr for ImePayStatus {
+    type Err = PaymentError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.trim()
+            .parse::<i64>()
+            .map(ImePayStatus::from)
+            .map_err(|e| PaymentError::decode(format!("Invalid IME Pay response code: {:?}", s), e))
+    }
+}
+
+impl Serialize for ImePayStatus {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i64(self.code())
+    }
+}
+
+impl<'de> Deserialize<'de> for ImePayStatus {
+    /// Accepts both JSON numbers and numeric strings
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match serde_json::Value::deserialize(deserializer)? {
+            serde_json::Value::Number(n) => n
+                .as_i64()
+                .map(ImePayStatus::from)
+                .ok_or_else(|| serde::de::Error::custom("expected an integer response code")),
+            serde_json::Value::String(s) => s.parse().map_err(serde::de::Error::custom),
+            _ => Err(serde::de::Error::custom("expected a response code")),
+        }
+    }
+}
+
+/// Response of the token endpoint
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ImePayTokenResponse {
+    pub response_code: ImePayStatus,
+    #[serde(default)]
+    pub response_description: String,
+    /// Token identifying the payment; needed for checkout, confirm and recheck
+    #[serde(default)]
+    pub token_id: String,
+    pub amount: Npr,
+    pub ref_id: String,
+}
+
+/// Payment response IME Pay sends to the response URL
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ImePayResponse {
+    pub response_code: ImePayStatus,
+    pub response_description: String,
+    /// Customer's IME Pay mobile number
+    pub msisdn: String,
+    pub transaction_id: String,
+    /// The merchant's reference ID
+    pub ref_id: String,
+    pub tran_amount: Npr,
+    pub token_id: String,
+}
+
+impl ImePayResponse {
+    /// Parses the response from callback parameters
+    ///
+    /// Accepts the base64 `data` parameter IME Pay sends for `GET` responses as
+    /// well as the individual fields it posts for `POST` responses.
+    pub fn from_params(params: &CallbackParams) -> Result<Self, PaymentError> {
+        if let Some(data) = params.get("data") {
+            return decode_imepay_response(data);
+        }
+
+        Ok(ImePayResponse {
+            response_code: required_param(params, "ResponseCode")?.parse()?,
+            response_description: params
+                .get("ResponseDescription")
+                .cloned()
+                .unwrap_or_default(),
+            msisdn: params.get("Msisdn").cloned().unwrap_or_default(),
+            transaction_id: params.get("TransactionId").cloned().unwrap_or_default(),
+            ref_id: required_param(params, "RefId")?.to_string(),
+            tran_amount: required_param(params, "TranAmount")?.parse()?,
+            token_id: required_param(params, "TokenId")?.to_string(),
+        })
+    }
+}
+
+/// Response of the confirm and recheck endpoints
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ImePayConfirmation {
+    pub response_code: ImePayStatus,
+    #[serde(default)]
+    pub response_description: String,
+    #[serde(default)]
+    pub msisdn: String,
+    #[serde(default)]
+    pub transaction_id: String,
+    pub ref_id: String,
+    pub token_id: String,
+}
+
+/// Decodes the base64 `data` parameter IME Pay appends to the response URL
+///
+/// The decoded value is
+/// `ResponseCode|ResponseDescription|Msisdn|TransactionId|RefId|TranAmount|TokenId`.
+/// The response is not signed; see [`ImePayClient::verify_callback`].
+pub fn decode_imepay_response(encoded_data: &str) -> Result<ImePayResponse, PaymentError> {
+    let decoded_bytes = general_purpose::STANDARD
+        .decode(encoded_data.trim())
+        .map_err(|e| PaymentError::decode("Base64 decode failed", e))?;
+    let decoded = String::from_utf8(decoded_bytes)
+        .map_err(|e| PaymentError::decode("UTF-8 decode failed", e))?;
+
+    let parts: Vec<&str> = decoded.split('|').collect();
+    let [code, description, msisdn, transaction_id, ref_id, amount, token_id] = parts[..] else {
+        return Err(PaymentError::Decode {
+            context: format!(
+                "Expected 7 `|`-separated fields in IME Pay response, got {}",
+                parts.len()
+            ),
+            source: None,
+        });
+    };
+
+    Ok(ImePayResponse {
+        response_code: code.parse()?,
+        response_description: description.to_string(),
+        msisdn: msisdn.to_string(),
+        transaction_id: transaction_id.to_string(),
+        ref_id: ref_id.to_string(),
+        tran_amount: amount.parse()?,
+        token_id: token_id.to_string(),
+    })
+}
+
+/// Reusable IME Pay client holding the merchant configuration and a pooled HTTP client
+///
+/// Create one per process with [`ImePayClient::builder`] and share it; cloning is cheap.
+#[derive(Clone)]
+pub struct ImePayClient {
+    merchant_code: String,
+    api_user: String,
+    password: MerchantSecret,
+    module: String,
+    environment: ImePayEnvironment,
+    http: Client,
+    retry: RetryPolicy,
+}
+
+impl std::fmt::Debug for ImePayClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ImePayClient")
+            .field("merchant_code", &self.merchant_code)
+            .field("api_user", &self.api_user)
+            .field("module", &self.module)
+            .field("environment", &self.environment)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ImePayClient {
+    /// Starts building a client with the credentials IME Pay issues to merchants
+    pub fn builder(
+        merchant_code: impl Into<String>,
+        api_user: impl Into<String>,
+        password: impl Into<MerchantSecret>,
+        module: impl Into<String>,
+    ) -> ImePayClientBuilder {
+        ImePayClientBuilder {
+            merchant_code: merchant_code.into(),
+            api_user: api_user.into(),
+            password: password.into(),
+            module: module.into(),
+            environment: ImePayEnvironment::Sandbox,
+            timeout: DEFAULT_TIMEOUT,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            user_agent: None,
+            http_client: None,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Merchant code used for every request
+    pub fn merchant_code(&self) -> &str {
+        &self.merchant_code
+    }
+
+    /// Environment this client talks to
+    pub fn environment(&self) -> &ImePayEnvironment {
+        &self.environment
+    }
+
+    /// Requests a payment token for `ref_id`
+    pub async fn get_token(
+        &self,
+        ref_id: &str,
+        amount: Npr,
+    ) -> Result<ImePayTokenResponse, PaymentError> {
+        let body = serde_json::json!({
+            "MerchantCode": self.merchant_code,
+            "Amount": format_amount(amount),
+            "RefId": ref_id,
+        });
+        let response: ImePayTokenResponse = self
+            .post_json(
+                self.environment.token_url(),
+                &body,
+                Idempotency::NotIdempotent,
+            )
+            .await?;
+
+        if !response.response_code.is_success() || response.token_id.is_empty() {
+            return Err(PaymentError::Gateway {
+                code: response.response_code.to_string(),
+                message: format!(
+                    "IME Pay did not issue a token: {}",
+                    response.response_description
+                ),
+            });
+        }
+        Ok(response)
+    }
+
+    /// Builds the self-submitting checkout form for a token
+    ///
+    /// IME Pay returns the customer to `response_url` with the result as a
+    /// base64 `data` query parameter, or to `cancel_url` if they cancel.
+    pub fn checkout_form(
+        &self,
+        token: &ImePayTokenResponse,
+        response_url: &str,
+        cancel_url: &str,
+    ) -> CheckoutForm {
+        let fields = [
+            ("TokenId", token.token_id.clone()),
+            ("MerchantCode", self.merchant_code.clone()),
+            ("RefId", token.ref_id.clone()),
+            ("TranAmount", format_amount(token.amount)),
+            ("Method", "GET".to_string()),
+            ("RespUrl", response_url.to_string()),
+            ("CancelUrl", cancel_url.to_string()),
+        ];
+
+        CheckoutForm {
+            action: self.environment.checkout_url(),
+            fields: fields
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+        }
+    }
+
+    /// Confirms a payment the customer completed
+    pub async fn confirm(
+        &self,
+        response: &ImePayResponse,
+    ) -> Result<ImePayConfirmation, PaymentError> {
+        let body = serde_json::json!({
+            "MerchantCode": self.merchant_code,
+            "RefId": response.ref_id,
+            "TokenId": response.token_id,
+            "TransactionId": response.transaction_id,
+            "Msisdn": response.msisdn,
+        });
+        self.post_json(
+            self.environment.confirm_url(),
+            &body,
+            Idempotency::NotIdempotent,
+        )
+        .await
+    }
+
+    /// Asks IME Pay for the current status of a token
+    pub async fn recheck(
+        &self,
+        ref_id: &str,
+        token_id: &str,
+    ) -> Result<ImePayConfirmation, PaymentError> {
+        let body = serde_json::json!({
+            "MerchantCode": self.merchant_code,
+            "RefId": ref_id,
+            "TokenId": token_id,
+        });
+        self.post_json(
+            self.environment.recheck_url(),
+            &body,
+            Idempotency::Idempotent,
+        )
+        .await
+    }
+
+    /// Checks a payment response against the expected payment and confirms it
+    ///
+    /// Fails closed: the response must carry the `token_id` issued by
+    /// [`ImePayClient::get_token`] for this payment and the expected
+    /// transaction ID as its reference. IME Pay issues a token for one amount,
+    /// so the token, rather than the unsigned `TranAmount`, ties the payment
+    /// to `expected.total_amount`; `TranAmount` must match as well. Successful
+    /// payments are confirmed with the Confirm API and other outcomes with the
+    /// Recheck API, and the status IME Pay reports there is returned.
+    pub async fn verify_callback(
+        &self,
+        response: &ImePayResponse,
+        token_id: &str,
+        expected: &ExpectedPayment,
+    ) -> Result<ImePayStatus, PaymentError> {
+        if response.token_id != token_id {
+            return Err(PaymentError::TransactionUuidMismatch {
+                expected: token_id.to_string(),
+                actual: response.token_id.clone(),
+            });
+        }
+        if response.ref_id != expected.transaction_uuid {
+            return Err(PaymentError::TransactionUuidMismatch {
+                expected: expected.transaction_uuid.clone(),
+                actual: response.ref_id.clone(),
+            });
+        }
+        if !response.response_code.is_success() {
+            // An unsigned failure could hide a completed payment
+            let recheck = self.recheck(&response.ref_id, token_id).await?;
+            check_same_transaction(&recheck, response, "rechecked")?;
+            return Ok(recheck.response_code);
+        }
+        if response.tran_amount != expected.total_amount {
+            return Err(PaymentError::AmountMismatch {
+                expected: expected.total_amount,
+                actual: response.tran_amount,
+            });
+        }
+
+        let confirmation = self.confirm(response).await?;
+        check_same_transaction(&confirmation, response, "confirmed")?;
+        Ok(confirmation.response_code)
+    }
+
+    async fn post_json<T: serde::de::DeserializeOwned>(
+        &self,
+        url: String,
+        body: &serde_json::Value,
+        idempotency: Idempotency,
+    ) -> Result<T, PaymentError> {
+        let request = self
+            .http
+            .post(url)
+            .basic_auth(&self.api_user, Some(self.password.expose_secret()))
+            .header("Module", general_purpose::STANDARD.encode(&self.module))
+            .json(body);
+
+        send_json(&self.retry, idempotency, request).await
+    }
+}
+
+/// IME Pay as a [`PaymentGateway`]
+///
+/// Initiation returns the checkout form and the token as the gateway
+/// reference; callback verification and lookups require that reference on
+/// the [`PaymentIntent`].
+#[async_trait]
+impl PaymentGateway for ImePayClient {
+    fn name(&self) -> &'static str {
+        "imepay"
+    }
+
+    async fn initiate(&self, intent: &PaymentIntent) -> Result<Initiation, PaymentError> {
+        let token = self
+            .get_token(&intent.transaction_id, intent.amount)
+            .await?;
+        let form = self.checkout_form(&token, &intent.success_url, &intent.failure_url);
+
+        Ok(Initiation {
+            action: CustomerAction::Form(form),
+            gateway_reference: Some(token.token_id),
+        })
+    }
+
+    async fn verify_callback(
+        &self,
+        params: &CallbackParams,
+        intent: &PaymentIntent,
+    ) -> Result<PaymentOutcome, PaymentError> {
+        let response = ImePayResponse::from_params(params)?;
+        let expected = ExpectedPayment::new(intent.transaction_id.clone(), intent.amount);
+        let status =
+            ImePayClient::verify_callback(self, &response, token_id(intent)?, &expected).await?;
+
+        Ok(PaymentOutcome {
+            gateway: self.name().to_string(),
+            transaction_id: response.ref_id,
+            gateway_reference: Some(response.transaction_id).filter(|id| !id.is_empty()),
+            amount: Some(intent.amount),
+            status: PaymentStatus::from(&status),
+        })
+    }
+
+    async fn lookup_status(&self, intent: &PaymentIntent) -> Result<PaymentOutcome, PaymentError> {
+        let recheck = self
+            .recheck(&intent.transaction_id, token_id(intent)?)
+            .await?;
+
+        Ok(PaymentOutcome {
+            gateway: self.name().to_string(),
+            transaction_id: recheck.ref_id,
+            gateway_reference: Some(recheck.transaction_id).filter(|id| !id.is_empty()),
+            amount: None,
+            status: PaymentStatus::from(&recheck.response_code),
+        })
+    }
+}
+
+/// The token issued at initiation
+fn token_id(intent: &PaymentIntent) -> Result<&str, PaymentError> {
+    intent.gateway_reference.as_deref().ok_or_else(|| {
+        PaymentError::Config("IME Pay payments need the token as gateway_reference".to_string())
+    })
+}
+
+/// Fails unless IME Pay answered for the transaction of `response`
+fn check_same_transaction(
+    answer: &ImePayConfirmation,
+    response: &ImePayResponse,
+    action: &str,
+) -> Result<(), PaymentError> {
+    if answer.ref_id != response.ref_id || answer.token_id != response.token_id {
+        return Err(PaymentError::InvalidResponse(format!(
+            "IME Pay {} a different transaction",
+            action
+        )));
+    }
+    Ok(())
+}
+
+/// Builder for [`ImePayClient`]
+#[derive(Debug)]
+pub struct ImePayClientBuilder {
+    merchant_code: String,
+    api_user: String,
+    password: MerchantSecret,
+    module: String,
+    environment: ImePayEnvironment,
+    timeout: Duration,
+    connect_timeout: Duration,
+    user_agent: Option<String>,
+    http_client: Option<Client>,
+    retry: RetryPolicy,
+}
+
+impl ImePayClientBuilder {
+    /// Starts a builder from environment variables
+    ///
+    /// Reads `IMEPAY_MERCHANT_CODE`, `IMEPAY_API_USER`, `IMEPAY_PASSWORD` and
+    /// `IMEPAY_MODULE` (all required), and the environment as described in
+    /// [`ImePayEnvironment::from_env`].
+    pub fn from_env() -> Result<Self, PaymentError> {
+        let required = |name: &str| {
+            read_env(name)?.ok_or_else(|| PaymentError::Config(format!("{} is not set", name)))
+        };
+
+        Ok(ImePayClient::builder(
+            required(ENV_MERCHANT_CODE)?,
+            required(ENV_API_USER)?,
+            MerchantSecret::from_env(ENV_PASSWORD)?,
+            required(ENV_MODULE)?,
+        )
+        .environment(ImePayEnvironment::from_env()?))
+    }
+
+    /// Sets the IME Pay environment (defaults to [`ImePayEnvironment::Sandbox`])
+    pub fn environment(mut self, environment: ImePayEnvironment) -> Self {
+        self.environment = environment;
+        self
+    }
+
+    /// Sets the total timeout for each request (defaults to 30 seconds)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the connection timeout (defaults to 10 seconds)
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Sets the `User-Agent` header sent to IME Pay
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Uses a preconfigured HTTP client; timeouts and user agent are then ignored
+    pub fn http_client(mut self, client: Client) -> Self {
+        self.http_client = Some(client);
+        self
+    }
+
+    /// Sets how failed requests are retried (defaults to [`RetryPolicy::default`])
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Builds the client
+    pub fn build(self) -> Result<ImePayClient, PaymentError> {
+        if self.merchant_code.is_empty() {
+            return Err(PaymentError::Config("merchant code is empty".to_string()));
+        }
+        if self.api_user.is_empty() || self.password.is_empty() {
+            return Err(PaymentError::Config(
+                "API user or password is empty".to_string(),
+            ));
+        }
+
+        let http = match self.http_client {
+            Some(client) => client,
+            None => build_client(self.timeout, self.connect_timeout, self.user_agent)?,
+        };
+
+        Ok(ImePayClient {
+            merchant_code: self.merchant_code,
+            api_user: self.api_user,
+            password: self.password,
+            module: self.module,
+            environment: self.environment,
+            http,
+            retry: self.retry,
+        })
+    }
+}
+
+/// Formats an amount with two decimals, as IME Pay expects
+fn format_amount(amount: Npr) -> String {
+    format!("{}.{:02}", amount.rupees(), amount.paisa() % 100)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn encode(fields: &str) -> String {
+        general_purpose::STANDARD.encode(fields)
+    }
+
+    fn client() -> ImePayClient {
+        ImePayClient::builder("TESTMERCHANT", "apiuser", "password", "TESTMODULE")
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_decode_response() {
+        let data = encode("0|Success|9800000000|123456789|ORDER-1|100.00|TOKEN-1");
+
+        let response = decode_imepay_response(&data).unwrap();
+
+        assert_eq!(response.response_code, ImePayStatus::Success);
+        assert_eq!(response.tran_amount, Npr::from_rupees(100));
+        assert_eq!(response.token_id, "TOKEN-1");
+    }
+
+    #[test]
+    fn test_decode_rejects_malformed_data() {
+        for data in [
+            "not base64!".to_string(),
+            encode("0|Success|too few"),
+            encode("x|a|b|c|d|1|e"),
+        ] {
+            assert!(
+                matches!(
+                    decode_imepay_response(&data),
+                    Err(PaymentError::Decode { .. })
+                ),
+                "{:?} should be rejected",
+                data
+            );
+        }
+    }
+
+    #[test]
+    fn test_response_from_posted_fields() {
+        let params: CallbackParams = [
+            ("ResponseCode", "3"),
+            ("ResponseDescription", "Cancelled"),
+            ("RefId", "ORDER-1"),
+            ("TranAmount", "100.00"),
+            ("TokenId", "TOKEN-1"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+        let response = ImePayResponse::from_params(&params).unwrap();
+
+        assert_eq!(response.response_code, ImePayStatus::Canceled);
+        assert_eq!(
+            PaymentStatus::from(&response.response_code),
+            PaymentStatus::Canceled
+        );
+    }
+
+    #[test]
+    fn test_token_response_accepts_string_codes() {
+        let body = r#"{"ResponseCode":"0","ResponseDescription":"Success","TokenId":"TOKEN-1","Amount":"100.50","RefId":"ORDER-1"}"#;
+        let token: ImePayTokenResponse = serde_json::from_str(body).unwrap();
+
+        assert_eq!(token.response_code, ImePayStatus::Success);
+        assert_eq!(token.amount, Npr::from_paisa(10050));
+    }
+
+    #[test]
+    fn test_checkout_form() {
+        let token = ImePayTokenResponse {
+            response_code: ImePayStatus::Success,
+            response_description: String::new(),
+            token_id: "TOKEN-1".to_string(),
+            amount: Npr::from_rupees(100),
+            ref_id: "ORDER-1".to_string(),
+        };
+
+        let form = client().checkout_form(&token, "http://shop.test/ok", "http://shop.test/cancel");
+
+        assert_eq!(
+            form.action,
+            "https://stg.imepay.com.np:7979/WebCheckout/Checkout"
+        );
+        assert_eq!(form.field("TranAmount"), Some("100.00"));
+        assert_eq!(form.field("MerchantCode"), Some("TESTMERCHANT"));
+    }
+
+    /// Serves `body` for every request and records the requested paths
+    async fn stub(body: &'static str) -> (ImePayClient, Arc<Mutex<Vec<String>>>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let paths = Arc::new(Mutex::new(Vec::new()));
+        let recorded = paths.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 4096];
+                // Read the head and as much of the body as Content-Length announces
+                loop {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request);
+                    if let Some(head_end) = text.find("\r\n\r\n") {
+                        let length = text[..head_end]
+                            .lines()
+                            .find_map(|line| {
+                                line.to_ascii_lowercase()
+                                    .strip_prefix("content-length:")
+                                    .map(|v| v.trim().parse::<usize>().unwrap())
+                            })
+                            .unwrap_or(0);
+                        if n == 0 || request.len() >= head_end + 4 + length {
+                            break;
+                        }
+                    }
+                }
+                let text = String::from_utf8_lossy(&request);
+                let path = text
+                    .split_whitespace()
+                    .nth(1)
+                    .unwrap_or_default()
+                    .to_string();
+                recorded.lock().unwrap().push(path);
+                let reply = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                stream.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+        let client = ImePayClient::builder("TESTMERCHANT", "apiuser", "password", "TESTMODULE")
+            .environment(ImePayEnvironment::Custom { base_url })
+            .build()
+            .unwrap();
+        (client, paths)
+    }
+
+    #[tokio::test]
+    async fn test_verify_callback_fails_closed_without_confirming() {
+        let expected = ExpectedPayment::new("ORDER-1", Npr::from_rupees(100));
+
+        let other_token =
+            decode_imepay_response(&encode("0|Success|98|1|ORDER-1|100.00|T2")).unwrap();
+        assert!(matches!(
+            client().verify_callback(&other_token, "T", &expected).await,
+            Err(PaymentError::TransactionUuidMismatch { .. })
+        ));
+
+        let other = decode_imepay_response(&encode("0|Success|98|1|ORDER-2|100.00|T")).unwrap();
+        assert!(matches!(
+            client().verify_callback(&other, "T", &expected).await,
+            Err(PaymentError::TransactionUuidMismatch { .. })
+        ));
+
+        let underpaid = decode_imepay_response(&encode("0|Success|98|1|ORDER-1|1.00|T")).unwrap();
+        assert!(matches!(
+            client().verify_callback(&underpaid, "T", &expected).await,
+            Err(PaymentError::AmountMismatch { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_verify_callback_rechecks_unsuccessful_response() {
+        let expected = ExpectedPayment::new("ORDER-1", Npr::from_rupees(100));
+        let failed = decode_imepay_response(&encode("1|Failed|98||ORDER-1|100.00|T")).unwrap();
+
+        let (client, paths) = stub(r#"{"ResponseCode":1,"RefId":"ORDER-1","TokenId":"T"}"#).await;
+        assert_eq!(
+            client
+                .verify_callback(&failed, "T", &expected)
+                .await
+                .unwrap(),
+            ImePayStatus::Failed
+        );
+        assert_eq!(*paths.lock().unwrap(), ["/api/Web/Recheck"]);
+
+        // The unsigned response code is not trusted over IME Pay's own answer
+        let (client, _) = stub(r#"{"ResponseCode":0,"RefId":"ORDER-1","TokenId":"T"}"#).await;
+        let canceled = decode_imepay_response(&encode("3|Cancelled|98||ORDER-1|100.00|T")).unwrap();
+        assert_eq!(
+            client
+                .verify_callback(&canceled, "T", &expected)
+                .await
+                .unwrap(),
+            ImePayStatus::Success
+        );
+
+        let (client, _) = stub(r#"{"ResponseCode":1,"RefId":"ORDER-9","TokenId":"T"}"#).await;
+        assert!(matches!(
+            client.verify_callback(&failed, "T", &expected).await,
+            Err(PaymentError::InvalidResponse(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_gateway_verify_callback_rejects_other_token() {
+        let (client, paths) = stub(r#"{"ResponseCode":0,"RefId":"ORDER-1","TokenId":"T"}"#).await;
+        let params: CallbackParams = [(
+            "data".to_string(),
+            encode("0|Success|98|1|ORDER-1|100.00|TOKEN-OF-ANOTHER-PAYMENT"),
+        )]
+        .into_iter()
+        .collect();
+        let intent = PaymentIntent::new(
+            "ORDER-1",
+            Npr::from_rupees(100),
+            "http://shop.test/ok",
+            "http://shop.test/failed",
+        );
+
+        let missing = PaymentGateway::verify_callback(&client, &params, &intent).await;
+        let intent = intent.with_gateway_reference("T");
+        let mismatch = PaymentGateway::verify_callback(&client, &params, &intent).await;
+
+        assert!(matches!(missing, Err(PaymentError::Config(_))));
+        match mismatch {
+            Err(PaymentError::TransactionUuidMismatch { expected, actual }) => {
+                assert_eq!(expected, "T");
+                assert_eq!(actual, "TOKEN-OF-ANOTHER-PAYMENT");
+            }
+            other => panic!("expected TransactionUuidMismatch, got {:?}", other),
+        }
+        assert!(paths.lock().unwrap().is_empty(), "nothing may be confirmed");
+
+        let params: CallbackParams = [(
+            "data".to_string(),
+            encode("0|Success|98|1|ORDER-1|100.00|T"),
+        )]
+        .into_iter()
+        .collect();
+        let outcome = PaymentGateway::verify_callback(&client, &params, &intent)
+            .await
+            .unwrap();
+        assert_eq!(outcome.status, PaymentStatus::Completed);
+        assert_eq!(*paths.lock().unwrap(), ["/api/Web/Confirm"]);
+    }
+}
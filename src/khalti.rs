@@ -0,0 +1,791 @@
+//! Khalti module implementing the ePayment (web checkout v2) API.
+//!
+//! The flow mirrors eSewa's: initiate a payment to obtain a `pidx` and a
+//! payment URL, redirect the customer, then confirm the callback with a
+//! lookup. Khalti callbacks are not signed, so they are only trusted after
+//! [`KhaltiClient::verify_callback`] has looked the payment up server-side.
+
+use crate::error::PaymentError;
+use crate::esewa::ExpectedPayment;
+use crate::gateway::{
+    required_param, CallbackParams, CustomerAction, Initiation, PaymentGateway, PaymentIntent,
+    PaymentOutcome, PaymentStatus,
+};
+use crate::http::{build_client, read_env, send_json, DEFAULT_CONNECT_TIMEOUT, DEFAULT_TIMEOUT};
+use crate::money::{self, Npr};
+use crate::retry::{Idempotency, RetryPolicy};
+use crate::secret::MerchantSecret;
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use zeroize::Zeroizing;
+
+/// Environment variable selecting the Khalti environment
+pub const ENV_ENVIRONMENT: &str = "KHALTI_ENVIRONMENT";
+/// Environment variable overriding the Khalti base URL
+pub const ENV_BASE_URL: &str = "KHALTI_BASE_URL";
+/// Environment variable holding the merchant live or test secret key
+pub const ENV_SECRET_KEY: &str = "KHALTI_SECRET_KEY";
+/// Environment variable holding the merchant website URL
+pub const ENV_WEBSITE_URL: &str = "KHALTI_WEBSITE_URL";
+
+/// Which Khalti environment to use for requests
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KhaltiEnvironment {
+    /// Use the Khalti sandbox (`dev.khalti.com`)
+    Sandbox,
+    /// Use the production Khalti API
+    Production,
+    /// Use another host exposing Khalti's API paths
+    Custom { base_url: String },
+}
+
+impl KhaltiEnvironment {
+    fn base_url(&self) -> &str {
+        match self {
+            KhaltiEnvironment::Sandbox => "https://dev.khalti.com",
+            KhaltiEnvironment::Production => "https://khalti.com",
+            KhaltiEnvironment::Custom { base_url } => base_url.trim_end_matches('/'),
+        }
+    }
+
+    /// URL of the payment initiation endpoint
+    pub fn initiate_url(&self) -> String {
+        format!("{}/api/v2/epayment/initiate/", self.base_url())
+    }
+
+    /// URL of the payment lookup endpoint
+    pub fn lookup_url(&self) -> String {
+        format!("{}/api/v2/epayment/lookup/", self.base_url())
+    }
+
+    /// Reads the environment from `KHALTI_BASE_URL` or `KHALTI_ENVIRONMENT`
+    ///
+    /// Behaves like [`EsewaEnvironment::from_env`](crate::EsewaEnvironment::from_env).
+    pub fn from_env() -> Result<Self, PaymentError> {
+        if let Some(base_url) = read_env(ENV_BASE_URL)? {
+            return Ok(KhaltiEnvironment::Custom { base_url });
+        }
+        match read_env(ENV_ENVIRONMENT)? {
+            Some(value) => value.parse(),
+            None => Ok(KhaltiEnvironment::Sandbox),
+        }
+    }
+}
+
+impl std::str::FromStr for KhaltiEnvironment {
+    type Err = PaymentError;
+
+    /// Parses `sandbox`, `production`, or an `http(s)://` base URL
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let value = s.trim();
+        match value.to_ascii_lowercase().as_str() {
+            "sandbox" | "test" | "dev" => Ok(KhaltiEnvironment::Sandbox),
+            "production" | "prod" | "live" => Ok(KhaltiEnvironment::Production),
+            lower if lower.starts_with("http://") || lower.starts_with("https://") => {
+                Ok(KhaltiEnvironment::Custom {
+                    base_url: value.to_string(),
+                })
+            }
+            _ => Err(PaymentError::Config(format!(
+                "Unknown Khalti environment {:?}; expected sandbox, production or a base URL",
+                value
+            ))),
+        }
+    }
+}
+
+/// Payment status reported by Khalti
+///
+/// Serialized as Khalti's wire string (`"Completed"`, `"User canceled"`, ...).
+/// Statuses this crate does not know yet deserialize to [`KhaltiStatus::Unknown`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum KhaltiStatus {
+    /// Payment completed; the order can be fulfilled
+    Completed,
+    /// Payment is being processed; look it up again later
+    Pending,
+    /// Payment link created but the customer has not paid
+    Initiated,
+    /// Payment refunded
+    Refunded,
+    /// Payment partially refunded
+    PartiallyRefunded,
+    /// Payment link expired before the customer paid
+    Expired,
+    /// The customer canceled the payment
+    UserCanceled,
+    /// A status not known to this version of the crate
+    Unknown(String),
+}
+
+impl KhaltiStatus {
+    /// The status string as Khalti sends it
+    pub fn as_str(&self) -> &str {
+        match self {
+            KhaltiStatus::Completed => "Completed",
+            KhaltiStatus::Pending => "Pending",
+            KhaltiStatus::Initiated => "Initiated",
+            KhaltiStatus::Refunded => "Refunded",
+            KhaltiStatus::PartiallyRefunded => "Partially Refunded",
+            KhaltiStatus::Expired => "Expired",
+            KhaltiStatus::UserCanceled => "User canceled",
+            KhaltiStatus::Unknown(status) => status,
+        }
+    }
+
+    /// Returns `true` if the payment attempt has a final outcome
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            KhaltiStatus::Completed
+                | KhaltiStatus::Refunded
+                | KhaltiStatus::PartiallyRefunded
+                | KhaltiStatus::Expired
+                | KhaltiStatus::UserCanceled
+        )
+    }
+
+    /// Returns `true` if the customer has paid
+    pub fn is_success(&self) -> bool {
+        matches!(self, KhaltiStatus::Completed)
+    }
+}
+
+impl From<&str> for KhaltiStatus {
+    fn from(status: &str) -> Self {
+        match status {
+            "Completed" => KhaltiStatus::Completed,
+            "Pending" => KhaltiStatus::Pending,
+            "Initiated" => KhaltiStatus::Initiated,
+            "Refunded" => KhaltiStatus::Refunded,
+            "Partially Refunded" => KhaltiStatus::PartiallyRefunded,
+            "Expired" => KhaltiStatus::Expired,
+            "User canceled" => KhaltiStatus::UserCanceled,
+            other => KhaltiStatus::Unknown(other.to_string()),
+        }
+    }
+}
+
+impl From<&KhaltiStatus> for PaymentStatus {
+    fn from(status: &KhaltiStatus) -> Self {
+        match status {
+            KhaltiStatus::Completed => PaymentStatus::Completed,
+            KhaltiStatus::Pending | KhaltiStatus::Initiated => PaymentStatus::Pending,
+            KhaltiStatus::Refunded => PaymentStatus::Refunded,
+            KhaltiStatus::PartiallyRefunded => PaymentStatus::PartiallyRefunded,
+            KhaltiStatus::Expired => PaymentStatus::Failed,
+            KhaltiStatus::UserCanceled => PaymentStatus::Canceled,
+            KhaltiStatus::Unknown(_) => PaymentStatus::Ambiguous,
+        }
+    }
+}
+
+impl std::fmt::Display for KhaltiStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for KhaltiStatus {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for KhaltiStatus {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let status = String::deserialize(deserializer)?;
+        Ok(KhaltiStatus::from(status.as_str()))
+    }
+}
+
+/// Optional customer details shown on the Khalti checkout page
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KhaltiCustomerInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phone: Option<String>,
+}
+
+/// Body of the payment initiation request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KhaltiInitiateRequest {
+    /// URL Khalti redirects to after the payment, with the callback parameters
+    pub return_url: String,
+    /// Merchant website URL (filled in by [`KhaltiClient`])
+    pub website_url: String,
+    /// Amount to collect; sent to Khalti in paisa
+    #[serde(with = "money::paisa")]
+    pub amount: Npr,
+    /// Merchant-side order identifier
+    pub purchase_order_id: String,
+    /// Merchant-side order name
+    pub purchase_order_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub customer_info: Option<KhaltiCustomerInfo>,
+}
+
+impl KhaltiInitiateRequest {
+    /// Creates a request without customer details
+    pub fn new(
+        purchase_order_id: impl Into<String>,
+        purchase_order_name: impl Into<String>,
+        amount: Npr,
+        return_url: impl Into<String>,
+    ) -> Self {
+        KhaltiInitiateRequest {
+            return_url: return_url.into(),
+            website_url: String::new(),
+            amount,
+            purchase_order_id: purchase_order_id.into(),
+            purchase_order_name: purchase_order_name.into(),
+            customer_info: None,
+        }
+    }
+}
+
+/// Response of the payment initiation request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KhaltiInitiateResponse {
+    /// Khalti's payment identifier, needed for lookups
+    pub pidx: String,
+    /// URL to redirect the customer to
+    pub payment_url: String,
+    /// Expiry timestamp of the payment link, as sent by Khalti
+    pub expires_at: String,
+    /// Seconds until the payment link expires
+    pub expires_in: u64,
+}
+
+/// Response of the payment lookup request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KhaltiLookupResponse {
+    pub pidx: String,
+    #[serde(with = "money::paisa")]
+    pub total_amount: Npr,
+    pub status: KhaltiStatus,
+    /// Khalti transaction ID, present once the customer has paid
+    #[serde(default)]
+    pub transaction_id: Option<String>,
+    #[serde(with = "money::paisa")]
+    pub fee: Npr,
+    pub refunded: bool,
+}
+
+/// Query parameters Khalti appends to the return URL
+///
+/// These are not signed; confirm them with [`KhaltiClient::verify_callback`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KhaltiCallback {
+    pub pidx: String,
+    pub status: KhaltiStatus,
+    pub transaction_id: Option<String>,
+    pub amount: Option<Npr>,
+    pub total_amount: Option<Npr>,
+    pub mobile: Option<String>,
+    pub purchase_order_id: String,
+    pub purchase_order_name: Option<String>,
+}
+
+impl KhaltiCallback {
+    /// Parses the callback from the return URL's query parameters
+    pub fn from_params(params: &CallbackParams) -> Result<Self, PaymentError> {
+        let paisa = |name: &str| -> Result<Option<Npr>, PaymentError> {
+            params
+                .get(name)
+                .filter(|value| !value.is_empty())
+                .map(|value| {
+                    value
+                        .trim()
+                        .parse::<u64>()
+                        .map(Npr::from_paisa)
+                        .map_err(|e| {
+                            PaymentError::decode(
+                                format!("`{}` is not a paisa amount: {:?}", name, value),
+                                e,
+                            )
+                        })
+                })
+                .transpose()
+        };
+        let optional = |name: &str| params.get(name).filter(|value| !value.is_empty()).cloned();
+
+        Ok(KhaltiCallback {
+            pidx: required_param(params, "pidx")?.to_string(),
+            status: KhaltiStatus::from(required_param(params, "status")?),
+            // Older callbacks only carry `txnId`
+            transaction_id: optional("transaction_id").or_else(|| optional("txnId")),
+            amount: paisa("amount")?,
+            total_amount: paisa("total_amount")?,
+            mobile: optional("mobile"),
+            purchase_order_id: required_param(params, "purchase_order_id")?.to_string(),
+            purchase_order_name: optional("purchase_order_name"),
+        })
+    }
+}
+
+/// Reusable Khalti client holding the merchant configuration and a pooled HTTP client
+///
+/// Create one per process with [`KhaltiClient::builder`] and share it; cloning is cheap.
+#[derive(Clone)]
+pub struct KhaltiClient {
+    secret_key: MerchantSecret,
+    website_url: String,
+    environment: KhaltiEnvironment,
+    http: Client,
+    retry: RetryPolicy,
+}
+
+impl std::fmt::Debug for KhaltiClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KhaltiClient")
+            .field("website_url", &self.website_url)
+            .field("environment", &self.environment)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KhaltiClient {
+    /// Starts building a client for the given secret key and merchant website
+    pub fn builder(
+        secret_key: impl Into<MerchantSecret>,
+        website_url: impl Into<String>,
+    ) -> KhaltiClientBuilder {
+        KhaltiClientBuilder {
+            secret_key: secret_key.into(),
+            website_url: website_url.into(),
+            environment: KhaltiEnvironment::Sandbox,
+            timeout: DEFAULT_TIMEOUT,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            user_agent: None,
+            http_client: None,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Environment this client talks to
+    pub fn environment(&self) -> &KhaltiEnvironment {
+        &self.environment
+    }
+
+    /// Initiates a payment and returns the `pidx` and payment URL
+    ///
+    /// The request's `website_url` is replaced with the client's.
+    pub async fn initiate_payment(
+        &self,
+        request: &KhaltiInitiateRequest,
+    ) -> Result<KhaltiInitiateResponse, PaymentError> {
+        let mut body = request.clone();
+        body.website_url = self.website_url.clone();
+
+        let authorization = self.authorization();
+        let request = self
+            .http
+            .post(self.environment.initiate_url())
+            .header(reqwest::header::AUTHORIZATION, authorization.as_str())
+            .json(&body);
+
+        send_json(&self.retry, Idempotency::NotIdempotent, request).await
+    }
+
+    /// Looks up a payment by its `pidx`
+    pub async fn lookup(&self, pidx: &str) -> Result<KhaltiLookupResponse, PaymentError> {
+        let authorization = self.authorization();
+        let request = self
+            .http
+            .post(self.environment.lookup_url())
+            .header(reqwest::header::AUTHORIZATION, authorization.as_str())
+            .json(&serde_json::json!({ "pidx": pidx }));
+
+        send_json(&self.retry, Idempotency::Idempotent, request).await
+    }
+
+    /// Confirms a callback with a lookup and checks it against the expected order
+    ///
+    /// Fails closed: the callback must carry the `pidx` returned by
+    /// [`KhaltiClient::initiate_payment`] for this order and belong to the
+    /// expected order, and the looked-up total must match. The lookup does not
+    /// name the order, so without the `pidx` another completed payment of the
+    /// same amount could be passed off as this one. The returned lookup, not
+    /// the unsigned callback, is authoritative for the status.
+    pub async fn verify_callback(
+        &self,
+        callback: &KhaltiCallback,
+        pidx: &str,
+        expected: &ExpectedPayment,
+    ) -> Result<KhaltiLookupResponse, PaymentError> {
+        if callback.pidx != pidx {
+            return Err(PaymentError::TransactionUuidMismatch {
+                expected: pidx.to_string(),
+                actual: callback.pidx.clone(),
+            });
+        }
+        if callback.purchase_order_id != expected.transaction_uuid {
+            return Err(PaymentError::TransactionUuidMismatch {
+                expected: expected.transaction_uuid.clone(),
+                actual: callback.purchase_order_id.clone(),
+            });
+        }
+
+        let lookup = self.lookup(pidx).await?;
+        if lookup.pidx != pidx {
+            return Err(PaymentError::TransactionUuidMismatch {
+                expected: pidx.to_string(),
+                actual: lookup.pidx,
+            });
+        }
+        if lookup.total_amount != expected.total_amount {
+            return Err(PaymentError::AmountMismatch {
+                expected: expected.total_amount,
+                actual: lookup.total_amount,
+            });
+        }
+
+        Ok(lookup)
+    }
+
+    fn authorization(&self) -> Zeroizing<String> {
+        Zeroizing::new(format!("Key {}", self.secret_key.expose_secret()))
+    }
+}
+
+/// Khalti as a [`PaymentGateway`]
+///
+/// Initiation returns a redirect and the `pidx` as the gateway reference;
+/// callback verification and lookups require that reference on the
+/// [`PaymentIntent`].
+#[async_trait]
+impl PaymentGateway for KhaltiClient {
+    fn name(&self) -> &'static str {
+        "khalti"
+    }
+
+    async fn initiate(&self, intent: &PaymentIntent) -> Result<Initiation, PaymentError> {
+        let request = KhaltiInitiateRequest::new(
+            intent.transaction_id.clone(),
+            intent
+                .description
+                .clone()
+                .unwrap_or_else(|| intent.transaction_id.clone()),
+            intent.amount,
+            intent.success_url.clone(),
+        );
+        let response = self.initiate_payment(&request).await?;
+
+        Ok(Initiation {
+            action: CustomerAction::Redirect(response.payment_url),
+            gateway_reference: Some(response.pidx),
+        })
+    }
+
+    async fn verify_callback(
+        &self,
+        params: &CallbackParams,
+        intent: &PaymentIntent,
+    ) -> Result<PaymentOutcome, PaymentError> {
+        let callback = KhaltiCallback::from_params(params)?;
+        let pidx = gateway_reference(intent)?;
+        let expected = ExpectedPayment::new(intent.transaction_id.clone(), intent.amount);
+        let lookup = KhaltiClient::verify_callback(self, &callback, pidx, &expected).await?;
+
+        Ok(outcome(self.name(), &intent.transaction_id, lookup))
+    }
+
+    async fn lookup_status(&self, intent: &PaymentIntent) -> Result<PaymentOutcome, PaymentError> {
+        let lookup = self.lookup(gateway_reference(intent)?).await?;
+
+        Ok(outcome(self.name(), &intent.transaction_id, lookup))
+    }
+}
+
+/// The `pidx` issued at initiation
+fn gateway_reference(intent: &PaymentIntent) -> Result<&str, PaymentError> {
+    intent.gateway_reference.as_deref().ok_or_else(|| {
+        PaymentError::Config("Khalti payments need the pidx as gateway_reference".to_string())
+    })
+}
+
+fn outcome(gateway: &str, transaction_id: &str, lookup: KhaltiLookupResponse) -> PaymentOutcome {
+    PaymentOutcome {
+        gateway: gateway.to_string(),
+        transaction_id: transaction_id.to_string(),
+        gateway_reference: lookup.transaction_id.or(Some(lookup.pidx)),
+        amount: Some(lookup.total_amount),
+        status: PaymentStatus::from(&lookup.status),
+    }
+}
+
+/// Builder for [`KhaltiClient`]
+#[derive(Debug)]
+pub struct KhaltiClientBuilder {
+    secret_key: MerchantSecret,
+    website_url: String,
+    environment: KhaltiEnvironment,
+    timeout: Duration,
+    connect_timeout: Duration,
+    user_agent: Option<String>,
+    http_client: Option<Client>,
+    retry: RetryPolicy,
+}
+
+impl KhaltiClientBuilder {
+    /// Starts a builder from environment variables
+    ///
+    /// Reads `KHALTI_SECRET_KEY` and `KHALTI_WEBSITE_URL` (both required) and
+    /// the environment as described in [`KhaltiEnvironment::from_env`].
+    pub fn from_env() -> Result<Self, PaymentError> {
+        let secret_key = MerchantSecret::from_env(ENV_SECRET_KEY)?;
+        let website_url = read_env(ENV_WEBSITE_URL)?
+            .ok_or_else(|| PaymentError::Config(format!("{} is not set", ENV_WEBSITE_URL)))?;
+
+        Ok(KhaltiClient::builder(secret_key, website_url)
+            .environment(KhaltiEnvironment::from_env()?))
+    }
+
+    /// Sets the Khalti environment (defaults to [`KhaltiEnvironment::Sandbox`])
+    pub fn environment(mut self, environment: KhaltiEnvironment) -> Self {
+        self.environment = environment;
+        self
+    }
+
+    /// Sets the total timeout for each request (defaults to 30 seconds)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the connection timeout (defaults to 10 seconds)
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Sets the `User-Agent` header sent to Khalti
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Uses a preconfigured HTTP client; timeouts and user agent are then ignored
+    pub fn http_client(mut self, client: Client) -> Self {
+        self.http_client = Some(client);
+        self
+    }
+
+    /// Sets how failed requests are retried (defaults to [`RetryPolicy::default`])
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Builds the client
+    pub fn build(self) -> Result<KhaltiClient, PaymentError> {
+        if self.secret_key.is_empty() {
+            return Err(PaymentError::Config("secret key is empty".to_string()));
+        }
+        if self.website_url.is_empty() {
+            return Err(PaymentError::Config("website URL is empty".to_string()));
+        }
+
+        let http = match self.http_client {
+            Some(client) => client,
+            None => build_client(self.timeout, self.connect_timeout, self.user_agent)?,
+        };
+
+        Ok(KhaltiClient {
+            secret_key: self.secret_key,
+            website_url: self.website_url,
+            environment: self.environment,
+            http,
+            retry: self.retry,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_environment_urls() {
+        assert_eq!(
+            KhaltiEnvironment::Sandbox.initiate_url(),
+            "https://dev.khalti.com/api/v2/epayment/initiate/"
+        );
+        assert_eq!(
+            KhaltiEnvironment::Production.lookup_url(),
+            "https://khalti.com/api/v2/epayment/lookup/"
+        );
+        assert_eq!(
+            "http://127.0.0.1:9000/"
+                .parse::<KhaltiEnvironment>()
+                .unwrap()
+                .lookup_url(),
+            "http://127.0.0.1:9000/api/v2/epayment/lookup/"
+        );
+    }
+
+    #[test]
+    fn test_initiate_request_serializes_paisa() {
+        let mut request = KhaltiInitiateRequest::new(
+            "order-1",
+            "Test order",
+            Npr::from_rupees(10),
+            "http://shop.test/return",
+        );
+        request.website_url = "http://shop.test".to_string();
+
+        let json = serde_json::to_value(&request).unwrap();
+
+        assert_eq!(json["amount"], 1000);
+        assert_eq!(json["purchase_order_id"], "order-1");
+        assert!(json.get("customer_info").is_none());
+    }
+
+    #[test]
+    fn test_lookup_response_deserialization() {
+        let body = r#"{"pidx":"HT6o6PEZRWFJ5ygavzHWd5","total_amount":1000,"status":"User canceled","transaction_id":null,"fee":0,"refunded":false}"#;
+        let lookup: KhaltiLookupResponse = serde_json::from_str(body).unwrap();
+
+        assert_eq!(lookup.status, KhaltiStatus::UserCanceled);
+        assert_eq!(lookup.total_amount, Npr::from_rupees(10));
+        assert_eq!(PaymentStatus::from(&lookup.status), PaymentStatus::Canceled);
+    }
+
+    #[test]
+    fn test_callback_from_params() {
+        let params: CallbackParams = [
+            ("pidx", "bZQLD9wRVWo4CdESSfuSsB"),
+            ("txnId", "4H7AhoXDJWg5WjrcPT9ixW"),
+            ("amount", "1000"),
+            ("total_amount", "1000"),
+            ("status", "Completed"),
+            ("mobile", "98XXXXX904"),
+            ("purchase_order_id", "order-1"),
+            ("purchase_order_name", "Test order"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+        let callback = KhaltiCallback::from_params(&params).unwrap();
+
+        assert_eq!(callback.status, KhaltiStatus::Completed);
+        assert_eq!(
+            callback.transaction_id.as_deref(),
+            Some("4H7AhoXDJWg5WjrcPT9ixW")
+        );
+        assert_eq!(callback.total_amount, Some(Npr::from_rupees(10)));
+
+        let mut missing = params.clone();
+        missing.remove("pidx");
+        assert!(matches!(
+            KhaltiCallback::from_params(&missing),
+            Err(PaymentError::InvalidResponse(_))
+        ));
+    }
+
+    #[test]
+    fn test_status_wire_strings() {
+        let status: KhaltiStatus = serde_json::from_str("\"Partially Refunded\"").unwrap();
+        assert_eq!(status, KhaltiStatus::PartiallyRefunded);
+
+        let status: KhaltiStatus = serde_json::from_str("\"On hold\"").unwrap();
+        assert_eq!(status, KhaltiStatus::Unknown("On hold".to_string()));
+        assert!(!status.is_terminal());
+        assert!(KhaltiStatus::Expired.is_terminal());
+    }
+
+    #[tokio::test]
+    async fn test_verify_callback_rejects_other_order() {
+        let client = KhaltiClient::builder("test-key", "http://shop.test")
+            .build()
+            .unwrap();
+        let callback = KhaltiCallback {
+            pidx: "pidx-1".to_string(),
+            status: KhaltiStatus::Completed,
+            transaction_id: None,
+            amount: None,
+            total_amount: None,
+            mobile: None,
+            purchase_order_id: "order-2".to_string(),
+            purchase_order_name: None,
+        };
+        let expected = ExpectedPayment::new("order-1", Npr::from_rupees(10));
+
+        let result = client.verify_callback(&callback, "pidx-1", &expected).await;
+
+        assert!(matches!(
+            result,
+            Err(PaymentError::TransactionUuidMismatch { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_verify_callback_rejects_other_pidx() {
+        let client = KhaltiClient::builder("test-key", "http://shop.test")
+            .build()
+            .unwrap();
+        let callback = KhaltiCallback {
+            pidx: "pidx-of-another-payment".to_string(),
+            status: KhaltiStatus::Completed,
+            transaction_id: None,
+            amount: None,
+            total_amount: None,
+            mobile: None,
+            purchase_order_id: "order-1".to_string(),
+            purchase_order_name: None,
+        };
+        let expected = ExpectedPayment::new("order-1", Npr::from_rupees(10));
+
+        let result = client.verify_callback(&callback, "pidx-1", &expected).await;
+
+        match result {
+            Err(PaymentError::TransactionUuidMismatch { expected, actual }) => {
+                assert_eq!(expected, "pidx-1");
+                assert_eq!(actual, "pidx-of-another-payment");
+            }
+            other => panic!("expected TransactionUuidMismatch, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_gateway_verify_callback_rejects_other_pidx() {
+        let client = KhaltiClient::builder("test-key", "http://shop.test")
+            .build()
+            .unwrap();
+        let params: CallbackParams = [
+            ("pidx", "pidx-of-another-payment"),
+            ("status", "Completed"),
+            ("total_amount", "1000"),
+            ("purchase_order_id", "order-1"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        let intent = PaymentIntent::new(
+            "order-1",
+            Npr::from_rupees(10),
+            "http://shop.test/ok",
+            "http://shop.test/failed",
+        );
+
+        let missing = PaymentGateway::verify_callback(&client, &params, &intent).await;
+        let intent = intent.with_gateway_reference("pidx-1");
+        let mismatch = PaymentGateway::verify_callback(&client, &params, &intent).await;
+
+        assert!(matches!(missing, Err(PaymentError::Config(_))));
+        match mismatch {
+            Err(PaymentError::TransactionUuidMismatch { expected, actual }) => {
+                assert_eq!(expected, "pidx-1");
+                assert_eq!(actual, "pidx-of-another-payment");
+            }
+            other => panic!("expected TransactionUuidMismatch, got {:?}", other),
+        }
+    }
+}
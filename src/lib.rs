@@ -1,16 +1,85 @@
-//! Top-level library that re-exports the `esewa` module.
+//! Top-level library that re-exports the payment provider modules
+//! (`connectips`, `esewa`, `fonepay`, `imepay`, `khalti`) and the shared
+//! `checkout`, `error`, `form`, `gateway`, `lifecycle`, `money`, `replay`,
+//! `retry`, `secret` and `store` modules.
 
+pub mod checkout;
+pub mod connectips;
+pub mod error;
 pub mod esewa;
+pub mod fonepay;
+pub mod form;
+pub mod gateway;
+mod http;
+pub mod imepay;
+pub mod khalti;
+pub mod lifecycle;
+pub mod money;
+pub mod replay;
+pub mod retry;
+pub mod secret;
+pub mod store;
+
+#[cfg(feature = "actix-web")]
+pub mod actix_web;
+#[cfg(feature = "axum")]
+pub mod axum;
+#[cfg(feature = "mock")]
+pub mod mock;
 
 // Re-export commonly used items so existing code and docs keep working
 pub use esewa::{
     pay_with_esewa,
+    build_checkout_form,
+    check_transaction_status,
     generate_transaction_uuid,
     generate_signature,
+    generate_signature_for_fields,
     validate_esewa_response,
+    validate_esewa_response_with_keys,
+    verify_esewa_response,
+    verify_esewa_response_with_keys,
     EsewaPaymentRequest,
+    EsewaPaymentRequestBuilder,
     EsewaPaymentResponse,
     ValidationResult,
+    KeySetValidation,
+    ExpectedPayment,
+    VerifiedPayment,
+    EsewaClient,
+    EsewaClientBuilder,
     EsewaEnvironment,
-    PaymentError,
+    EsewaStatus,
+    EsewaStatusResponse,
+};
+
+pub use error::{BoxError, PaymentError};
+pub use checkout::{CheckoutHooks, CheckoutPage, EsewaCheckout, EsewaCheckoutBuilder};
+pub use connectips::{
+    ConnectIpsClient, ConnectIpsClientBuilder, ConnectIpsEnvironment, ConnectIpsKey, ConnectIpsPaymentRequest,
+    ConnectIpsStatus, ConnectIpsValidation, ConnectIpsValidationResult,
+};
+pub use fonepay::{
+    verify_fonepay_return, FonepayClient, FonepayClientBuilder, FonepayEnvironment, FonepayPaymentRequest, FonepayQrRequest,
+    FonepayQrResponse, FonepayQrStatus, FonepayQrStatusResponse, FonepayReturn,
+};
+pub use form::CheckoutForm;
+pub use imepay::{
+    decode_imepay_response, ImePayClient, ImePayClientBuilder, ImePayConfirmation, ImePayEnvironment, ImePayResponse,
+    ImePayStatus, ImePayTokenResponse,
+};
+pub use khalti::{
+    KhaltiCallback, KhaltiClient, KhaltiClientBuilder, KhaltiCustomerInfo, KhaltiEnvironment, KhaltiInitiateRequest,
+    KhaltiInitiateResponse, KhaltiLookupResponse, KhaltiStatus,
+};
+pub use gateway::{
+    CallbackParams, CustomerAction, Initiation, PaymentGateway, PaymentIntent, PaymentOutcome, PaymentStatus,
 };
+pub use lifecycle::{PaymentLifecycle, PaymentState};
+pub use money::Npr;
+pub use secret::{EnvSecretProvider, MatchedKey, MerchantSecret, SecretKeySet, SecretProvider};
+pub use retry::RetryPolicy;
+pub use replay::{CallbackKey, CallbackOutcome, CallbackProcessor, DedupStore, MemoryDedupStore};
+pub use store::{verify_esewa_callback, MemoryTransactionStore, TransactionRecord, TransactionStore};
+#[cfg(feature = "sqlite")]
+pub use store::SqliteTransactionStore;
@@ -0,0 +1,357 @@
+//! Payment lifecycle state machine.
+//!
+//! A payment moves `Created → Initiated → Pending` and then settles as
+//! `Completed`, `Failed` or `Canceled`; completed payments may later be
+//! refunded. `Ambiguous` covers gateway answers that cannot be classified
+//! yet. [`PaymentState::transition_to`] holds the rules and rejects anything
+//! else with [`PaymentError::InvalidTransition`], e.g. `Completed → Pending`.
+
+use crate::error::PaymentError;
+use crate::esewa::{EsewaStatus, EsewaStatusResponse, ValidationResult, VerifiedPayment};
+use crate::gateway::{PaymentOutcome, PaymentStatus};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// State of a payment in its lifecycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PaymentState {
+    /// Recorded locally; the customer has not been sent to the gateway
+    Created,
+    /// The customer was sent to the gateway
+    Initiated,
+    /// The gateway is processing the payment
+    Pending,
+    /// Paid in full
+    Completed,
+    /// Failed, expired or not found at the gateway
+    Failed,
+    /// Canceled by the customer or the gateway
+    Canceled,
+    /// Fully refunded
+    Refunded,
+    /// Partially refunded; may be refunded further
+    PartiallyRefunded,
+    /// The gateway could not tell; check again later
+    Ambiguous,
+}
+
+impl PaymentState {
+    /// Returns `true` if no further transition is allowed
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            PaymentState::Failed | PaymentState::Canceled | PaymentState::Refunded
+        )
+    }
+
+    /// Returns `true` if the payment has settled one way or the other
+    ///
+    /// Unlike [`is_terminal`](Self::is_terminal) this includes `Completed`
+    /// and `PartiallyRefunded`, which can still be refunded.
+    pub fn is_settled(&self) -> bool {
+        !matches!(
+            self,
+            PaymentState::Created
+                | PaymentState::Initiated
+                | PaymentState::Pending
+                | PaymentState::Ambiguous
+        )
+    }
+
+    /// Returns `true` if moving to `next` is allowed
+    ///
+    /// Staying in the same state is always allowed, so re-delivered
+    /// callbacks and repeated status checks are harmless.
+    pub fn can_transition_to(&self, next: PaymentState) -> bool {
+        use PaymentState::*;
+
+        if *self == next {
+            return true;
+        }
+        match self {
+            Created => matches!(next, Initiated | Failed | Canceled),
+            // A refund reported by a status check implies the payment completed
+            Initiated | Pending | Ambiguous => !matches!(next, Created | Initiated),
+            Completed => matches!(next, Refunded | PartiallyRefunded),
+            PartiallyRefunded => matches!(next, Refunded),
+            Failed | Canceled | Refunded => false,
+        }
+    }
+
+    /// Returns `next` if the transition is allowed
+    pub fn transition_to(self, next: PaymentState) -> Result<PaymentState, PaymentError> {
+        if self.can_transition_to(next) {
+            Ok(next)
+        } else {
+            Err(PaymentError::InvalidTransition {
+                from: self,
+                to: next,
+            })
+        }
+    }
+}
+
+impl From<PaymentStatus> for PaymentState {
+    fn from(status: PaymentStatus) -> Self {
+        match status {
+            PaymentStatus::Pending => PaymentState::Pending,
+            PaymentStatus::Completed => PaymentState::Completed,
+            PaymentStatus::Failed => PaymentState::Failed,
+            PaymentStatus::Canceled => PaymentState::Canceled,
+            PaymentStatus::Refunded => PaymentState::Refunded,
+            PaymentStatus::PartiallyRefunded => PaymentState::PartiallyRefunded,
+            PaymentStatus::Ambiguous => PaymentState::Ambiguous,
+        }
+    }
+}
+
+impl From<&EsewaStatus> for PaymentState {
+    fn from(status: &EsewaStatus) -> Self {
+        PaymentState::from(PaymentStatus::from(status))
+    }
+}
+
+impl fmt::Display for PaymentState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl std::str::FromStr for PaymentState {
+    type Err = PaymentError;
+
+    /// Parses the [`Display`](fmt::Display) form, e.g. `"PartiallyRefunded"`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use PaymentState::*;
+
+        [
+            Created,
+            Initiated,
+            Pending,
+            Completed,
+            Failed,
+            Canceled,
+            Refunded,
+            PartiallyRefunded,
+            Ambiguous,
+        ]
+        .into_iter()
+        .find(|state| state.to_string() == s)
+        .ok_or_else(|| PaymentError::Decode {
+            context: format!("Unknown payment state: {:?}", s),
+            source: None,
+        })
+    }
+}
+
+/// A payment's current state together with its transaction ID
+///
+/// The `apply_*` methods feed gateway results through
+/// [`PaymentState::transition_to`] and only change the state when the
+/// result belongs to this payment and the transition is allowed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaymentLifecycle {
+    transaction_id: String,
+    state: PaymentState,
+}
+
+impl PaymentLifecycle {
+    /// Starts tracking a payment in [`PaymentState::Created`]
+    pub fn new(transaction_id: impl Into<String>) -> Self {
+        PaymentLifecycle {
+            transaction_id: transaction_id.into(),
+            state: PaymentState::Created,
+        }
+    }
+
+    /// Resumes tracking a payment in a previously stored state
+    pub fn resume(transaction_id: impl Into<String>, state: PaymentState) -> Self {
+        PaymentLifecycle {
+            transaction_id: transaction_id.into(),
+            state,
+        }
+    }
+
+    /// Transaction ID of the tracked payment
+    pub fn transaction_id(&self) -> &str {
+        &self.transaction_id
+    }
+
+    /// Current state
+    pub fn state(&self) -> PaymentState {
+        self.state
+    }
+
+    /// Moves to `next` if allowed and returns the new state
+    pub fn transition(&mut self, next: PaymentState) -> Result<PaymentState, PaymentError> {
+        self.state = self.state.transition_to(next)?;
+        Ok(self.state)
+    }
+
+    /// Records that the customer was sent to the gateway
+    pub fn mark_initiated(&mut self) -> Result<PaymentState, PaymentError> {
+        self.transition(PaymentState::Initiated)
+    }
+
+    /// Applies a [`validate_esewa_response`](crate::validate_esewa_response) result
+    ///
+    /// Results with an invalid signature or for another transaction are
+    /// rejected without changing the state.
+    pub fn apply_validation(
+        &mut self,
+        result: &ValidationResult,
+    ) -> Result<PaymentState, PaymentError> {
+        if !result.signature_valid {
+            return Err(PaymentError::SignatureMismatch);
+        }
+        self.check_transaction(&result.response.transaction_uuid)?;
+        self.transition(PaymentState::from(&result.response.status))
+    }
+
+    /// Applies a strictly verified eSewa callback
+    pub fn apply_verified(
+        &mut self,
+        payment: &VerifiedPayment,
+    ) -> Result<PaymentState, PaymentError> {
+        self.check_transaction(payment.transaction_uuid())?;
+        self.transition(PaymentState::from(payment.status()))
+    }
+
+    /// Applies an eSewa status check result
+    pub fn apply_status(
+        &mut self,
+        status: &EsewaStatusResponse,
+    ) -> Result<PaymentState, PaymentError> {
+        self.check_transaction(&status.transaction_uuid)?;
+        self.transition(PaymentState::from(&status.status))
+    }
+
+    /// Applies a gateway-agnostic [`PaymentOutcome`]
+    pub fn apply_outcome(
+        &mut self,
+        outcome: &PaymentOutcome,
+    ) -> Result<PaymentState, PaymentError> {
+        self.check_transaction(&outcome.transaction_id)?;
+        self.transition(PaymentState::from(outcome.status))
+    }
+
+    fn check_transaction(&self, transaction_id: &str) -> Result<(), PaymentError> {
+        if transaction_id != self.transaction_id {
+            return Err(PaymentError::TransactionUuidMismatch {
+                expected: self.transaction_id.clone(),
+                actual: transaction_id.to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::esewa::EsewaPaymentResponse;
+    use crate::money::Npr;
+
+    fn validation(status: EsewaStatus, signature_valid: bool) -> ValidationResult {
+        ValidationResult {
+            signature_valid,
+            response: EsewaPaymentResponse {
+                transaction_code: "000AWEO".to_string(),
+                status,
+                total_amount: Npr::from_rupees(100),
+                transaction_uuid: "uuid-1".to_string(),
+                product_code: "EPAYTEST".to_string(),
+                signed_field_names: "transaction_code,status".to_string(),
+                signature: String::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_happy_path() {
+        let mut payment = PaymentLifecycle::new("uuid-1");
+        payment.mark_initiated().unwrap();
+        payment.transition(PaymentState::Pending).unwrap();
+        payment
+            .apply_validation(&validation(EsewaStatus::Complete, true))
+            .unwrap();
+        payment.transition(PaymentState::PartiallyRefunded).unwrap();
+        payment.transition(PaymentState::Refunded).unwrap();
+
+        assert_eq!(payment.state(), PaymentState::Refunded);
+        assert!(payment.state().is_terminal());
+    }
+
+    #[test]
+    fn test_illegal_transitions_are_rejected() {
+        for (from, to) in [
+            (PaymentState::Completed, PaymentState::Pending),
+            (PaymentState::Completed, PaymentState::Failed),
+            (PaymentState::Failed, PaymentState::Completed),
+            (PaymentState::Canceled, PaymentState::Initiated),
+            (PaymentState::Refunded, PaymentState::Completed),
+            (PaymentState::Created, PaymentState::Completed),
+            (PaymentState::PartiallyRefunded, PaymentState::Completed),
+            (PaymentState::Pending, PaymentState::Initiated),
+        ] {
+            assert!(
+                matches!(
+                    from.transition_to(to),
+                    Err(PaymentError::InvalidTransition { from: f, to: t }) if f == from && t == to
+                ),
+                "{} -> {} should be rejected",
+                from,
+                to
+            );
+        }
+    }
+
+    #[test]
+    fn test_repeated_results_are_idempotent() {
+        let mut payment = PaymentLifecycle::resume("uuid-1", PaymentState::Completed);
+
+        assert_eq!(
+            payment
+                .apply_validation(&validation(EsewaStatus::Complete, true))
+                .unwrap(),
+            PaymentState::Completed
+        );
+        assert!(payment
+            .apply_validation(&validation(EsewaStatus::Pending, true))
+            .is_err());
+        assert_eq!(payment.state(), PaymentState::Completed);
+    }
+
+    #[test]
+    fn test_invalid_or_foreign_results_leave_state_unchanged() {
+        let mut payment = PaymentLifecycle::resume("uuid-1", PaymentState::Initiated);
+
+        assert!(matches!(
+            payment.apply_validation(&validation(EsewaStatus::Complete, false)),
+            Err(PaymentError::SignatureMismatch)
+        ));
+
+        let mut other = PaymentLifecycle::resume("uuid-2", PaymentState::Initiated);
+        assert!(matches!(
+            other.apply_validation(&validation(EsewaStatus::Complete, true)),
+            Err(PaymentError::TransactionUuidMismatch { .. })
+        ));
+
+        assert_eq!(payment.state(), PaymentState::Initiated);
+        assert_eq!(other.state(), PaymentState::Initiated);
+    }
+
+    #[test]
+    fn test_status_check_results() {
+        let mut payment = PaymentLifecycle::resume("uuid-1", PaymentState::Initiated);
+        let status = EsewaStatusResponse {
+            product_code: "EPAYTEST".to_string(),
+            transaction_uuid: "uuid-1".to_string(),
+            total_amount: Npr::from_rupees(100),
+            status: EsewaStatus::NotFound,
+            ref_id: None,
+        };
+
+        assert_eq!(payment.apply_status(&status).unwrap(), PaymentState::Failed);
+    }
+}
@@ -0,0 +1,383 @@
+//! In-process eSewa stand-in server for offline tests.
+//!
+//! Enabled with the `mock` feature. [`MockEsewaServer`] binds to a random
+//! local port and serves the ePay form and status endpoints, so the whole
+//! payment flow can be exercised without network access:
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! use rustpayment::mock::{MockEsewaServer, MockOutcome};
+//! use rustpayment::EsewaClient;
+//!
+//! let server = MockEsewaServer::start("EPAYTEST", "8gBm/:&EnhH.1/q").await?;
+//! server.set_default_outcome(MockOutcome::Complete);
+//!
+//! let client = EsewaClient::builder("EPAYTEST", "8gBm/:&EnhH.1/q")
+//!     .environment(server.environment())
+//!     .build()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! After a form is posted, [`MockEsewaServer::redirect_url`] returns where
+//! eSewa would send the customer's browser: the success URL with a signed
+//! base64 `data` payload, or the failure URL.
+
+use crate::esewa::{
+    build_signature_message, generate_signature_for_fields, EsewaEnvironment,
+    EsewaPaymentResponse, EsewaStatus,
+};
+use crate::money::Npr;
+use crate::secret::MerchantSecret;
+use axum::extract::{Form, Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use base64::{engine::general_purpose, Engine};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// How long a [`MockOutcome::Timeout`] keeps requests waiting
+const HANG_DURATION: Duration = Duration::from_secs(300);
+
+/// Field list the mock signs callbacks with, as eSewa does
+const CALLBACK_SIGNED_FIELD_NAMES: &str =
+    "transaction_code,status,total_amount,transaction_uuid,product_code,signed_field_names";
+
+/// Scripted result of a mock transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MockOutcome {
+    /// Customer pays; success redirect and status report `COMPLETE`
+    Complete,
+    /// Payment stays pending; success redirect and status report `PENDING`
+    Pending,
+    /// Customer cancels; failure redirect and status report `CANCELED`
+    Canceled,
+    /// Success redirect whose payload was signed as `PENDING` and then
+    /// flipped to `COMPLETE`, so its signature does not verify
+    TamperedSignature,
+    /// The form and status endpoints never answer in time
+    Timeout,
+    /// Success redirect and status report carry the given status
+    Status(EsewaStatus),
+}
+
+impl MockOutcome {
+    fn status(&self) -> EsewaStatus {
+        match self {
+            MockOutcome::Complete | MockOutcome::TamperedSignature | MockOutcome::Timeout => {
+                EsewaStatus::Complete
+            }
+            MockOutcome::Pending => EsewaStatus::Pending,
+            MockOutcome::Canceled => EsewaStatus::Canceled,
+            MockOutcome::Status(status) => status.clone(),
+        }
+    }
+}
+
+/// A payment form received by the mock server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockTransaction {
+    pub transaction_uuid: String,
+    pub transaction_code: String,
+    pub product_code: String,
+    pub total_amount: Npr,
+    pub success_url: String,
+    pub failure_url: String,
+}
+
+struct MockState {
+    product_code: String,
+    secret_key: MerchantSecret,
+    default_outcome: MockOutcome,
+    outcomes: HashMap<String, MockOutcome>,
+    transactions: HashMap<String, MockTransaction>,
+    next_code: u32,
+}
+
+impl MockState {
+    fn outcome(&self, transaction_uuid: &str) -> MockOutcome {
+        self.outcomes
+            .get(transaction_uuid)
+            .unwrap_or(&self.default_outcome)
+            .clone()
+    }
+}
+
+type SharedState = Arc<Mutex<MockState>>;
+
+/// In-process eSewa stand-in server
+///
+/// The server runs on the current Tokio runtime and stops when dropped.
+pub struct MockEsewaServer {
+    base_url: String,
+    state: SharedState,
+    handle: JoinHandle<()>,
+}
+
+impl MockEsewaServer {
+    /// Starts a server accepting forms signed with `secret_key` for `product_code`
+    pub async fn start(
+        product_code: impl Into<String>,
+        secret_key: impl Into<MerchantSecret>,
+    ) -> std::io::Result<Self> {
+        let state = Arc::new(Mutex::new(MockState {
+            product_code: product_code.into(),
+            secret_key: secret_key.into(),
+            default_outcome: MockOutcome::Complete,
+            outcomes: HashMap::new(),
+            transactions: HashMap::new(),
+            next_code: 1,
+        }));
+
+        let router = Router::new()
+            .route("/api/epay/main/v2/form", post(submit_form))
+            .route("/api/epay/transaction/status/", get(transaction_status))
+            .route("/mock/checkout/{uuid}", get(checkout_page))
+            .route("/mock/checkout/{uuid}/complete", get(complete_checkout))
+            .with_state(state.clone());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let base_url = format!("http://{}", listener.local_addr()?);
+        let handle = tokio::spawn(async move {
+            let _ = axum::serve(listener, router).await;
+        });
+
+        Ok(MockEsewaServer {
+            base_url,
+            state,
+            handle,
+        })
+    }
+
+    /// Base URL of the server, e.g. `http://127.0.0.1:41234`
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Environment pointing an [`EsewaClient`](crate::EsewaClient) at this server
+    pub fn environment(&self) -> EsewaEnvironment {
+        EsewaEnvironment::Custom {
+            base_url: self.base_url.clone(),
+        }
+    }
+
+    /// Sets the outcome of transactions without a scripted outcome
+    pub fn set_default_outcome(&self, outcome: MockOutcome) {
+        self.lock().default_outcome = outcome;
+    }
+
+    /// Scripts the outcome of one transaction
+    pub fn set_outcome(&self, transaction_uuid: impl Into<String>, outcome: MockOutcome) {
+        self.lock().outcomes.insert(transaction_uuid.into(), outcome);
+    }
+
+    /// Returns a transaction whose form was received
+    pub fn transaction(&self, transaction_uuid: &str) -> Option<MockTransaction> {
+        self.lock().transactions.get(transaction_uuid).cloned()
+    }
+
+    /// Base64 `data` payload eSewa would append to the success URL
+    ///
+    /// Returns `None` for unknown transactions and for canceled ones, which
+    /// are sent to the failure URL without a payload.
+    pub fn callback_data(&self, transaction_uuid: &str) -> Option<String> {
+        let state = self.lock();
+        let transaction = state.transactions.get(transaction_uuid)?;
+        callback_payload(&state, transaction)
+    }
+
+    /// URL eSewa would redirect the customer's browser to after checkout
+    pub fn redirect_url(&self, transaction_uuid: &str) -> Option<String> {
+        let state = self.lock();
+        let transaction = state.transactions.get(transaction_uuid)?;
+        Some(redirect_target(&state, transaction))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.state.lock().expect("mock state poisoned")
+    }
+}
+
+impl Drop for MockEsewaServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+fn callback_payload(state: &MockState, transaction: &MockTransaction) -> Option<String> {
+    let outcome = state.outcome(&transaction.transaction_uuid);
+    if outcome == MockOutcome::Canceled {
+        return None;
+    }
+
+    let signed_status = match outcome {
+        MockOutcome::TamperedSignature => EsewaStatus::Pending,
+        _ => outcome.status(),
+    };
+    let mut response = EsewaPaymentResponse {
+        transaction_code: transaction.transaction_code.clone(),
+        status: signed_status,
+        total_amount: transaction.total_amount,
+        transaction_uuid: transaction.transaction_uuid.clone(),
+        product_code: transaction.product_code.clone(),
+        signed_field_names: CALLBACK_SIGNED_FIELD_NAMES.to_string(),
+        signature: String::new(),
+    };
+    response.signature = response
+        .compute_signature(&state.secret_key)
+        .expect("callback fields are always present");
+    response.status = outcome.status();
+
+    let json = serde_json::to_string(&response).expect("callback serializes");
+    Some(general_purpose::STANDARD.encode(json))
+}
+
+fn redirect_target(state: &MockState, transaction: &MockTransaction) -> String {
+    match callback_payload(state, transaction) {
+        Some(data) => match reqwest::Url::parse(&transaction.success_url) {
+            Ok(mut url) => {
+                url.query_pairs_mut().append_pair("data", &data);
+                url.to_string()
+            }
+            Err(_) => format!("{}?data={}", transaction.success_url, data),
+        },
+        None => transaction.failure_url.clone(),
+    }
+}
+
+fn redirect(location: &str) -> Response {
+    (StatusCode::FOUND, [(header::LOCATION, location.to_string())]).into_response()
+}
+
+async fn submit_form(
+    State(state): State<SharedState>,
+    Form(fields): Form<HashMap<String, String>>,
+) -> Response {
+    let result = {
+        let mut state = state.lock().expect("mock state poisoned");
+        accept_form(&mut state, &fields)
+    };
+
+    match result {
+        Ok((transaction_uuid, MockOutcome::Timeout)) => {
+            tokio::time::sleep(HANG_DURATION).await;
+            redirect(&format!("/mock/checkout/{}", transaction_uuid))
+        }
+        Ok((transaction_uuid, _)) => redirect(&format!("/mock/checkout/{}", transaction_uuid)),
+        Err(message) => (StatusCode::BAD_REQUEST, message).into_response(),
+    }
+}
+
+/// Validates a posted form the way eSewa does and records the transaction
+fn accept_form(
+    state: &mut MockState,
+    fields: &HashMap<String, String>,
+) -> Result<(String, MockOutcome), String> {
+    let field = |name: &str| {
+        fields
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("missing field {}", name))
+    };
+
+    let signed_field_names = field("signed_field_names")?;
+    let signature = field("signature")?;
+    let expected = generate_signature_for_fields(
+        &signed_field_names,
+        |name| fields.get(name).cloned(),
+        &state.secret_key,
+    )
+    .map_err(|e| e.to_string())?;
+    if expected != signature {
+        let message = build_signature_message(&signed_field_names, |name| fields.get(name).cloned())
+            .unwrap_or_default();
+        return Err(format!("invalid signature for {}", message));
+    }
+
+    let product_code = field("product_code")?;
+    if product_code != state.product_code {
+        return Err(format!("unknown product code {}", product_code));
+    }
+
+    let total_amount: Npr = field("total_amount")?
+        .parse()
+        .map_err(|e: crate::error::PaymentError| e.to_string())?;
+    let transaction_uuid = field("transaction_uuid")?;
+    let transaction_code = format!("MOCK{:04}", state.next_code);
+    state.next_code += 1;
+
+    state.transactions.insert(
+        transaction_uuid.clone(),
+        MockTransaction {
+            transaction_uuid: transaction_uuid.clone(),
+            transaction_code,
+            product_code,
+            total_amount,
+            success_url: field("success_url")?,
+            failure_url: field("failure_url")?,
+        },
+    );
+
+    let outcome = state.outcome(&transaction_uuid);
+    Ok((transaction_uuid, outcome))
+}
+
+async fn checkout_page(Path(uuid): Path<String>) -> Html<String> {
+    Html(format!(
+        "<!DOCTYPE html>\n<html><body><h1>Mock eSewa checkout</h1><a href=\"/mock/checkout/{}/complete\">Pay</a></body></html>\n",
+        crate::form::escape_html(&uuid)
+    ))
+}
+
+async fn complete_checkout(
+    State(state): State<SharedState>,
+    Path(uuid): Path<String>,
+) -> Response {
+    let state = state.lock().expect("mock state poisoned");
+    match state.transactions.get(&uuid) {
+        Some(transaction) => redirect(&redirect_target(&state, transaction)),
+        None => (StatusCode::NOT_FOUND, "unknown transaction").into_response(),
+    }
+}
+
+async fn transaction_status(
+    State(state): State<SharedState>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    let product_code = query.get("product_code").cloned().unwrap_or_default();
+    let transaction_uuid = query.get("transaction_uuid").cloned().unwrap_or_default();
+    let total_amount = query
+        .get("total_amount")
+        .and_then(|amount| amount.parse::<Npr>().ok());
+
+    let (outcome, found) = {
+        let state = state.lock().expect("mock state poisoned");
+        let found = state.transactions.get(&transaction_uuid).is_some_and(|transaction| {
+            transaction.product_code == product_code && Some(transaction.total_amount) == total_amount
+        });
+        (state.outcome(&transaction_uuid), found)
+    };
+
+    if outcome == MockOutcome::Timeout {
+        tokio::time::sleep(HANG_DURATION).await;
+    }
+
+    let (status, ref_id) = match (found, outcome.status()) {
+        (false, _) => (EsewaStatus::NotFound, None),
+        (true, EsewaStatus::Complete) => (EsewaStatus::Complete, Some(format!("REF-{}", transaction_uuid))),
+        (true, status) => (status, None),
+    };
+
+    Json(serde_json::json!({
+        "product_code": product_code,
+        "transaction_uuid": transaction_uuid,
+        "total_amount": total_amount.unwrap_or_default(),
+        "status": status,
+        "ref_id": ref_id,
+    }))
+    .into_response()
+}
@@ -0,0 +1,252 @@
+//! Money type for Nepalese rupee amounts.
+//!
+//! Amounts are stored as a whole number of paisa so no floating point is
+//! involved in arithmetic, comparison or formatting.
+
+use crate::error::PaymentError;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// An amount in Nepalese rupees with paisa precision
+///
+/// Formats the way it is sent to and signed for eSewa: whole rupee amounts
+/// have no decimal part (`"110"`), others always have two digits (`"110.50"`).
+/// Parsing accepts both forms, trailing zeros (`"110.0"`) and thousands
+/// separators (`"1,000.0"`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Npr(u64);
+
+impl Npr {
+    /// Zero rupees
+    pub const ZERO: Npr = Npr(0);
+
+    /// Creates an amount from a number of paisa
+    pub const fn from_paisa(paisa: u64) -> Self {
+        Npr(paisa)
+    }
+
+    /// Creates an amount from a whole number of rupees
+    ///
+    /// # Panics
+    ///
+    /// Panics if the amount does not fit in `u64` paisa.
+    pub const fn from_rupees(rupees: u64) -> Self {
+        match rupees.checked_mul(100) {
+            Some(paisa) => Npr(paisa),
+            None => panic!("rupee amount overflows"),
+        }
+    }
+
+    /// Total number of paisa
+    pub const fn paisa(self) -> u64 {
+        self.0
+    }
+
+    /// Whole rupee part of the amount
+    pub const fn rupees(self) -> u64 {
+        self.0 / 100
+    }
+
+    /// Adds two amounts, returning `None` on overflow
+    pub const fn checked_add(self, other: Npr) -> Option<Npr> {
+        match self.0.checked_add(other.0) {
+            Some(paisa) => Some(Npr(paisa)),
+            None => None,
+        }
+    }
+
+    /// Returns `true` if the amount is zero
+    pub const fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl fmt::Display for Npr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (rupees, paisa) = (self.0 / 100, self.0 % 100);
+        if paisa == 0 {
+            write!(f, "{}", rupees)
+        } else {
+            write!(f, "{}.{:02}", rupees, paisa)
+        }
+    }
+}
+
+impl FromStr for Npr {
+    type Err = PaymentError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || PaymentError::InvalidAmount(format!("Not a valid NPR amount: {:?}", s));
+
+        let trimmed = s.trim();
+        let (grouped, fraction) = match trimmed.split_once('.') {
+            Some((_, "")) => return Err(invalid()),
+            Some((whole, fraction)) => (whole, fraction),
+            None => (trimmed, ""),
+        };
+
+        // Commas are only accepted as thousands separators, e.g. "1,000"
+        let mut groups = grouped.split(',');
+        let leading = groups.next().unwrap_or_default();
+        let rest: Vec<&str> = groups.collect();
+        let is_digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+        if !is_digits(leading) || (!rest.is_empty() && leading.len() > 3) {
+            return Err(invalid());
+        }
+        if !rest.iter().all(|group| group.len() == 3 && is_digits(group)) {
+            return Err(invalid());
+        }
+        let whole: String = grouped.chars().filter(|c| *c != ',').collect();
+
+        if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        // Digits beyond paisa are only accepted when they are zero
+        if fraction.len() > 2 && fraction[2..].bytes().any(|b| b != b'0') {
+            return Err(invalid());
+        }
+
+        let rupees: u64 = whole.parse().map_err(|_| invalid())?;
+        let paisa = match fraction.len() {
+            0 => 0,
+            1 => u64::from(fraction.as_bytes()[0] - b'0') * 10,
+            _ => fraction[..2].parse::<u64>().map_err(|_| invalid())?,
+        };
+
+        rupees
+            .checked_mul(100)
+            .and_then(|r| r.checked_add(paisa))
+            .map(Npr)
+            .ok_or_else(invalid)
+    }
+}
+
+/// Adds two amounts
+///
+/// # Panics
+///
+/// Panics on overflow, in debug and release builds alike; use
+/// [`Npr::checked_add`] for amounts that come from outside the program.
+impl std::ops::Add for Npr {
+    type Output = Npr;
+
+    fn add(self, other: Npr) -> Npr {
+        self.checked_add(other).expect("NPR amount overflows")
+    }
+}
+
+impl Serialize for Npr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Npr {
+    /// Accepts both JSON strings and JSON numbers, as eSewa sends either
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let text = match &value {
+            serde_json::Value::String(s) => s.clone(),
+            // Shortest round-trip representation, so 110.45 stays "110.45"
+            serde_json::Value::Number(n) => n.to_string(),
+            _ => return Err(serde::de::Error::custom("expected an amount string or number")),
+        };
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Serde helpers for gateways that exchange amounts as an integer number of paisa
+///
+/// Use with `#[serde(with = "crate::money::paisa")]`.
+pub mod paisa {
+    use super::Npr;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(amount: &Npr, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(amount.paisa())
+    }
+
+    /// Accepts integers and integer strings
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Npr, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let paisa = match &value {
+            serde_json::Value::Number(n) => n.as_u64(),
+            serde_json::Value::String(s) => s.trim().parse().ok(),
+            _ => None,
+        };
+        paisa
+            .map(Npr::from_paisa)
+            .ok_or_else(|| serde::de::Error::custom("expected a whole number of paisa"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_format() {
+        assert_eq!("110".parse::<Npr>().unwrap(), Npr::from_rupees(110));
+        assert_eq!("110.0".parse::<Npr>().unwrap(), Npr::from_rupees(110));
+        assert_eq!("110.5".parse::<Npr>().unwrap(), Npr::from_paisa(11050));
+        assert_eq!("1,000.00".parse::<Npr>().unwrap(), Npr::from_rupees(1000));
+        assert_eq!("12,345,678".parse::<Npr>().unwrap(), Npr::from_rupees(12_345_678));
+
+        assert_eq!(Npr::from_rupees(110).to_string(), "110");
+        assert_eq!(Npr::from_paisa(11050).to_string(), "110.50");
+        assert_eq!(Npr::from_paisa(5).to_string(), "0.05");
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid() {
+        for input in ["abc", "", "-10", "1.2.3", ".5", "10.005", "1e3", "1.", "110.", "1.,5"] {
+            assert!(input.parse::<Npr>().is_err(), "{:?} should be rejected", input);
+        }
+        assert!("10.500".parse::<Npr>().is_ok());
+    }
+
+    #[test]
+    fn test_parse_rejects_misplaced_commas() {
+        for input in ["1,0,0", "1,00", "1000,000", ",100", "100,", "1,,000", "1,000.0,0"] {
+            assert!(input.parse::<Npr>().is_err(), "{:?} should be rejected", input);
+        }
+    }
+
+    #[test]
+    fn test_addition() {
+        let total = Npr::from_rupees(100) + Npr::from_paisa(1050);
+        assert_eq!(total, Npr::from_paisa(11050));
+        assert_eq!(Npr::from_paisa(u64::MAX).checked_add(Npr::from_paisa(1)), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "NPR amount overflows")]
+    fn test_addition_panics_on_overflow() {
+        let _ = Npr::from_paisa(u64::MAX) + Npr::from_paisa(1);
+    }
+
+    #[test]
+    fn test_serde_accepts_strings_and_numbers() {
+        let from_number: Npr = serde_json::from_str("1000.0").unwrap();
+        let from_string: Npr = serde_json::from_str("\"110.45\"").unwrap();
+
+        assert_eq!(from_number, Npr::from_rupees(1000));
+        assert_eq!(from_string, Npr::from_paisa(11045));
+        assert_eq!(serde_json::to_string(&from_string).unwrap(), "\"110.45\"");
+    }
+
+    #[test]
+    fn test_paisa_serde() {
+        #[derive(Serialize, Deserialize)]
+        struct Body {
+            #[serde(with = "paisa")]
+            amount: Npr,
+        }
+
+        let body: Body = serde_json::from_str(r#"{"amount":"1000"}"#).unwrap();
+        assert_eq!(body.amount, Npr::from_rupees(10));
+        assert_eq!(serde_json::to_string(&body).unwrap(), r#"{"amount":1000}"#);
+        assert!(serde_json::from_str::<Body>(r#"{"amount":10.5}"#).is_err());
+    }
+}
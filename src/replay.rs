@@ -0,0 +1,353 @@
+//! Replay and double-credit protection for callbacks.
+//!
+//! The same callback `data` can reach the success URL more than once: the
+//! customer reloads the page, the browser retries, or someone replays a
+//! captured URL. [`CallbackProcessor`] verifies each callback, records its
+//! `transaction_code`/`transaction_uuid` pair in a [`DedupStore`], and
+//! reports re-deliveries as [`CallbackOutcome::Duplicate`] so the order is
+//! credited only once.
+
+use crate::error::PaymentError;
+use crate::esewa::{validate_esewa_response_with_keys, EsewaPaymentResponse};
+use crate::lifecycle::PaymentState;
+use crate::secret::SecretKeySet;
+use crate::store::{TransactionRecord, TransactionStore};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+/// Identifies one delivery of a gateway callback
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CallbackKey {
+    pub transaction_uuid: String,
+    pub transaction_code: String,
+}
+
+impl CallbackKey {
+    /// Key recording that a transaction settled as `state`
+    ///
+    /// A payment settles once, whichever `transaction_code` or status check
+    /// reports it, so this key does not include a transaction code.
+    pub fn settlement(transaction_uuid: impl Into<String>, state: PaymentState) -> Self {
+        CallbackKey {
+            transaction_uuid: transaction_uuid.into(),
+            transaction_code: format!("state:{}", state),
+        }
+    }
+}
+
+impl From<&EsewaPaymentResponse> for CallbackKey {
+    fn from(response: &EsewaPaymentResponse) -> Self {
+        CallbackKey {
+            transaction_uuid: response.transaction_uuid.clone(),
+            transaction_code: response.transaction_code.clone(),
+        }
+    }
+}
+
+/// Storage for the keys of processed callbacks
+///
+/// [`mark_processed`](Self::mark_processed) must be atomic: when two
+/// deliveries race, exactly one of them may see `true`.
+#[async_trait]
+pub trait DedupStore: Send + Sync {
+    /// Records `key`, returning `true` if it was not recorded before
+    async fn mark_processed(&self, key: &CallbackKey) -> Result<bool, PaymentError>;
+
+    /// Removes `key` so the callback can be processed again
+    ///
+    /// Used to roll back when processing fails after the key was recorded.
+    async fn forget(&self, key: &CallbackKey) -> Result<(), PaymentError>;
+}
+
+/// In-memory [`DedupStore`]; keys are lost when it is dropped
+#[derive(Debug, Default)]
+pub struct MemoryDedupStore {
+    processed: Mutex<HashSet<CallbackKey>>,
+}
+
+impl MemoryDedupStore {
+    /// Creates an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl DedupStore for MemoryDedupStore {
+    async fn mark_processed(&self, key: &CallbackKey) -> Result<bool, PaymentError> {
+        let mut processed = self.processed.lock().expect("dedup store lock poisoned");
+        Ok(processed.insert(key.clone()))
+    }
+
+    async fn forget(&self, key: &CallbackKey) -> Result<(), PaymentError> {
+        let mut processed = self.processed.lock().expect("dedup store lock poisoned");
+        processed.remove(key);
+        Ok(())
+    }
+}
+
+/// Result of processing a callback
+#[derive(Debug, Clone)]
+pub enum CallbackOutcome {
+    /// First delivery; act on it (e.g. credit the order)
+    Processed {
+        response: EsewaPaymentResponse,
+        /// The updated record, when the processor has a [`TransactionStore`]
+        record: Option<TransactionRecord>,
+    },
+    /// Re-delivery of a callback that was already processed; do not act on it again
+    Duplicate { response: EsewaPaymentResponse },
+}
+
+impl CallbackOutcome {
+    /// The verified callback
+    pub fn response(&self) -> &EsewaPaymentResponse {
+        match self {
+            CallbackOutcome::Processed { response, .. }
+            | CallbackOutcome::Duplicate { response } => response,
+        }
+    }
+
+    /// Returns `true` for [`CallbackOutcome::Duplicate`]
+    pub fn is_duplicate(&self) -> bool {
+        matches!(self, CallbackOutcome::Duplicate { .. })
+    }
+}
+
+/// Idempotent eSewa callback processing
+///
+/// Without a [`TransactionStore`] the processor checks the signature with
+/// [`validate_esewa_response_with_keys`]. With one, it also checks the callback
+/// strictly against the stored payment and records the new state.
+///
+/// Besides the `transaction_code`/`transaction_uuid` pair, the processor
+/// records each settled state per transaction, so a second `COMPLETE`
+/// callback with a different `transaction_code` is also a duplicate.
+#[derive(Clone)]
+pub struct CallbackProcessor {
+    secret_keys: SecretKeySet,
+    dedup: Arc<dyn DedupStore>,
+    store: Option<Arc<dyn TransactionStore>>,
+}
+
+impl std::fmt::Debug for CallbackProcessor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CallbackProcessor")
+            .field("has_store", &self.store.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl CallbackProcessor {
+    /// Creates a processor that verifies with `secret_keys` and records keys in `dedup`
+    ///
+    /// Takes a single secret key or a [`SecretKeySet`] accepting previous keys.
+    pub fn new(secret_keys: impl Into<SecretKeySet>, dedup: Arc<dyn DedupStore>) -> Self {
+        CallbackProcessor {
+            secret_keys: secret_keys.into(),
+            dedup,
+            store: None,
+        }
+    }
+
+    /// Verifies callbacks against, and records their state in, `store`
+    pub fn with_store(mut self, store: Arc<dyn TransactionStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Verifies a callback's `data` parameter and processes it at most once
+    ///
+    /// Invalid callbacks fail without being recorded, so a later valid
+    /// delivery is still processed.
+    pub async fn process(&self, encoded_data: &str) -> Result<CallbackOutcome, PaymentError> {
+        let validation = validate_esewa_response_with_keys(encoded_data, &self.secret_keys)?;
+        if !validation.signature_valid() {
+            return Err(PaymentError::SignatureMismatch);
+        }
+
+        let (response, stored) = match &self.store {
+            Some(store) => {
+                let record = store.require(&validation.response.transaction_uuid).await?;
+                let verified =
+                    validation.verify(&record.product_code, &record.expected_payment())?;
+                (verified.response().clone(), Some((store, record)))
+            }
+            None => (validation.response, None),
+        };
+
+        let next = PaymentState::from(&response.status);
+        let keys = callback_keys(&response);
+        for key in &keys {
+            if !self.dedup.mark_processed(key).await? {
+                return Ok(CallbackOutcome::Duplicate { response });
+            }
+        }
+
+        let record = match stored {
+            Some((store, record)) => match store.transition(&record.transaction_id, next).await {
+                Ok(record) => Some(record),
+                Err(e) => {
+                    self.forget(&response).await?;
+                    return Err(e);
+                }
+            },
+            None => None,
+        };
+
+        Ok(CallbackOutcome::Processed { response, record })
+    }
+
+    /// Forgets a processed callback so its re-delivery is processed again
+    ///
+    /// Call this when acting on a [`CallbackOutcome::Processed`] failed,
+    /// e.g. the order could not be credited.
+    pub async fn forget(&self, response: &EsewaPaymentResponse) -> Result<(), PaymentError> {
+        for key in callback_keys(response) {
+            self.dedup.forget(&key).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Keys recorded for a callback: its own, plus its settlement if settled
+fn callback_keys(response: &EsewaPaymentResponse) -> Vec<CallbackKey> {
+    let mut keys = vec![CallbackKey::from(response)];
+    let state = PaymentState::from(&response.status);
+    if state.is_settled() {
+        keys.push(CallbackKey::settlement(
+            response.transaction_uuid.clone(),
+            state,
+        ));
+    }
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::esewa::test_callbacks::{encode, replayed_form_signature, signed_callback, SECRET};
+    use crate::money::Npr;
+    use crate::store::MemoryTransactionStore;
+    use base64::{engine::general_purpose, Engine};
+
+    fn callback(transaction_code: &str, total_amount: Npr) -> String {
+        signed_callback(|response| {
+            response.transaction_code = transaction_code.to_string();
+            response.total_amount = total_amount;
+        })
+    }
+
+    async fn store() -> Arc<MemoryTransactionStore> {
+        let store = Arc::new(MemoryTransactionStore::new());
+        let mut record = TransactionRecord::new(
+            "id-123-abc",
+            "order-1",
+            "esewa",
+            "EPAYTEST",
+            Npr::from_rupees(110),
+        );
+        record.state = PaymentState::Initiated;
+        store.insert(&record).await.unwrap();
+        store
+    }
+
+    #[tokio::test]
+    async fn test_redelivery_is_duplicate() {
+        let processor = CallbackProcessor::new(SECRET, Arc::new(MemoryDedupStore::new()));
+        let data = callback("000D13A", Npr::from_rupees(110));
+
+        assert!(!processor.process(&data).await.unwrap().is_duplicate());
+        assert!(processor.process(&data).await.unwrap().is_duplicate());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_callback_is_not_recorded() {
+        let dedup = Arc::new(MemoryDedupStore::new());
+        let processor = CallbackProcessor::new(SECRET, dedup.clone());
+        let mut response: serde_json::Value = serde_json::from_slice(
+            &general_purpose::STANDARD
+                .decode(callback("000D13A", Npr::from_rupees(110)))
+                .unwrap(),
+        )
+        .unwrap();
+        response["status"] = "PENDING".into();
+        let tampered = general_purpose::STANDARD.encode(response.to_string());
+
+        assert!(processor.process(&tampered).await.is_err());
+        assert!(dedup
+            .mark_processed(&CallbackKey {
+                transaction_uuid: "id-123-abc".to_string(),
+                transaction_code: "000D13A".to_string(),
+            })
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_store_rejects_wrong_amount_and_records_state() {
+        let store = store().await;
+        let processor = CallbackProcessor::new(SECRET, Arc::new(MemoryDedupStore::new()))
+            .with_store(store.clone());
+
+        assert!(matches!(
+            processor
+                .process(&callback("000D13A", Npr::from_rupees(1)))
+                .await,
+            Err(PaymentError::AmountMismatch { .. })
+        ));
+
+        let outcome = processor
+            .process(&callback("000D13A", Npr::from_rupees(110)))
+            .await
+            .unwrap();
+        match outcome {
+            CallbackOutcome::Processed { record, .. } => {
+                assert_eq!(record.unwrap().state, PaymentState::Completed)
+            }
+            other => panic!("expected Processed, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_rejects_forged_completion() {
+        let store = store().await;
+        let dedup = Arc::new(MemoryDedupStore::new());
+        let processor = CallbackProcessor::new(SECRET, dedup.clone()).with_store(store.clone());
+
+        assert!(matches!(
+            processor.process(&encode(&replayed_form_signature())).await,
+            Err(PaymentError::SignatureMismatch)
+        ));
+
+        let record = store.require("id-123-abc").await.unwrap();
+        assert_eq!(record.state, PaymentState::Initiated);
+        assert!(dedup
+            .mark_processed(&CallbackKey {
+                transaction_uuid: "id-123-abc".to_string(),
+                transaction_code: "FAKE".to_string(),
+            })
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_second_completion_for_settled_payment_is_duplicate() {
+        let store = store().await;
+        let processor = CallbackProcessor::new(SECRET, Arc::new(MemoryDedupStore::new()))
+            .with_store(store.clone());
+
+        processor
+            .process(&callback("000D13A", Npr::from_rupees(110)))
+            .await
+            .unwrap();
+        let again = processor
+            .process(&callback("000D13B", Npr::from_rupees(110)))
+            .await
+            .unwrap();
+
+        assert!(again.is_duplicate());
+    }
+}
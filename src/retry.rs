@@ -0,0 +1,282 @@
+//! Retries for gateway HTTP calls.
+//!
+//! Every client retries failed requests according to its [`RetryPolicy`],
+//! set with `retry_policy()` on the client's builder. A failure is retried
+//! only if [`PaymentError::is_retryable`], and only for operations that are
+//! safe to repeat, such as status checks. Operations that create something
+//! at the gateway, such as a payment initiation, are retried only when the
+//! connection failed before the request was sent.
+//!
+//! When a request was retried and still failed, the error is
+//! [`PaymentError::Retried`], holding the number of attempts and the last
+//! failure.
+
+use crate::error::PaymentError;
+use rand::Rng;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// How failed gateway requests are retried
+///
+/// The delay before retry `n` is `initial_backoff * 2^(n - 1)`, capped at
+/// `max_backoff`. With jitter, a random delay between half and all of that
+/// is used instead, so clients that failed together do not retry together.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    deadline: Option<Duration>,
+    jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    /// Three attempts, 200 ms doubling up to 2 s, with jitter, within 30 seconds
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(2),
+            deadline: Some(Duration::from_secs(30)),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The default policy; see [`RetryPolicy::default`]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Never retries
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Sets the number of attempts, including the first one
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Sets the delay before the first retry and the cap on later delays
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    /// Bounds the time spent on all attempts and delays together
+    ///
+    /// An attempt still running at the deadline fails with
+    /// [`PaymentError::Timeout`], and no retry is started that would only
+    /// begin after it.
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Retries until the attempts run out, however long that takes
+    pub fn no_deadline(mut self) -> Self {
+        self.deadline = None;
+        self
+    }
+
+    /// Turns jitter on or off (on by default)
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Delay before retry `retry`, counting from 1, without jitter
+    pub fn backoff_for(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+
+    /// Runs `attempt` until it succeeds or the policy gives up
+    pub(crate) async fn run<T, F, Fut>(
+        &self,
+        idempotency: Idempotency,
+        mut attempt: F,
+    ) -> Result<T, PaymentError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, PaymentError>>,
+    {
+        let start = Instant::now();
+        let mut attempts = 1;
+        loop {
+            let result = match self.deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_sub(start.elapsed());
+                    tokio::time::timeout(remaining, attempt())
+                        .await
+                        .unwrap_or_else(|elapsed| Err(PaymentError::Timeout(elapsed.into())))
+                }
+                None => attempt().await,
+            };
+            let error = match result {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+
+            let delay = self.delay(attempts);
+            let retry = attempts < self.max_attempts
+                && may_retry(&error, idempotency)
+                && self
+                    .deadline
+                    .is_none_or(|deadline| start.elapsed() + delay < deadline);
+            if !retry {
+                return Err(if attempts > 1 {
+                    PaymentError::Retried {
+                        attempts,
+                        last: Box::new(error),
+                    }
+                } else {
+                    error
+                });
+            }
+
+            tokio::time::sleep(delay).await;
+            attempts += 1;
+        }
+    }
+
+    fn delay(&self, retry: u32) -> Duration {
+        let delay = self.backoff_for(retry);
+        if self.jitter {
+            let half = delay / 2;
+            half + rand::rng().random_range(Duration::ZERO..=half)
+        } else {
+            delay
+        }
+    }
+}
+
+/// Whether repeating an operation can have effects beyond the first attempt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Idempotency {
+    /// Safe to repeat, e.g. a status check
+    Idempotent,
+    /// Creates something at the gateway, e.g. a payment or a token
+    NotIdempotent,
+}
+
+fn may_retry(error: &PaymentError, idempotency: Idempotency) -> bool {
+    match idempotency {
+        Idempotency::Idempotent => error.is_retryable(),
+        Idempotency::NotIdempotent => never_sent(error),
+    }
+}
+
+/// Returns `true` if the connection failed, so the gateway never saw the request
+fn never_sent(error: &PaymentError) -> bool {
+    match error {
+        PaymentError::Network(source) => source
+            .downcast_ref::<reqwest::Error>()
+            .is_some_and(reqwest::Error::is_connect),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn unavailable() -> PaymentError {
+        PaymentError::Http {
+            status: 503,
+            body: String::new(),
+        }
+    }
+
+    fn fast() -> RetryPolicy {
+        RetryPolicy::new().backoff(Duration::from_millis(1), Duration::from_millis(4))
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_cap() {
+        let policy =
+            RetryPolicy::new().backoff(Duration::from_millis(100), Duration::from_millis(300));
+
+        assert_eq!(policy.backoff_for(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff_for(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff_for(3), Duration::from_millis(300));
+        assert_eq!(policy.backoff_for(40), Duration::from_millis(300));
+        for _ in 0..20 {
+            let delay = policy.delay(2);
+            assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(200));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries_idempotent_calls_and_reports_attempts() {
+        let calls = AtomicU32::new(0);
+        let result = fast()
+            .run(Idempotency::Idempotent, || async {
+                match calls.fetch_add(1, Ordering::SeqCst) {
+                    0 => Err(unavailable()),
+                    _ => Ok("ok"),
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), "ok");
+
+        let result: Result<(), _> = fast()
+            .max_attempts(4)
+            .run(Idempotency::Idempotent, || async { Err(unavailable()) })
+            .await;
+        match result {
+            Err(PaymentError::Retried { attempts, last }) => {
+                assert_eq!(attempts, 4);
+                assert!(matches!(*last, PaymentError::Http { status: 503, .. }));
+            }
+            other => panic!("expected Retried, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_does_not_retry_unsafe_or_permanent_failures() {
+        let calls = AtomicU32::new(0);
+        let result: Result<(), _> = fast()
+            .run(Idempotency::NotIdempotent, || async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err(unavailable())
+            })
+            .await;
+        assert!(matches!(
+            result,
+            Err(PaymentError::Http { status: 503, .. })
+        ));
+
+        let result: Result<(), _> = fast()
+            .run(Idempotency::Idempotent, || async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err(PaymentError::SignatureMismatch)
+            })
+            .await;
+        assert!(matches!(result, Err(PaymentError::SignatureMismatch)));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_deadline_stops_slow_attempts() {
+        let result: Result<(), _> = fast()
+            .deadline(Duration::from_millis(50))
+            .run(Idempotency::Idempotent, || async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Ok(())
+            })
+            .await;
+
+        assert!(matches!(result, Err(PaymentError::Timeout(_))));
+    }
+}
@@ -0,0 +1,301 @@
+//! Merchant secret keys.
+//!
+//! [`MerchantSecret`] keeps a gateway secret out of logs and memory dumps:
+//! its `Debug` and `Display` print `<redacted>`, and its buffer is zeroed
+//! when dropped. Every function taking a `secret_key` accepts it, as well as
+//! plain `&str` and `String`. Clients and their builders keep secret keys and
+//! API passwords as `MerchantSecret`, so printing them shows neither.
+//!
+//! Load it with [`MerchantSecret::from_env`] or [`MerchantSecret::from_file`],
+//! or implement [`SecretProvider`] to fetch it from a secret manager.
+//!
+//! When the gateway issues a new key, callbacks for payments started before
+//! the switch still arrive signed with the old one. A [`SecretKeySet`] signs
+//! with the current key and keeps accepting previous keys until they expire.
+
+use crate::error::PaymentError;
+use crate::http::read_env;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use zeroize::Zeroizing;
+
+/// A gateway secret key, zeroed on drop and redacted when printed
+#[derive(Clone)]
+pub struct MerchantSecret {
+    value: Zeroizing<String>,
+}
+
+impl MerchantSecret {
+    /// Wraps a secret key
+    pub fn new(value: impl Into<String>) -> Self {
+        MerchantSecret {
+            value: Zeroizing::new(value.into()),
+        }
+    }
+
+    /// Reads the secret from environment variable `name`
+    ///
+    /// Fails with [`PaymentError::Config`] if it is unset or blank.
+    pub fn from_env(name: &str) -> Result<Self, PaymentError> {
+        read_env(name)?
+            .map(MerchantSecret::new)
+            .ok_or_else(|| PaymentError::Config(format!("{} is not set", name)))
+    }
+
+    /// Reads the secret from a file, ignoring a trailing newline
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, PaymentError> {
+        let path = path.as_ref();
+        let mut value =
+            Zeroizing::new(std::fs::read_to_string(path).map_err(|e| {
+                PaymentError::Config(format!("Cannot read {}: {}", path.display(), e))
+            })?);
+        let len = value.trim_end_matches(['\r', '\n']).len();
+        value.truncate(len);
+
+        if value.is_empty() {
+            return Err(PaymentError::Config(format!("{} is empty", path.display())));
+        }
+        Ok(MerchantSecret { value })
+    }
+
+    /// Fetches the secret `name` from `provider`
+    pub async fn from_provider(
+        provider: &dyn SecretProvider,
+        name: &str,
+    ) -> Result<Self, PaymentError> {
+        provider.fetch(name).await
+    }
+
+    /// The secret itself; avoid keeping copies of it
+    pub fn expose_secret(&self) -> &str {
+        &self.value
+    }
+
+    /// Returns `true` if the secret is empty
+    pub fn is_empty(&self) -> bool {
+        self.value.is_empty()
+    }
+}
+
+impl AsRef<[u8]> for MerchantSecret {
+    fn as_ref(&self) -> &[u8] {
+        self.value.as_bytes()
+    }
+}
+
+impl From<String> for MerchantSecret {
+    fn from(value: String) -> Self {
+        MerchantSecret::new(value)
+    }
+}
+
+impl From<&str> for MerchantSecret {
+    fn from(value: &str) -> Self {
+        MerchantSecret::new(value)
+    }
+}
+
+impl fmt::Debug for MerchantSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MerchantSecret(<redacted>)")
+    }
+}
+
+impl fmt::Display for MerchantSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+/// Source of secrets, e.g. a vault or a cloud secret manager
+#[async_trait]
+pub trait SecretProvider: Send + Sync {
+    /// Fetches the secret stored under `name`
+    async fn fetch(&self, name: &str) -> Result<MerchantSecret, PaymentError>;
+}
+
+/// [`SecretProvider`] reading environment variables, e.g. `ESEWA_SECRET_KEY`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EnvSecretProvider;
+
+#[async_trait]
+impl SecretProvider for EnvSecretProvider {
+    async fn fetch(&self, name: &str) -> Result<MerchantSecret, PaymentError> {
+        MerchantSecret::from_env(name)
+    }
+}
+
+/// Which key of a [`SecretKeySet`] a signature was made with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MatchedKey {
+    /// The current key
+    Current,
+    /// A previous key, by rank; `0` is the most recently retired one
+    Previous(usize),
+}
+
+/// The current secret key plus previous keys accepted until they expire
+///
+/// Requests are always signed with [`current`](Self::current). Signatures
+/// are checked against the current key first, then the unexpired previous
+/// keys in rank order.
+#[derive(Debug, Clone)]
+pub struct SecretKeySet {
+    current: MerchantSecret,
+    previous: Vec<PreviousKey>,
+}
+
+#[derive(Debug, Clone)]
+struct PreviousKey {
+    secret: MerchantSecret,
+    expires_at: SystemTime,
+}
+
+impl SecretKeySet {
+    /// Creates a set holding only `current`
+    pub fn new(current: impl Into<MerchantSecret>) -> Self {
+        SecretKeySet {
+            current: current.into(),
+            previous: Vec::new(),
+        }
+    }
+
+    /// Also accepts `secret` until `expires_at`, ranked after the keys added before it
+    pub fn with_previous(
+        mut self,
+        secret: impl Into<MerchantSecret>,
+        expires_at: SystemTime,
+    ) -> Self {
+        self.previous.push(PreviousKey {
+            secret: secret.into(),
+            expires_at,
+        });
+        self
+    }
+
+    /// Makes `current` the signing key and accepts the replaced one for `grace`
+    ///
+    /// Previous keys that have already expired are dropped.
+    pub fn rotate(&mut self, current: impl Into<MerchantSecret>, grace: Duration) {
+        let now = SystemTime::now();
+        let retired = std::mem::replace(&mut self.current, current.into());
+        self.previous.retain(|key| key.expires_at > now);
+        self.previous.insert(
+            0,
+            PreviousKey {
+                secret: retired,
+                expires_at: now + grace,
+            },
+        );
+    }
+
+    /// The key new requests are signed with
+    pub fn current(&self) -> &MerchantSecret {
+        &self.current
+    }
+
+    /// Keys accepted at `now`, current first
+    pub(crate) fn active_keys(
+        &self,
+        now: SystemTime,
+    ) -> impl Iterator<Item = (MatchedKey, &MerchantSecret)> {
+        std::iter::once((MatchedKey::Current, &self.current)).chain(
+            self.previous
+                .iter()
+                .enumerate()
+                .filter(move |(_, key)| key.expires_at > now)
+                .map(|(rank, key)| (MatchedKey::Previous(rank), &key.secret)),
+        )
+    }
+}
+
+impl From<MerchantSecret> for SecretKeySet {
+    fn from(current: MerchantSecret) -> Self {
+        SecretKeySet::new(current)
+    }
+}
+
+impl From<String> for SecretKeySet {
+    fn from(current: String) -> Self {
+        SecretKeySet::new(current)
+    }
+}
+
+impl From<&str> for SecretKeySet {
+    fn from(current: &str) -> Self {
+        SecretKeySet::new(current)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debug_and_display_are_redacted() {
+        let secret = MerchantSecret::new("8gBm/:&EnhH.1/q");
+
+        assert_eq!(format!("{:?}", secret), "MerchantSecret(<redacted>)");
+        assert_eq!(secret.to_string(), "<redacted>");
+        assert_eq!(secret.expose_secret(), "8gBm/:&EnhH.1/q");
+    }
+
+    #[test]
+    fn test_from_file_strips_trailing_newline() {
+        let path = std::env::temp_dir().join(format!("rustpayment-secret-{}", std::process::id()));
+        std::fs::write(&path, "8gBm/:&EnhH.1/q\r\n").unwrap();
+        let secret = MerchantSecret::from_file(&path);
+        std::fs::write(&path, "\n").unwrap();
+        let empty = MerchantSecret::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(secret.unwrap().expose_secret(), "8gBm/:&EnhH.1/q");
+        assert!(matches!(empty, Err(PaymentError::Config(_))));
+    }
+
+    #[tokio::test]
+    async fn test_provider() {
+        struct Fixed;
+
+        #[async_trait]
+        impl SecretProvider for Fixed {
+            async fn fetch(&self, name: &str) -> Result<MerchantSecret, PaymentError> {
+                Ok(MerchantSecret::new(format!("secret-for-{}", name)))
+            }
+        }
+
+        let secret = MerchantSecret::from_provider(&Fixed, "esewa")
+            .await
+            .unwrap();
+        assert_eq!(secret.expose_secret(), "secret-for-esewa");
+    }
+
+    #[test]
+    fn test_rotate_ranks_retired_key_first_and_skips_expired() {
+        let now = SystemTime::now();
+        let mut keys = SecretKeySet::new("v2").with_previous("v1", now + Duration::from_secs(60));
+        keys.rotate("v3", Duration::from_secs(60));
+
+        let active = |at| {
+            keys.active_keys(at)
+                .map(|(rank, key)| (rank, key.expose_secret().to_string()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(keys.current().expose_secret(), "v3");
+        assert_eq!(
+            active(now),
+            vec![
+                (MatchedKey::Current, "v3".to_string()),
+                (MatchedKey::Previous(0), "v2".to_string()),
+                (MatchedKey::Previous(1), "v1".to_string()),
+            ]
+        );
+        assert_eq!(
+            active(now + Duration::from_secs(120)),
+            vec![(MatchedKey::Current, "v3".to_string())]
+        );
+    }
+}
@@ -0,0 +1,666 @@
+//! Persistence of initiated payments.
+//!
+//! A [`TransactionStore`] records which order a transaction ID belongs to,
+//! the amount and product code it was initiated with, and its
+//! [`PaymentState`]. Callback handling then checks the callback against the
+//! stored values instead of trusting the decoded payload; see
+//! [`verify_esewa_callback`].
+//!
+//! [`MemoryTransactionStore`] suits tests and single-process services. With
+//! the `sqlite` feature, `SqliteTransactionStore` persists to a SQLite file.
+
+use crate::error::PaymentError;
+use crate::esewa::{
+    validate_esewa_response_with_keys, EsewaPaymentRequest, ExpectedPayment, VerifiedPayment,
+};
+use crate::lifecycle::{PaymentLifecycle, PaymentState};
+use crate::money::Npr;
+use crate::secret::SecretKeySet;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A payment as recorded when it was initiated
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransactionRecord {
+    /// Transaction ID sent to the gateway (eSewa's `transaction_uuid`)
+    pub transaction_id: String,
+    /// Merchant order the payment belongs to
+    pub order_id: String,
+    /// Gateway name, as returned by [`PaymentGateway::name`](crate::PaymentGateway::name)
+    pub gateway: String,
+    /// Merchant product or merchant code the payment was initiated with
+    pub product_code: String,
+    /// Amount the payment was initiated for
+    pub amount: Npr,
+    /// Current lifecycle state
+    pub state: PaymentState,
+    /// The gateway's own reference, once known
+    pub gateway_reference: Option<String>,
+    /// Unix time in seconds when the record was created
+    pub created_at: u64,
+    /// Unix time in seconds of the last change
+    pub updated_at: u64,
+}
+
+impl TransactionRecord {
+    /// Creates a record in [`PaymentState::Created`]
+    pub fn new(
+        transaction_id: impl Into<String>,
+        order_id: impl Into<String>,
+        gateway: impl Into<String>,
+        product_code: impl Into<String>,
+        amount: Npr,
+    ) -> Self {
+        let now = unix_now();
+        TransactionRecord {
+            transaction_id: transaction_id.into(),
+            order_id: order_id.into(),
+            gateway: gateway.into(),
+            product_code: product_code.into(),
+            amount,
+            state: PaymentState::Created,
+            gateway_reference: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    /// Creates a record for an eSewa payment request
+    pub fn for_esewa(order_id: impl Into<String>, request: &EsewaPaymentRequest) -> Self {
+        TransactionRecord::new(
+            request.transaction_uuid.clone(),
+            order_id,
+            "esewa",
+            request.product_code.clone(),
+            request.total_amount,
+        )
+    }
+
+    /// The payment the gateway is expected to settle
+    pub fn expected_payment(&self) -> ExpectedPayment {
+        ExpectedPayment::new(self.transaction_id.clone(), self.amount)
+    }
+
+    /// The record's lifecycle, for applying gateway results
+    pub fn lifecycle(&self) -> PaymentLifecycle {
+        PaymentLifecycle::resume(self.transaction_id.clone(), self.state)
+    }
+}
+
+/// Storage for [`TransactionRecord`]s
+///
+/// Implementations must apply [`transition`](Self::transition) atomically:
+/// two concurrent callers must not both move a payment out of the same state.
+#[async_trait]
+pub trait TransactionStore: Send + Sync {
+    /// Stores a new record
+    ///
+    /// Fails with [`PaymentError::DuplicateTransaction`] if the transaction ID
+    /// is already recorded.
+    async fn insert(&self, record: &TransactionRecord) -> Result<(), PaymentError>;
+
+    /// Returns the record for a transaction ID, if any
+    async fn get(&self, transaction_id: &str) -> Result<Option<TransactionRecord>, PaymentError>;
+
+    /// Moves a payment to `next` if the lifecycle allows it and returns the updated record
+    ///
+    /// Fails with [`PaymentError::InvalidTransition`] without changing the
+    /// record if the move is not allowed.
+    async fn transition(
+        &self,
+        transaction_id: &str,
+        next: PaymentState,
+    ) -> Result<TransactionRecord, PaymentError>;
+
+    /// Records the gateway's reference for a payment and returns the updated record
+    async fn set_gateway_reference(
+        &self,
+        transaction_id: &str,
+        gateway_reference: &str,
+    ) -> Result<TransactionRecord, PaymentError>;
+
+    /// Returns the record for a transaction ID or [`PaymentError::UnknownTransaction`]
+    async fn require(&self, transaction_id: &str) -> Result<TransactionRecord, PaymentError> {
+        self.get(transaction_id)
+            .await?
+            .ok_or_else(|| PaymentError::UnknownTransaction(transaction_id.to_string()))
+    }
+}
+
+/// Verifies an eSewa callback against the stored payment and records the new state
+///
+/// The transaction UUID is read from the callback only to find the record;
+/// the signature, product code, UUID and amount are then checked strictly
+/// against the record, as in
+/// [`verify_esewa_response_with_keys`](crate::verify_esewa_response_with_keys).
+/// During a key rotation, callbacks signed with an unexpired previous key of
+/// `secret_keys` are accepted. Unknown transactions fail with
+/// [`PaymentError::UnknownTransaction`].
+pub async fn verify_esewa_callback(
+    store: &dyn TransactionStore,
+    encoded_data: &str,
+    secret_keys: &SecretKeySet,
+) -> Result<(VerifiedPayment, TransactionRecord), PaymentError> {
+    let validation = validate_esewa_response_with_keys(encoded_data, secret_keys)?;
+    let record = store.require(&validation.response.transaction_uuid).await?;
+
+    let verified = validation.verify(&record.product_code, &record.expected_payment())?;
+    let record = store
+        .transition(
+            &record.transaction_id,
+            PaymentState::from(verified.status()),
+        )
+        .await?;
+
+    Ok((verified, record))
+}
+
+/// In-memory [`TransactionStore`]; records are lost when it is dropped
+#[derive(Debug, Default)]
+pub struct MemoryTransactionStore {
+    records: Mutex<HashMap<String, TransactionRecord>>,
+}
+
+impl MemoryTransactionStore {
+    /// Creates an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn update(
+        &self,
+        transaction_id: &str,
+        change: impl FnOnce(&mut TransactionRecord) -> Result<(), PaymentError>,
+    ) -> Result<TransactionRecord, PaymentError> {
+        let mut records = self
+            .records
+            .lock()
+            .expect("transaction store lock poisoned");
+        let record = records
+            .get_mut(transaction_id)
+            .ok_or_else(|| PaymentError::UnknownTransaction(transaction_id.to_string()))?;
+        change(record)?;
+        record.updated_at = unix_now();
+        Ok(record.clone())
+    }
+}
+
+#[async_trait]
+impl TransactionStore for MemoryTransactionStore {
+    async fn insert(&self, record: &TransactionRecord) -> Result<(), PaymentError> {
+        let mut records = self
+            .records
+            .lock()
+            .expect("transaction store lock poisoned");
+        if records.contains_key(&record.transaction_id) {
+            return Err(PaymentError::DuplicateTransaction(
+                record.transaction_id.clone(),
+            ));
+        }
+        records.insert(record.transaction_id.clone(), record.clone());
+        Ok(())
+    }
+
+    async fn get(&self, transaction_id: &str) -> Result<Option<TransactionRecord>, PaymentError> {
+        let records = self
+            .records
+            .lock()
+            .expect("transaction store lock poisoned");
+        Ok(records.get(transaction_id).cloned())
+    }
+
+    async fn transition(
+        &self,
+        transaction_id: &str,
+        next: PaymentState,
+    ) -> Result<TransactionRecord, PaymentError> {
+        self.update(transaction_id, |record| {
+            record.state = record.state.transition_to(next)?;
+            Ok(())
+        })
+    }
+
+    async fn set_gateway_reference(
+        &self,
+        transaction_id: &str,
+        gateway_reference: &str,
+    ) -> Result<TransactionRecord, PaymentError> {
+        self.update(transaction_id, |record| {
+            record.gateway_reference = Some(gateway_reference.to_string());
+            Ok(())
+        })
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::SqliteTransactionStore;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::{unix_now, TransactionRecord, TransactionStore};
+    use crate::error::PaymentError;
+    use crate::lifecycle::PaymentState;
+    use crate::money::Npr;
+    use crate::replay::{CallbackKey, DedupStore};
+    use async_trait::async_trait;
+    use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+
+    const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS rustpayment_transactions (
+        transaction_id TEXT PRIMARY KEY NOT NULL,
+        order_id TEXT NOT NULL,
+        gateway TEXT NOT NULL,
+        product_code TEXT NOT NULL,
+        amount_paisa INTEGER NOT NULL,
+        state TEXT NOT NULL,
+        gateway_reference TEXT,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS rustpayment_processed_callbacks (
+        transaction_uuid TEXT NOT NULL,
+        transaction_code TEXT NOT NULL,
+        processed_at INTEGER NOT NULL,
+        PRIMARY KEY (transaction_uuid, transaction_code)
+    )";
+
+    const SELECT: &str = "SELECT transaction_id, order_id, gateway, product_code, amount_paisa, \
+        state, gateway_reference, created_at, updated_at \
+        FROM rustpayment_transactions WHERE transaction_id = ?1";
+
+    /// SQLite-backed [`TransactionStore`] and [`DedupStore`]
+    ///
+    /// Creates the `rustpayment_transactions` and
+    /// `rustpayment_processed_callbacks` tables on open. Queries run on
+    /// Tokio's blocking thread pool.
+    #[derive(Debug, Clone)]
+    pub struct SqliteTransactionStore {
+        conn: Arc<Mutex<Connection>>,
+    }
+
+    impl SqliteTransactionStore {
+        /// Opens (or creates) a database file
+        pub fn open(path: impl AsRef<Path>) -> Result<Self, PaymentError> {
+            Self::from_connection(Connection::open(path).map_err(storage_error)?)
+        }
+
+        /// Opens a private in-memory database
+        pub fn open_in_memory() -> Result<Self, PaymentError> {
+            Self::from_connection(Connection::open_in_memory().map_err(storage_error)?)
+        }
+
+        /// Uses an existing connection
+        pub fn from_connection(conn: Connection) -> Result<Self, PaymentError> {
+            conn.execute_batch(SCHEMA).map_err(storage_error)?;
+            Ok(SqliteTransactionStore {
+                conn: Arc::new(Mutex::new(conn)),
+            })
+        }
+
+        async fn run<T: Send + 'static>(
+            &self,
+            task: impl FnOnce(&mut Connection) -> Result<T, PaymentError> + Send + 'static,
+        ) -> Result<T, PaymentError> {
+            let conn = Arc::clone(&self.conn);
+            tokio::task::spawn_blocking(move || {
+                let mut conn = conn.lock().expect("SQLite connection lock poisoned");
+                task(&mut conn)
+            })
+            .await
+            .map_err(|e| PaymentError::Storage(e.into()))?
+        }
+
+        async fn update(
+            &self,
+            transaction_id: &str,
+            change: impl FnOnce(&mut TransactionRecord) -> Result<(), PaymentError> + Send + 'static,
+        ) -> Result<TransactionRecord, PaymentError> {
+            let transaction_id = transaction_id.to_string();
+            self.run(move |conn| {
+                let tx = conn
+                    .transaction_with_behavior(TransactionBehavior::Immediate)
+                    .map_err(storage_error)?;
+                let mut record = select(&tx, &transaction_id)?
+                    .ok_or(PaymentError::UnknownTransaction(transaction_id))?;
+                change(&mut record)?;
+                record.updated_at = unix_now();
+                tx.execute(
+                    "UPDATE rustpayment_transactions \
+                     SET state = ?2, gateway_reference = ?3, updated_at = ?4 \
+                     WHERE transaction_id = ?1",
+                    params![
+                        record.transaction_id,
+                        record.state.to_string(),
+                        record.gateway_reference,
+                        to_sql_int(record.updated_at)?,
+                    ],
+                )
+                .map_err(storage_error)?;
+                tx.commit().map_err(storage_error)?;
+                Ok(record)
+            })
+            .await
+        }
+    }
+
+    #[async_trait]
+    impl TransactionStore for SqliteTransactionStore {
+        async fn insert(&self, record: &TransactionRecord) -> Result<(), PaymentError> {
+            let record = record.clone();
+            self.run(move |conn| {
+                let inserted = conn
+                    .execute(
+                        "INSERT OR IGNORE INTO rustpayment_transactions \
+                         (transaction_id, order_id, gateway, product_code, amount_paisa, state, \
+                          gateway_reference, created_at, updated_at) \
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                        params![
+                            record.transaction_id,
+                            record.order_id,
+                            record.gateway,
+                            record.product_code,
+                            to_sql_int(record.amount.paisa())?,
+                            record.state.to_string(),
+                            record.gateway_reference,
+                            to_sql_int(record.created_at)?,
+                            to_sql_int(record.updated_at)?,
+                        ],
+                    )
+                    .map_err(storage_error)?;
+                if inserted == 0 {
+                    return Err(PaymentError::DuplicateTransaction(record.transaction_id));
+                }
+                Ok(())
+            })
+            .await
+        }
+
+        async fn get(
+            &self,
+            transaction_id: &str,
+        ) -> Result<Option<TransactionRecord>, PaymentError> {
+            let transaction_id = transaction_id.to_string();
+            self.run(move |conn| select(conn, &transaction_id)).await
+        }
+
+        async fn transition(
+            &self,
+            transaction_id: &str,
+            next: PaymentState,
+        ) -> Result<TransactionRecord, PaymentError> {
+            self.update(transaction_id, move |record| {
+                record.state = record.state.transition_to(next)?;
+                Ok(())
+            })
+            .await
+        }
+
+        async fn set_gateway_reference(
+            &self,
+            transaction_id: &str,
+            gateway_reference: &str,
+        ) -> Result<TransactionRecord, PaymentError> {
+            let gateway_reference = gateway_reference.to_string();
+            self.update(transaction_id, move |record| {
+                record.gateway_reference = Some(gateway_reference);
+                Ok(())
+            })
+            .await
+        }
+    }
+
+    #[async_trait]
+    impl DedupStore for SqliteTransactionStore {
+        async fn mark_processed(&self, key: &CallbackKey) -> Result<bool, PaymentError> {
+            let key = key.clone();
+            self.run(move |conn| {
+                let inserted = conn
+                    .execute(
+                        "INSERT OR IGNORE INTO rustpayment_processed_callbacks \
+                         (transaction_uuid, transaction_code, processed_at) VALUES (?1, ?2, ?3)",
+                        params![
+                            key.transaction_uuid,
+                            key.transaction_code,
+                            to_sql_int(unix_now())?,
+                        ],
+                    )
+                    .map_err(storage_error)?;
+                Ok(inserted == 1)
+            })
+            .await
+        }
+
+        async fn forget(&self, key: &CallbackKey) -> Result<(), PaymentError> {
+            let key = key.clone();
+            self.run(move |conn| {
+                conn.execute(
+                    "DELETE FROM rustpayment_processed_callbacks \
+                     WHERE transaction_uuid = ?1 AND transaction_code = ?2",
+                    params![key.transaction_uuid, key.transaction_code],
+                )
+                .map_err(storage_error)?;
+                Ok(())
+            })
+            .await
+        }
+    }
+
+    fn select(
+        conn: &Connection,
+        transaction_id: &str,
+    ) -> Result<Option<TransactionRecord>, PaymentError> {
+        let row = conn
+            .query_row(SELECT, params![transaction_id], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, i64>(4)?,
+                    row.get::<_, String>(5)?,
+                    row.get::<_, Option<String>>(6)?,
+                    row.get::<_, i64>(7)?,
+                    row.get::<_, i64>(8)?,
+                ))
+            })
+            .optional()
+            .map_err(storage_error)?;
+
+        let Some((
+            transaction_id,
+            order_id,
+            gateway,
+            product_code,
+            amount,
+            state,
+            gateway_reference,
+            created_at,
+            updated_at,
+        )) = row
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(TransactionRecord {
+            transaction_id,
+            order_id,
+            gateway,
+            product_code,
+            amount: Npr::from_paisa(from_sql_int(amount)?),
+            state: state.parse()?,
+            gateway_reference,
+            created_at: from_sql_int(created_at)?,
+            updated_at: from_sql_int(updated_at)?,
+        }))
+    }
+
+    fn storage_error(error: rusqlite::Error) -> PaymentError {
+        PaymentError::Storage(error.into())
+    }
+
+    fn to_sql_int(value: u64) -> Result<i64, PaymentError> {
+        i64::try_from(value)
+            .map_err(|_| PaymentError::Storage(format!("{} does not fit in SQLite", value).into()))
+    }
+
+    fn from_sql_int(value: i64) -> Result<u64, PaymentError> {
+        u64::try_from(value).map_err(|_| {
+            PaymentError::Storage(format!("Unexpected negative value {}", value).into())
+        })
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::esewa::test_callbacks::{signed_callback, SECRET};
+    use crate::secret::MatchedKey;
+    use std::time::{Duration, SystemTime};
+
+    fn record() -> TransactionRecord {
+        TransactionRecord::new(
+            "id-123-abc",
+            "order-1",
+            "esewa",
+            "EPAYTEST",
+            Npr::from_rupees(110),
+        )
+    }
+
+    fn callback(total_amount: Npr) -> String {
+        signed_callback(|response| response.total_amount = total_amount)
+    }
+
+    async fn exercise(store: &dyn TransactionStore) {
+        store.insert(&record()).await.unwrap();
+        assert!(matches!(
+            store.insert(&record()).await,
+            Err(PaymentError::DuplicateTransaction(_))
+        ));
+
+        let stored = store.require("id-123-abc").await.unwrap();
+        assert_eq!(stored.amount, Npr::from_rupees(110));
+        assert_eq!(stored.state, PaymentState::Created);
+
+        store
+            .transition("id-123-abc", PaymentState::Initiated)
+            .await
+            .unwrap();
+        let updated = store
+            .set_gateway_reference("id-123-abc", "ref-1")
+            .await
+            .unwrap();
+        assert_eq!(updated.gateway_reference.as_deref(), Some("ref-1"));
+        assert_eq!(updated.state, PaymentState::Initiated);
+
+        store
+            .transition("id-123-abc", PaymentState::Completed)
+            .await
+            .unwrap();
+        assert!(matches!(
+            store.transition("id-123-abc", PaymentState::Pending).await,
+            Err(PaymentError::InvalidTransition { .. })
+        ));
+        assert_eq!(
+            store.require("id-123-abc").await.unwrap().state,
+            PaymentState::Completed
+        );
+
+        assert!(store.get("missing").await.unwrap().is_none());
+        assert!(matches!(
+            store.transition("missing", PaymentState::Initiated).await,
+            Err(PaymentError::UnknownTransaction(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_memory_store() {
+        exercise(&MemoryTransactionStore::new()).await;
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_sqlite_store() {
+        exercise(&SqliteTransactionStore::open_in_memory().unwrap()).await;
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_sqlite_dedup_store() {
+        use crate::replay::{CallbackKey, DedupStore};
+
+        let store = SqliteTransactionStore::open_in_memory().unwrap();
+        let key = CallbackKey {
+            transaction_uuid: "id-123-abc".to_string(),
+            transaction_code: "000D13A".to_string(),
+        };
+
+        assert!(store.mark_processed(&key).await.unwrap());
+        assert!(!store.mark_processed(&key).await.unwrap());
+        store.forget(&key).await.unwrap();
+        assert!(store.mark_processed(&key).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_verify_esewa_callback_uses_stored_amount() {
+        let store = MemoryTransactionStore::new();
+        let mut initiated = record();
+        initiated.state = PaymentState::Initiated;
+        store.insert(&initiated).await.unwrap();
+
+        // Correctly signed, but for less than the stored amount
+        let keys = SecretKeySet::new(SECRET);
+        let result = verify_esewa_callback(&store, &callback(Npr::from_rupees(1)), &keys).await;
+        assert!(matches!(result, Err(PaymentError::AmountMismatch { .. })));
+        assert_eq!(
+            store.require("id-123-abc").await.unwrap().state,
+            PaymentState::Initiated
+        );
+
+        let (verified, record) =
+            verify_esewa_callback(&store, &callback(Npr::from_rupees(110)), &keys)
+                .await
+                .unwrap();
+        assert_eq!(verified.transaction_code(), "000D13A");
+        assert_eq!(record.state, PaymentState::Completed);
+    }
+
+    #[tokio::test]
+    async fn test_verify_esewa_callback_rejects_unknown_transaction() {
+        let store = MemoryTransactionStore::new();
+        let keys = SecretKeySet::new(SECRET);
+
+        let result = verify_esewa_callback(&store, &callback(Npr::from_rupees(110)), &keys).await;
+
+        assert!(matches!(result, Err(PaymentError::UnknownTransaction(_))));
+    }
+
+    #[tokio::test]
+    async fn test_verify_esewa_callback_accepts_previous_key() {
+        let store = MemoryTransactionStore::new();
+        let mut initiated = record();
+        initiated.state = PaymentState::Initiated;
+        store.insert(&initiated).await.unwrap();
+        let keys = SecretKeySet::new("rotated-key")
+            .with_previous(SECRET, SystemTime::now() + Duration::from_secs(60));
+
+        let (verified, record) =
+            verify_esewa_callback(&store, &callback(Npr::from_rupees(110)), &keys)
+                .await
+                .unwrap();
+
+        assert_eq!(verified.matched_key(), MatchedKey::Previous(0));
+        assert_eq!(record.state, PaymentState::Completed);
+    }
+}
@@ -0,0 +1,121 @@
+//! Tests for the `rustpayment` binary.
+#![cfg(feature = "cli")]
+
+use base64::{engine::general_purpose, Engine};
+use rustpayment::{generate_signature, EsewaPaymentResponse, EsewaStatus, Npr};
+use std::io::Write;
+use std::process::{Command, Output};
+
+const TEST_SECRET_KEY: &str = "8gBm/:&EnhH.1/q";
+
+fn rustpayment(args: &[&str], secret: Option<&str>) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_rustpayment"));
+    command.args(args).env_remove("ESEWA_SECRET_KEY");
+    if let Some(secret) = secret {
+        command.env("ESEWA_SECRET_KEY", secret);
+    }
+    command.output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn callback(signed_status: EsewaStatus) -> String {
+    let mut response = EsewaPaymentResponse {
+        transaction_code: "000AWEO".to_string(),
+        status: signed_status,
+        total_amount: Npr::from_rupees(1000),
+        transaction_uuid: "250610-162413".to_string(),
+        product_code: "EPAYTEST".to_string(),
+        signed_field_names: "transaction_code,status,total_amount,transaction_uuid,product_code,signed_field_names".to_string(),
+        signature: String::new(),
+    };
+    response.signature = response.compute_signature(TEST_SECRET_KEY).unwrap();
+    response.status = EsewaStatus::Complete;
+    general_purpose::STANDARD.encode(serde_json::to_string(&response).unwrap())
+}
+
+#[test]
+fn test_sign_matches_library() {
+    let output = rustpayment(
+        &["sign", "total_amount=110", "transaction_uuid=id-123-abc", "product_code=EPAYTEST"],
+        Some(TEST_SECRET_KEY),
+    );
+
+    assert!(output.status.success());
+    let expected = generate_signature("110", "id-123-abc", "EPAYTEST", TEST_SECRET_KEY);
+    assert!(stdout(&output).contains(&format!("signature={}\n", expected)));
+}
+
+#[test]
+fn test_secret_file_is_read() {
+    let path = std::env::temp_dir().join(format!("rustpayment-cli-{}.key", std::process::id()));
+    writeln!(std::fs::File::create(&path).unwrap(), "{}", TEST_SECRET_KEY).unwrap();
+
+    let output = rustpayment(
+        &["sign", "--secret-file", path.to_str().unwrap(), "total_amount=110", "transaction_uuid=id-123-abc", "product_code=EPAYTEST"],
+        None,
+    );
+    std::fs::remove_file(&path).unwrap();
+
+    let expected = generate_signature("110", "id-123-abc", "EPAYTEST", TEST_SECRET_KEY);
+    assert!(stdout(&output).contains(&expected));
+}
+
+#[test]
+fn test_secret_is_never_taken_from_argv() {
+    let output = rustpayment(&["sign", "--secret", TEST_SECRET_KEY, "a=1"], None);
+    assert_eq!(output.status.code(), Some(2));
+
+    let output = rustpayment(&["sign", "a=1"], None);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("ESEWA_SECRET_KEY"));
+}
+
+#[test]
+fn test_decode_reports_signature_verdict() {
+    let output = rustpayment(&["decode", &callback(EsewaStatus::Complete)], Some(TEST_SECRET_KEY));
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("\"transaction_code\": \"000AWEO\""));
+    assert!(stdout(&output).contains("signature: valid"));
+
+    let output = rustpayment(&["decode", &callback(EsewaStatus::Pending)], Some(TEST_SECRET_KEY));
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).contains("signature: INVALID"));
+}
+
+#[test]
+fn test_decode_rejects_replayed_form_signature() {
+    let mut response: serde_json::Value = serde_json::from_slice(
+        &general_purpose::STANDARD.decode(callback(EsewaStatus::Complete)).unwrap(),
+    )
+    .unwrap();
+    response["transaction_code"] = "FAKE".into();
+    response["signed_field_names"] = "total_amount,transaction_uuid,product_code".into();
+    response["signature"] = generate_signature("1000", "250610-162413", "EPAYTEST", TEST_SECRET_KEY).into();
+    let forged = general_purpose::STANDARD.encode(response.to_string());
+
+    let output = rustpayment(&["decode", &forged], Some(TEST_SECRET_KEY));
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).contains("signature: INVALID"));
+}
+
+#[test]
+fn test_form_renders_signed_checkout_form() {
+    let output = rustpayment(
+        &[
+            "form", "100", "--tax-amount", "10", "--product-code", "EPAYTEST",
+            "--transaction-uuid", "id-123-abc",
+            "--success-url", "https://shop.test/success", "--failure-url", "https://shop.test/failure",
+            "--environment", "sandbox",
+        ],
+        Some(TEST_SECRET_KEY),
+    );
+
+    assert!(output.status.success());
+    let html = stdout(&output);
+    assert!(html.contains("action=\"https://rc-epay.esewa.com.np/api/epay/main/v2/form\""));
+    let signature = generate_signature("110", "id-123-abc", "EPAYTEST", TEST_SECRET_KEY);
+    assert!(html.contains(&format!("value=\"{}\"", signature)));
+}
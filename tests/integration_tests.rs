@@ -1,6 +1,6 @@
 use rustpayment::{
-    generate_signature, generate_transaction_uuid, validate_esewa_response, EsewaPaymentRequest,
-    EsewaPaymentResponse,
+    generate_signature, generate_transaction_uuid, validate_esewa_response, verify_esewa_response,
+    EsewaPaymentRequest, EsewaPaymentResponse, EsewaStatus, ExpectedPayment, Npr, PaymentError,
 };
 use base64::{engine::general_purpose, Engine};
 
@@ -53,15 +53,16 @@ fn test_transaction_uuid_uniqueness() {
 #[test]
 fn test_validate_complete_payment() {
     // Create a test payment response
-    let response = EsewaPaymentResponse {
+    let mut response = EsewaPaymentResponse {
         transaction_code: "TEST123".to_string(),
-        status: "COMPLETE".to_string(),
-        total_amount: "110.0".to_string(),
+        status: EsewaStatus::Complete,
+        total_amount: Npr::from_rupees(110),
         transaction_uuid: "id-test-uuid".to_string(),
         product_code: "EPAYTEST".to_string(),
         signed_field_names: "transaction_code,status,total_amount,transaction_uuid,product_code,signed_field_names".to_string(),
-        signature: generate_signature("110.0", "id-test-uuid", "EPAYTEST", TEST_SECRET_KEY),
+        signature: String::new(),
     };
+    response.signature = response.compute_signature(TEST_SECRET_KEY).unwrap();
     
     // Encode to base64
     let json = serde_json::to_string(&response).unwrap();
@@ -71,7 +72,7 @@ fn test_validate_complete_payment() {
     let result = validate_esewa_response(&encoded, TEST_SECRET_KEY).unwrap();
     
     assert!(result.signature_valid);
-    assert_eq!(result.response.status, "COMPLETE");
+    assert_eq!(result.response.status, EsewaStatus::Complete);
     assert_eq!(result.response.transaction_code, "TEST123");
 }
 
@@ -79,12 +80,12 @@ fn test_validate_complete_payment() {
 fn test_validate_invalid_signature() {
     let response = EsewaPaymentResponse {
         transaction_code: "TEST123".to_string(),
-        status: "COMPLETE".to_string(),
-        total_amount: "110.0".to_string(),
+        status: EsewaStatus::Complete,
+        total_amount: Npr::from_rupees(110),
         transaction_uuid: "id-test-uuid".to_string(),
         product_code: "EPAYTEST".to_string(),
         signed_field_names: "transaction_code,status,total_amount,transaction_uuid,product_code,signed_field_names".to_string(),
-        signature: "INVALID_SIGNATURE".to_string(),
+        signature: generate_signature("999", "id-other", "EPAYTEST", TEST_SECRET_KEY),
     };
     
     let json = serde_json::to_string(&response).unwrap();
@@ -95,6 +96,73 @@ fn test_validate_invalid_signature() {
     assert!(!result.signature_valid, "Invalid signature should be detected");
 }
 
+#[test]
+fn test_validate_malformed_signature() {
+    let json = r#"{"transaction_code":"TEST123","status":"COMPLETE","total_amount":"110.0","transaction_uuid":"id-test-uuid","product_code":"EPAYTEST","signed_field_names":"transaction_code,status,total_amount,transaction_uuid,product_code,signed_field_names","signature":"INVALID_SIGNATURE"}"#;
+    let encoded = general_purpose::STANDARD.encode(json.as_bytes());
+
+    let result = validate_esewa_response(&encoded, TEST_SECRET_KEY);
+    assert!(
+        matches!(result, Err(PaymentError::MalformedSignature(_))),
+        "Undecodable signature should be reported separately from a mismatch"
+    );
+}
+
+#[test]
+fn test_validate_detects_tampered_signed_field() {
+    let mut response = EsewaPaymentResponse {
+        transaction_code: "TEST123".to_string(),
+        status: EsewaStatus::Pending,
+        total_amount: Npr::from_rupees(110),
+        transaction_uuid: "id-test-uuid".to_string(),
+        product_code: "EPAYTEST".to_string(),
+        signed_field_names: "transaction_code,status,total_amount,transaction_uuid,product_code,signed_field_names".to_string(),
+        signature: String::new(),
+    };
+    response.signature = response.compute_signature(TEST_SECRET_KEY).unwrap();
+    response.transaction_code = "FORGED".to_string();
+
+    let json = serde_json::to_string(&response).unwrap();
+    let encoded = general_purpose::STANDARD.encode(json.as_bytes());
+
+    let result = validate_esewa_response(&encoded, TEST_SECRET_KEY).unwrap();
+
+    assert!(!result.signature_valid, "Tampered transaction_code should be detected");
+}
+
+#[test]
+fn test_validate_missing_signed_field() {
+    let json = r#"{"transaction_code":"TEST123","status":"COMPLETE","total_amount":"110.0","transaction_uuid":"id-test-uuid","product_code":"EPAYTEST","signed_field_names":"ref_id,status","signature":"x"}"#;
+    let encoded = general_purpose::STANDARD.encode(json.as_bytes());
+
+    let result = validate_esewa_response(&encoded, TEST_SECRET_KEY);
+    assert!(result.is_err(), "Should fail when a signed field is absent");
+}
+
+#[test]
+fn test_verify_rejects_tampered_callback() {
+    let mut response = EsewaPaymentResponse {
+        transaction_code: "TEST123".to_string(),
+        status: EsewaStatus::Pending,
+        total_amount: Npr::from_rupees(110),
+        transaction_uuid: "id-test-uuid".to_string(),
+        product_code: "EPAYTEST".to_string(),
+        signed_field_names: "transaction_code,status,total_amount,transaction_uuid,product_code,signed_field_names".to_string(),
+        signature: String::new(),
+    };
+    response.signature = response.compute_signature(TEST_SECRET_KEY).unwrap();
+    let expected = ExpectedPayment::new("id-test-uuid", Npr::from_rupees(110));
+
+    let encoded = general_purpose::STANDARD.encode(serde_json::to_string(&response).unwrap());
+    let verified = verify_esewa_response(&encoded, TEST_SECRET_KEY, "EPAYTEST", &expected).unwrap();
+    assert_eq!(verified.status(), &EsewaStatus::Pending);
+
+    response.status = EsewaStatus::Complete;
+    let encoded = general_purpose::STANDARD.encode(serde_json::to_string(&response).unwrap());
+    let result = verify_esewa_response(&encoded, TEST_SECRET_KEY, "EPAYTEST", &expected);
+    assert!(matches!(result, Err(PaymentError::SignatureMismatch)));
+}
+
 #[test]
 fn test_validate_malformed_base64() {
     let result = validate_esewa_response("not-valid-base64!!!", TEST_SECRET_KEY);
@@ -113,13 +181,13 @@ fn test_validate_malformed_json() {
 #[test]
 fn test_payment_request_serialization() {
     let request = EsewaPaymentRequest {
-        amount: "100".to_string(),
-        tax_amount: "10".to_string(),
-        total_amount: "110".to_string(),
+        amount: Npr::from_rupees(100),
+        tax_amount: Npr::from_rupees(10),
+        total_amount: Npr::from_rupees(110),
         transaction_uuid: "id-123-abc".to_string(),
         product_code: "EPAYTEST".to_string(),
-        product_service_charge: "0".to_string(),
-        product_delivery_charge: "0".to_string(),
+        product_service_charge: Npr::from_rupees(0),
+        product_delivery_charge: Npr::from_rupees(0),
         success_url: "http://example.com/success".to_string(),
         failure_url: "http://example.com/failure".to_string(),
         signed_field_names: "total_amount,transaction_uuid,product_code".to_string(),
@@ -134,6 +202,44 @@ fn test_payment_request_serialization() {
     assert_eq!(request.transaction_uuid, deserialized.transaction_uuid);
 }
 
+#[test]
+fn test_payment_request_builder() {
+    let request = EsewaPaymentRequest::builder(Npr::from_rupees(100))
+        .tax_amount(Npr::from_rupees(10))
+        .product_code("EPAYTEST")
+        .success_url("http://example.com/success")
+        .failure_url("http://example.com/failure")
+        .build()
+        .unwrap();
+
+    assert_eq!(request.total_amount, Npr::from_rupees(110));
+    assert!(request.transaction_uuid.starts_with("id-"));
+
+    // The signed total is formatted the same way it is posted
+    assert_eq!(
+        request.signature(TEST_SECRET_KEY).unwrap(),
+        generate_signature("110", &request.transaction_uuid, "EPAYTEST", TEST_SECRET_KEY)
+    );
+}
+
+#[test]
+fn test_payment_request_rejects_inconsistent_total() {
+    let result = EsewaPaymentRequest::builder(Npr::from_rupees(100))
+        .tax_amount(Npr::from_rupees(10))
+        .total_amount(Npr::from_rupees(120))
+        .success_url("http://example.com/success")
+        .failure_url("http://example.com/failure")
+        .build();
+
+    assert!(matches!(result, Err(PaymentError::InvalidAmount(_))));
+}
+
+#[test]
+fn test_amount_parsing() {
+    assert_eq!("110.0".parse::<Npr>().unwrap(), Npr::from_rupees(110));
+    assert!("abc".parse::<Npr>().is_err());
+}
+
 #[test]
 fn test_signature_with_different_amounts() {
     let sig1 = generate_signature("100", "id-test", "EPAYTEST", TEST_SECRET_KEY);
@@ -159,3 +265,315 @@ fn test_signature_with_different_product_codes() {
     
     assert_ne!(sig1, sig2, "Different product codes should produce different signatures");
 }
+
+/// Full payment flow against the in-process mock server; runs without network.
+#[cfg(feature = "mock")]
+mod offline_flow {
+    use super::TEST_SECRET_KEY;
+    use rustpayment::mock::{MockEsewaServer, MockOutcome};
+    use rustpayment::{
+        CallbackParams, CustomerAction, EsewaClient, EsewaPaymentRequest, EsewaStatus,
+        ExpectedPayment, Npr, PaymentError, PaymentGateway, PaymentIntent, PaymentStatus,
+    };
+    use std::time::Duration;
+
+    async fn setup() -> (MockEsewaServer, EsewaClient) {
+        let server = MockEsewaServer::start("EPAYTEST", TEST_SECRET_KEY).await.unwrap();
+        let client = EsewaClient::builder("EPAYTEST", TEST_SECRET_KEY)
+            .environment(server.environment())
+            .timeout(Duration::from_millis(500))
+            .build()
+            .unwrap();
+        (server, client)
+    }
+
+    fn request(uuid: &str) -> EsewaPaymentRequest {
+        EsewaPaymentRequest::builder(Npr::from_rupees(100))
+            .tax_amount(Npr::from_rupees(10))
+            .transaction_uuid(uuid)
+            .success_url("http://shop.test/success")
+            .failure_url("http://shop.test/failure")
+            .build()
+            .unwrap()
+    }
+
+    fn data_param(url: &str) -> String {
+        reqwest::Url::parse(url)
+            .unwrap()
+            .query_pairs()
+            .find(|(key, _)| key == "data")
+            .map(|(_, value)| value.into_owned())
+            .expect("redirect carries a data parameter")
+    }
+
+    #[tokio::test]
+    async fn test_complete_payment_flow() {
+        let (server, client) = setup().await;
+        let request = request("id-offline-1");
+        let expected = ExpectedPayment::from(&request);
+
+        let checkout_url = client.initiate_payment(request).await.unwrap();
+        assert!(checkout_url.contains("/mock/checkout/id-offline-1"));
+
+        let redirect = server.redirect_url("id-offline-1").unwrap();
+        assert!(redirect.starts_with("http://shop.test/success?data="));
+
+        let verified = client.verify_callback(&data_param(&redirect), &expected).unwrap();
+        assert!(verified.status().is_success());
+
+        let status = client.check_status(Npr::from_rupees(110), "id-offline-1").await.unwrap();
+        assert_eq!(status.status, EsewaStatus::Complete);
+        assert!(status.ref_id.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_pending_payment() {
+        let (server, client) = setup().await;
+        server.set_outcome("id-offline-2", MockOutcome::Pending);
+
+        client.initiate_payment(request("id-offline-2")).await.unwrap();
+
+        let data = server.callback_data("id-offline-2").unwrap();
+        let result = client.validate_response(&data).unwrap();
+        assert!(result.signature_valid);
+        assert_eq!(result.response.status, EsewaStatus::Pending);
+
+        let status = client.check_status(Npr::from_rupees(110), "id-offline-2").await.unwrap();
+        assert_eq!(status.status, EsewaStatus::Pending);
+    }
+
+    #[tokio::test]
+    async fn test_canceled_payment() {
+        let (server, client) = setup().await;
+        server.set_default_outcome(MockOutcome::Canceled);
+
+        client.initiate_payment(request("id-offline-3")).await.unwrap();
+
+        assert_eq!(server.redirect_url("id-offline-3").unwrap(), "http://shop.test/failure");
+        let status = client.check_status(Npr::from_rupees(110), "id-offline-3").await.unwrap();
+        assert_eq!(status.status, EsewaStatus::Canceled);
+    }
+
+    #[tokio::test]
+    async fn test_tampered_callback_is_rejected() {
+        let (server, client) = setup().await;
+        server.set_outcome("id-offline-4", MockOutcome::TamperedSignature);
+        let request = request("id-offline-4");
+        let expected = ExpectedPayment::from(&request);
+
+        client.initiate_payment(request).await.unwrap();
+
+        let data = server.callback_data("id-offline-4").unwrap();
+        let result = client.verify_callback(&data, &expected);
+        assert!(matches!(
+            result,
+            Err(PaymentError::SignatureMismatch)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_status_check_times_out() {
+        let (server, client) = setup().await;
+        client.initiate_payment(request("id-offline-5")).await.unwrap();
+        server.set_outcome("id-offline-5", MockOutcome::Timeout);
+
+        let result = client.check_status(Npr::from_rupees(110), "id-offline-5").await;
+        match result {
+            Err(PaymentError::Retried { attempts, last }) => {
+                assert_eq!(attempts, 3);
+                assert!(matches!(*last, PaymentError::Timeout(_)));
+            }
+            other => panic!("expected a retried timeout, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unknown_transaction_is_not_found() {
+        let (_server, client) = setup().await;
+
+        let status = client.check_status(Npr::from_rupees(110), "id-never-sent").await.unwrap();
+        assert_eq!(status.status, EsewaStatus::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_flow_through_payment_gateway_trait() {
+        let (server, client) = setup().await;
+        let gateway: Box<dyn PaymentGateway> = Box::new(client);
+        let intent = PaymentIntent::new(
+            "id-offline-7",
+            Npr::from_rupees(250),
+            "http://shop.test/success",
+            "http://shop.test/failure",
+        );
+
+        let form = match gateway.initiate(&intent).await.unwrap().action {
+            CustomerAction::Form(form) => form,
+            other => panic!("expected a form, got {:?}", other),
+        };
+        // Post the form the way the customer's browser would
+        reqwest::Client::new()
+            .post(&form.action)
+            .form(&form.fields)
+            .send()
+            .await
+            .unwrap();
+
+        let mut params = CallbackParams::new();
+        params.insert("data".to_string(), server.callback_data("id-offline-7").unwrap());
+        let outcome = gateway.verify_callback(&params, &intent).await.unwrap();
+        assert_eq!(outcome.status, PaymentStatus::Completed);
+
+        let outcome = gateway.lookup_status(&intent).await.unwrap();
+        assert_eq!(outcome.status, PaymentStatus::Completed);
+        assert_eq!(outcome.amount, Some(Npr::from_rupees(250)));
+    }
+
+    #[tokio::test]
+    async fn test_form_with_wrong_key_is_rejected() {
+        let (server, _client) = setup().await;
+        let client = EsewaClient::builder("EPAYTEST", "wrong-secret")
+            .environment(server.environment())
+            .build()
+            .unwrap();
+
+        let result = client.initiate_payment(request("id-offline-6")).await;
+        assert!(matches!(result, Err(PaymentError::Http { status: 400, .. })));
+        assert!(server.transaction("id-offline-6").is_none());
+    }
+}
+
+/// Checkout routes served by axum against the mock server.
+#[cfg(all(feature = "axum", feature = "mock"))]
+mod axum_checkout {
+    use super::TEST_SECRET_KEY;
+    use async_trait::async_trait;
+    use rustpayment::axum::{CheckoutHooks, EsewaCheckout};
+    use rustpayment::mock::{MockEsewaServer, MockOutcome};
+    use rustpayment::{CheckoutForm, EsewaClient, Npr, PaymentError, PaymentState, TransactionRecord};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Orders {
+        paid: Mutex<Vec<String>>,
+        failed: Mutex<Vec<String>>,
+    }
+
+    struct Hooks(Arc<Orders>);
+
+    #[async_trait]
+    impl CheckoutHooks for Hooks {
+        async fn order_amount(&self, _order_id: &str) -> Result<Npr, PaymentError> {
+            Ok(Npr::from_rupees(150))
+        }
+
+        async fn on_paid(&self, record: &TransactionRecord) -> Result<(), PaymentError> {
+            self.0.paid.lock().unwrap().push(record.order_id.clone());
+            Ok(())
+        }
+
+        async fn on_failed(&self, record: &TransactionRecord) -> Result<(), PaymentError> {
+            self.0.failed.lock().unwrap().push(record.order_id.clone());
+            Ok(())
+        }
+    }
+
+    async fn setup() -> (MockEsewaServer, EsewaCheckout, Arc<Orders>, String) {
+        let server = MockEsewaServer::start("EPAYTEST", TEST_SECRET_KEY).await.unwrap();
+        let client = EsewaClient::builder("EPAYTEST", TEST_SECRET_KEY)
+            .environment(server.environment())
+            .build()
+            .unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}/pay", listener.local_addr().unwrap());
+        let orders = Arc::new(Orders::default());
+        let checkout = EsewaCheckout::builder(client, base_url.clone(), Hooks(orders.clone()))
+            .build()
+            .unwrap();
+        let app = axum::Router::new().nest("/pay", checkout.clone().router());
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        (server, checkout, orders, base_url)
+    }
+
+    /// Posts the form the way the customer's browser would
+    async fn submit(form: &CheckoutForm) {
+        reqwest::Client::new()
+            .post(&form.action)
+            .form(&form.fields)
+            .send()
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_initiate_route_returns_checkout_form() {
+        let (server, _checkout, _orders, base_url) = setup().await;
+
+        let response = reqwest::Client::new()
+            .post(format!("{}/initiate", base_url))
+            .form(&[("order_id", "order-1")])
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 200);
+        let html = response.text().await.unwrap();
+        assert!(html.contains(&format!("{}/api/epay/main/v2/form", server.base_url())));
+        assert!(html.contains("value=\"150\""));
+    }
+
+    #[tokio::test]
+    async fn test_paid_order_is_credited_once() {
+        let (server, checkout, orders, base_url) = setup().await;
+        let form = checkout.initiate("order-2").await.unwrap();
+        let uuid = form.field("transaction_uuid").unwrap().to_string();
+        submit(&form).await;
+
+        let redirect = server.redirect_url(&uuid).unwrap();
+        assert!(redirect.starts_with(&format!("{}/success?data=", base_url)));
+        for _ in 0..2 {
+            let response = reqwest::get(&redirect).await.unwrap();
+            assert_eq!(response.status(), 200);
+            assert!(response.text().await.unwrap().contains("Payment received"));
+        }
+
+        assert_eq!(*orders.paid.lock().unwrap(), vec!["order-2".to_string()]);
+        let record = checkout.store().require(&uuid).await.unwrap();
+        assert_eq!(record.state, PaymentState::Completed);
+    }
+
+    #[tokio::test]
+    async fn test_tampered_callback_is_rejected() {
+        let (server, checkout, orders, _base_url) = setup().await;
+        let form = checkout.initiate("order-3").await.unwrap();
+        let uuid = form.field("transaction_uuid").unwrap().to_string();
+        server.set_outcome(uuid.clone(), MockOutcome::TamperedSignature);
+        submit(&form).await;
+
+        let response = reqwest::get(server.redirect_url(&uuid).unwrap()).await.unwrap();
+
+        assert_eq!(response.status(), 400);
+        assert!(orders.paid.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_canceled_payment_is_checked_and_recorded() {
+        let (server, checkout, orders, base_url) = setup().await;
+        server.set_default_outcome(MockOutcome::Canceled);
+        let form = checkout.initiate("order-4").await.unwrap();
+        let uuid = form.field("transaction_uuid").unwrap().to_string();
+        submit(&form).await;
+
+        let redirect = server.redirect_url(&uuid).unwrap();
+        assert_eq!(redirect, format!("{}/failure?transaction_uuid={}", base_url, uuid));
+        let response = reqwest::get(&redirect).await.unwrap();
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(*orders.failed.lock().unwrap(), vec!["order-4".to_string()]);
+        let record = checkout.store().require(&uuid).await.unwrap();
+        assert_eq!(record.state, PaymentState::Canceled);
+    }
+}